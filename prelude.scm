@@ -9,8 +9,53 @@
       accum
       (foldl func (func accum (car lst)) (cdr lst))))
 
-(define (append list1 list2)
-   (foldr cons list2 list1))
-
 (define (reverse items)
-  (foldr (lambda (x r) (append r (list x))) '() items))
+  (foldl (lambda (r x) (cons x r)) '() items))
+
+(define (list-tail lst k)
+  (if (= k 0)
+      lst
+      (list-tail (cdr lst) (- k 1))))
+
+(define (list-ref lst k)
+  (car (list-tail lst k)))
+
+(define (last-pair lst)
+  (if (pair? (cdr lst))
+      (last-pair (cdr lst))
+      lst))
+
+; (member x lst) and (member x lst compare)
+(define (member x lst . compare)
+  (define (loop same? lst)
+    (cond ((null? lst) #f)
+          ((same? x (car lst)) lst)
+          (else (loop same? (cdr lst)))))
+  (loop (if (null? compare) equal? (car compare)) lst))
+
+(define (memq x lst) (member x lst eq?))
+
+(define (memv x lst) (member x lst eqv?))
+
+; (assoc key alist) and (assoc key alist compare)
+(define (assoc key alist . compare)
+  (define (loop same? alist)
+    (cond ((null? alist) #f)
+          ((same? key (car (car alist))) (car alist))
+          (else (loop same? (cdr alist)))))
+  (loop (if (null? compare) equal? (car compare)) alist))
+
+(define (assq key alist) (assoc key alist eq?))
+
+(define (assv key alist) (assoc key alist eqv?))
+
+(define (for-each func lst . lists)
+  (define (any-null? lists)
+    (cond ((null? lists) #f)
+          ((null? (car lists)) #t)
+          (else (any-null? (cdr lists)))))
+  (define (loop lists)
+    (cond ((not (any-null? lists))
+           (apply func (map car lists))
+           (loop (map cdr lists)))))
+  (loop (cons lst lists)))
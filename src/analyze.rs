@@ -0,0 +1,595 @@
+//! Syntactic analysis of expressions separated from their execution.
+//!
+//! An expression is analyzed once into a tree of nodes where special forms are already recognized,
+//! so functions called many times don't inspect the syntax of their bodies again.
+//! Local variables are resolved to slots of frames during the analysis, see the `lexical` module,
+//! other variables are looked up by name in the global scope. Execution gives the same results as `eval`.
+//! Syntax errors are reported by the analysis before anything is executed, even in branches which
+//! are never taken, while `eval` reports them only when the malformed form is evaluated.
+
+use crate::context;
+use crate::errors::EvalErr;
+use crate::eval::{is_cxr, read_loaded_file, SPECIAL_FORMS};
+use crate::functions::{apply_args, Body, Function};
+use crate::gc;
+use crate::lexical::{scan_defines, Frame, Params, Resolver};
+use crate::object::{List, Object};
+use crate::profiler::Activation;
+use crate::scope::Scope;
+use crate::service::{expect_1_arg, expect_args, list_to_vec, undef};
+use crate::source::{self, Location};
+use crate::symbol::Symbol;
+
+use std::rc::Rc;
+
+/// An analyzed expression
+pub enum Node {
+    Const(Rc<Object>),
+    /// A variable in the slot of the frame at the depth, the name is kept for errors
+    Local(u32, u32, Symbol),
+    Global(Symbol),
+    If(Box<[Node; 3]>),
+    Let(Box<Let>),
+    Sequence(Vec<Node>),
+    /// Stores the value to the slot of the innermost frame
+    SetLocal(u32, Box<Node>),
+    DefineGlobal(Symbol, Box<Node>),
+    Lambda(Rc<Lambda>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Cond(Vec<Clause>),
+    Apply(Vec<Node>),
+    Load(Box<Node>),
+    /// A call of the function with the arguments, the last is the called expression for errors
+    Call(Box<Node>, Vec<Node>, Rc<Object>),
+}
+
+/// A `let` form with the frame of the given size.
+/// Initial values are stored to the slots, `let*` and `letrec` compute them in the new frame.
+pub struct Let {
+    inner: bool,
+    size: usize,
+    inits: Vec<(u32, Node)>,
+    body: Vec<Node>,
+}
+
+pub struct Lambda {
+    name: String,
+    args: Rc<Object>,
+    location: Option<Location>,
+    params: Params,
+    /// The number of slots in the frame of a call
+    size: usize,
+    body: Vec<Node>,
+}
+
+/// A clause of `cond`. `else` has no test, a clause with a test only has no body.
+pub struct Clause {
+    test: Option<Node>,
+    body: Option<Vec<Node>>,
+}
+
+/// An analyzed lambda with the frame it was created in
+pub struct Closure {
+    lambda: Rc<Lambda>,
+    env: Option<Rc<Frame>>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        &self.lambda.name
+    }
+
+    /// Formal arguments as they are written in the source
+    pub fn args(&self) -> &Rc<Object> {
+        &self.lambda.args
+    }
+
+    /// The frame of the local variables the closure was created in
+    pub(crate) fn env(&self) -> Option<&Rc<Frame>> {
+        self.env.as_ref()
+    }
+
+    /// Where the lambda is defined if it is known
+    pub fn location(&self) -> Option<&Location> {
+        self.lambda.location.as_ref()
+    }
+
+    /// Makes the environment of a call
+    fn bind(&self, args: List, globals: &Rc<Scope>) -> Result<Env, EvalErr> {
+        let lambda = &self.lambda;
+        let slots = lambda.params.bind(&lambda.name, args, lambda.size)?;
+        let frame = Frame::new(slots, self.env.clone());
+        Ok(Env { frame: Some(frame), globals: globals.clone() })
+    }
+}
+
+/// Variables visible during execution: the innermost frame of locals and the global scope
+#[derive(Clone)]
+struct Env {
+    frame: Option<Rc<Frame>>,
+    globals: Rc<Scope>,
+}
+
+/// The result of an expression in a tail position.
+/// The call is left to the caller so that tail calls don't grow the stack.
+enum Step {
+    Value(Rc<Object>),
+    Call(Rc<Object>, List),
+}
+
+/// An entered function: an analyzed one with the environment of its body or the value of any other
+enum Entry<'a> {
+    Body(&'a Closure, Env),
+    Value(Rc<Object>),
+}
+
+/// Analyzes the expression and executes it. Top-level definitions are bound in the given scope.
+pub fn eval(obj: &Rc<Object>, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr> {
+    let env = Env { frame: None, globals: scope.clone() };
+    exec(&analyze(obj)?, &env)
+}
+
+/// Analyzes a top-level expression
+pub fn analyze(obj: &Rc<Object>) -> Result<Node, EvalErr> {
+    Analyzer::default().analyze(obj)
+}
+
+/// Calls an analyzed function whose free variables are looked up in the global scope
+pub fn call(closure: &Closure, globals: &Rc<Scope>, args: List) -> Result<Rc<Object>, EvalErr> {
+    context::tick()?;
+    let env = closure.bind(args, globals)?;
+    let mut activation = Activation::default();
+    activation.enter(closure.name(), closure.location());
+    exec_body(&closure.lambda.body, &env, activation)
+}
+
+#[derive(Default)]
+struct Analyzer {
+    frames: Resolver,
+}
+
+impl Analyzer {
+    fn analyze(&mut self, obj: &Rc<Object>) -> Result<Node, EvalErr> {
+        match obj.as_ref() {
+            Object::Symbol(s) if is_cxr(s.as_str()) => Ok(Node::Const(Rc::new(Object::from(
+                Function::Dynamic(s.to_string()),
+            )))),
+            Object::Symbol(s) => Ok(match self.frames.resolve(*s) {
+                Some((depth, index)) => Node::Local(depth, index, *s),
+                None => Node::Global(*s),
+            }),
+            Object::Pair(head, tail) => {
+                let args = list_to_vec(tail)?;
+                match head.as_ref() {
+                    Object::Symbol(s) if SPECIAL_FORMS.contains(&s.as_str()) => {
+                        self.analyze_form(s.as_str(), tail, args)
+                    }
+                    _ => Ok(Node::Call(
+                        Box::new(self.analyze(head)?),
+                        self.analyze_all(&args)?,
+                        head.clone(),
+                    )),
+                }
+            }
+            _ => Ok(Node::Const(obj.clone())),
+        }
+    }
+
+    fn analyze_all(&mut self, exprs: &[Rc<Object>]) -> Result<Vec<Node>, EvalErr> {
+        exprs.iter().map(|expr| self.analyze(expr)).collect()
+    }
+
+    /// Analyzes the special form, `tail` is the rest of the form and `args` are its elements
+    fn analyze_form(&mut self, form: &str, tail: &Rc<Object>, args: List) -> Result<Node, EvalErr> {
+        Ok(match form {
+            "quote" => Node::Const(expect_1_arg(args, "quote")?),
+            "if" => {
+                let args = expect_args(args, "if", 3)?;
+                let branches = [
+                    self.analyze(&args[0])?,
+                    self.analyze(&args[1])?,
+                    self.analyze(&args[2])?,
+                ];
+                Node::If(Box::new(branches))
+            }
+            "let" => self.analyze_let(args, false, false)?,
+            "let*" => self.analyze_let(args, true, false)?,
+            "letrec" => self.analyze_let(args, false, true)?,
+            "begin" => Node::Sequence(self.analyze_all(&args)?),
+            "define" => self.analyze_define(args, source::location(tail))?,
+            "lambda" => {
+                if args.len() < 2 {
+                    return Err(EvalErr::TooFewArguments("lambda".to_string()));
+                }
+                if args.len() > 2 {
+                    return Err(EvalErr::TooManyArguments("lambda".to_string()));
+                }
+                self.analyze_lambda("#<lambda>", &args[0], &args[1..], source::location(tail))?
+            }
+            "and" => Node::And(self.analyze_all(&args)?),
+            "or" => Node::Or(self.analyze_all(&args)?),
+            "cond" => self.analyze_cond(args)?,
+            "apply" => Node::Apply(self.analyze_all(&args)?),
+            "load" => Node::Load(Box::new(self.analyze(&expect_1_arg(args, "load")?)?)),
+            _ => unreachable!("unknown special form {}", form),
+        })
+    }
+
+    /// Each `let` form executes its body in a new frame.
+    /// Values of `let` are computed in the enclosing frame, `let*` and `letrec` compute them in the new one.
+    fn analyze_let(&mut self, args: List, star: bool, rec: bool) -> Result<Node, EvalErr> {
+        if args.len() < 2 {
+            return Err(EvalErr::NeedAtLeastArgs("let".to_string(), 2, args.len()));
+        }
+        let mut bindings = vec![];
+        for binding in list_to_vec(&args[0])? {
+            let init_expr = list_to_vec(&binding)?;
+            if init_expr.len() < 2 {
+                return Err(EvalErr::LetNeedListForBinding(binding.to_string()));
+            }
+            match init_expr[0].as_ref() {
+                Object::Symbol(s) => bindings.push((*s, init_expr[1].clone())),
+                var => return Err(EvalErr::LetNeedSymbolForBinding(var.to_string())),
+            }
+        }
+        let mut inits = vec![];
+        let mut names = if star {
+            self.frames.push(vec![]);
+            for (var, init) in &bindings {
+                let init = self.analyze(init)?;
+                inits.push((self.frames.bind(*var), init));
+            }
+            self.frames.pop()
+        } else {
+            let vars = || bindings.iter().map(|(var, _)| *var).collect();
+            if rec {
+                self.frames.push(vars());
+            }
+            for (index, (_, init)) in bindings.iter().enumerate() {
+                inits.push((index as u32, self.analyze(init)?));
+            }
+            if rec {
+                self.frames.pop()
+            } else {
+                vars()
+            }
+        };
+        let body = &args[1..];
+        for expr in body {
+            scan_defines(expr, &mut names);
+        }
+        self.frames.push(names);
+        let body = self.analyze_all(body)?;
+        let size = self.frames.pop().len();
+        Ok(Node::Let(Box::new(Let {
+            inner: star || rec,
+            size,
+            inits,
+            body,
+        })))
+    }
+
+    fn analyze_define(&mut self, args: List, location: Option<Location>) -> Result<Node, EvalErr> {
+        let head = args
+            .first()
+            .ok_or_else(|| EvalErr::TooFewArguments("define".to_string()))?;
+        let (name, value) = match head.as_ref() {
+            Object::Symbol(s) => {
+                if args.len() < 2 {
+                    return Err(EvalErr::TooFewArguments("define".to_string()));
+                } else if args.len() > 2 {
+                    return Err(EvalErr::TooManyArguments("define".to_string()));
+                }
+                (s, self.analyze(&args[1])?)
+            }
+            Object::Pair(name, fun_args) => {
+                if let Object::Symbol(s) = name.as_ref() {
+                    let lambda = self.analyze_lambda(s.as_str(), fun_args, &args[1..], location)?;
+                    (s, lambda)
+                } else {
+                    return Err(EvalErr::ExpectedSymbolForFunctionName(name.to_string()));
+                }
+            }
+            x => return Err(EvalErr::WrongDefineArgument(x.to_string())),
+        };
+        Ok(match self.frames.define(*name) {
+            Some(index) => Node::SetLocal(index, Box::new(value)),
+            None => Node::DefineGlobal(*name, Box::new(value)),
+        })
+    }
+
+    fn analyze_lambda(
+        &mut self, name: &str, args: &Rc<Object>, body: &[Rc<Object>], location: Option<Location>,
+    ) -> Result<Node, EvalErr> {
+        Function::check_args(args)?;
+        if body.is_empty() {
+            return Err(EvalErr::EmptyFunctionBody());
+        }
+        let params = Params::new(args);
+        let mut names = params.names.clone();
+        for expr in body {
+            scan_defines(expr, &mut names);
+        }
+        self.frames.push(names);
+        let body = self.analyze_all(body)?;
+        let size = self.frames.pop().len();
+        Ok(Node::Lambda(Rc::new(Lambda {
+            name: name.to_string(),
+            args: args.clone(),
+            location,
+            params,
+            size,
+            body,
+        })))
+    }
+
+    fn analyze_cond(&mut self, clauses: List) -> Result<Node, EvalErr> {
+        if clauses.is_empty() {
+            return Err(EvalErr::CondNeedsClause());
+        }
+        let mut result = vec![];
+        for clause in clauses {
+            let vec = list_to_vec(&clause)?;
+            result.push(match vec.as_slice() {
+                [] => return Err(EvalErr::CondEmptyClause()),
+                [test, body @ ..] if test.is_symbol("else") => {
+                    Clause { test: None, body: Some(self.analyze_all(body)?) }
+                }
+                [test] => Clause { test: Some(self.analyze(test)?), body: None },
+                [test, body @ ..] => Clause {
+                    test: Some(self.analyze(test)?),
+                    body: Some(self.analyze_all(body)?),
+                },
+            });
+        }
+        Ok(Node::Cond(result))
+    }
+}
+
+/// Executes the analyzed expression
+fn exec(node: &Node, env: &Env) -> Result<Rc<Object>, EvalErr> {
+    // leaves can't go deeper
+    match node {
+        Node::Const(value) => Ok(value.clone()),
+        Node::Local(depth, index, name) => get_local(*depth, *index, name, env),
+        Node::Global(name) => get_global(name, env),
+        _ => exec_body(std::slice::from_ref(node), env, Activation::default()),
+    }
+}
+
+fn get_local(depth: u32, index: u32, name: &Symbol, env: &Env) -> Result<Rc<Object>, EvalErr> {
+    let frame = env.frame.as_ref().unwrap();
+    (frame.get(depth, index)).ok_or_else(|| EvalErr::UnboundVariable(name.to_string()))
+}
+
+fn get_global(name: &Symbol, env: &Env) -> Result<Rc<Object>, EvalErr> {
+    (env.globals.get(name)).ok_or_else(|| EvalErr::UnboundVariable(name.to_string()))
+}
+
+/// Executes the sequence of expressions and the calls in its tail position.
+/// The activation is the procedure the sequence belongs to, called procedures replace it.
+fn exec_body(body: &[Node], env: &Env, mut activation: Activation) -> Result<Rc<Object>, EvalErr> {
+    let _depth = context::enter_eval()?;
+    let mut step = exec_tail(body, env)?;
+    while let Step::Call(func, args) = step {
+        step = match enter(&func, args, &mut activation)? {
+            Entry::Body(closure, env) => {
+                activation.enter(closure.name(), closure.location());
+                exec_tail(&closure.lambda.body, &env)?
+            }
+            Entry::Value(value) => Step::Value(value),
+        };
+    }
+    match step {
+        Step::Value(value) => {
+            activation.returned(&value)?;
+            Ok(value)
+        }
+        Step::Call(..) => unreachable!(),
+    }
+}
+
+/// Binds the arguments of an analyzed function and returns its body with the environment,
+/// the call is printed by the activation if the function is traced.
+/// Other functions are called and their value is returned instead.
+fn enter<'a>(
+    func: &'a Rc<Object>, args: List, activation: &mut Activation,
+) -> Result<Entry<'a>, EvalErr> {
+    context::tick()?;
+    match func.as_ref() {
+        Object::Function(f @ Function::Object { body: Body::Analyzed(closure), scope, .. }) => {
+            activation.trace(f, &args)?;
+            let env = closure.bind(args, scope)?;
+            Ok(Entry::Body(closure, env))
+        }
+        Object::Function(f) => Ok(Entry::Value(f.invoke(args)?)),
+        x => Err(EvalErr::IllegalObjectAsAFunction(x.to_string())),
+    }
+}
+
+/// Executes all expressions of the sequence but the last one which is returned
+fn exec_init<'a>(body: &'a [Node], env: &Env) -> Result<Option<&'a Node>, EvalErr> {
+    match body.split_last() {
+        Some((last, init)) => {
+            for node in init {
+                exec(node, env)?;
+            }
+            Ok(Some(last))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Executes the sequence of expressions, the last one is in a tail position.
+/// Nested tail positions are handled in the loop to save the Rust stack,
+/// forms are executed by separate functions to keep the frame of the loop small.
+fn exec_tail(body: &[Node], env: &Env) -> Result<Step, EvalErr> {
+    let mut body = body;
+    let mut env = env.clone();
+    loop {
+        let node = match exec_init(body, &env)? {
+            Some(Node::Call(head, items, expr)) => return exec_call(head, items, expr, &env),
+            Some(Node::Apply(items)) => return exec_apply(items, &env),
+            Some(node) => node,
+            None => return Ok(Step::Value(undef())),
+        };
+        if let Node::Let(form) = node {
+            env = let_env(form, &env)?;
+            body = &form.body;
+            continue;
+        }
+        match exec_form(node, &env)? {
+            Ok(next) => body = next,
+            Err(value) => return Ok(Step::Value(value)),
+        }
+    }
+}
+
+/// Returns the expressions in the tail position of the form or its value if there are none
+fn exec_form<'a>(node: &'a Node, env: &Env) -> Result<Result<&'a [Node], Rc<Object>>, EvalErr> {
+    match node {
+        Node::If(branches) => exec_if(branches, env).map(Ok),
+        Node::Sequence(body) => Ok(Ok(body)),
+        Node::And(items) => exec_and(items, env),
+        Node::Or(items) => exec_or(items, env),
+        Node::Cond(clauses) => select_clause(clauses, env),
+        _ => exec_simple(node, env).map(Err),
+    }
+}
+
+/// Returns the branch to execute
+fn exec_if<'a>(branches: &'a [Node; 3], env: &Env) -> Result<&'a [Node], EvalErr> {
+    let [test, then, other] = branches;
+    let branch = if exec(test, env)?.is_true() {
+        then
+    } else {
+        other
+    };
+    Ok(std::slice::from_ref(branch))
+}
+
+/// Returns the last expression to execute or the value if it is known earlier
+fn exec_and<'a>(items: &'a [Node], env: &Env) -> Result<Result<&'a [Node], Rc<Object>>, EvalErr> {
+    match items.split_last() {
+        None => Ok(Err(Rc::new(Object::from(true)))),
+        Some((last, init)) => {
+            for item in init {
+                if !exec(item, env)?.is_true() {
+                    return Ok(Err(Rc::new(Object::from(false))));
+                }
+            }
+            Ok(Ok(std::slice::from_ref(last)))
+        }
+    }
+}
+
+/// Returns the last expression to execute or the value if it is known earlier
+fn exec_or<'a>(items: &'a [Node], env: &Env) -> Result<Result<&'a [Node], Rc<Object>>, EvalErr> {
+    match items.split_last() {
+        None => Ok(Err(Rc::new(Object::from(false)))),
+        Some((last, init)) => {
+            for item in init {
+                let value = exec(item, env)?;
+                if value.is_true() {
+                    return Ok(Err(value));
+                }
+            }
+            Ok(Ok(std::slice::from_ref(last)))
+        }
+    }
+}
+
+fn exec_apply(items: &[Node], env: &Env) -> Result<Step, EvalErr> {
+    let values = items.iter().map(|item| exec(item, env));
+    let (func, args) = apply_args(values.collect::<Result<_, _>>()?)?;
+    Ok(Step::Call(func, args))
+}
+
+fn exec_call(head: &Node, items: &[Node], expr: &Object, env: &Env) -> Result<Step, EvalErr> {
+    let func = exec(head, env)?;
+    if !matches!(func.as_ref(), Object::Function(_)) {
+        return Err(EvalErr::IllegalObjectAsAFunction(expr.to_string()));
+    }
+    let mut args = Vec::with_capacity(items.len());
+    for item in items {
+        args.push(exec(item, env)?);
+    }
+    Ok(Step::Call(func, args))
+}
+
+/// Executes expressions without subexpressions in tail positions
+fn exec_simple(node: &Node, env: &Env) -> Result<Rc<Object>, EvalErr> {
+    Ok(match node {
+        Node::Const(value) => value.clone(),
+        Node::Local(depth, index, name) => get_local(*depth, *index, name, env)?,
+        Node::Global(name) => get_global(name, env)?,
+        Node::SetLocal(index, value) => {
+            let value = exec(value, env)?;
+            env.frame.as_ref().unwrap().set(0, *index, value);
+            Rc::new(Object::Nil)
+        }
+        Node::DefineGlobal(name, value) => {
+            env.globals.bind(name, exec(value, env)?);
+            Rc::new(Object::Nil)
+        }
+        Node::Lambda(lambda) => {
+            env.frame.iter().for_each(gc::track_frame);
+            gc::track_scope(&env.globals);
+            let closure = Closure { lambda: lambda.clone(), env: env.frame.clone() };
+            Rc::new(Object::from(Function::Object {
+                name: lambda.name.clone(),
+                args: lambda.args.clone(),
+                body: Body::Analyzed(closure),
+                scope: env.globals.clone(),
+                location: lambda.location.clone(),
+            }))
+        }
+        Node::Load(name) => {
+            for expr in read_loaded_file(&exec(name, env)?)? {
+                eval(&Rc::new(expr), &env.globals)?;
+            }
+            undef()
+        }
+        _ => unreachable!(),
+    })
+}
+
+/// Finds the body of the first clause of `cond` whose test is true.
+/// The value is returned instead if the clause has no body or no clause is selected.
+fn select_clause<'a>(
+    clauses: &'a [Clause], env: &Env,
+) -> Result<Result<&'a [Node], Rc<Object>>, EvalErr> {
+    for clause in clauses {
+        let value = match &clause.test {
+            Some(test) => exec(test, env)?,
+            None => Rc::new(Object::from(true)),
+        };
+        if value.is_true() {
+            return Ok(match &clause.body {
+                Some(body) => Ok(body),
+                None => Err(value),
+            });
+        }
+    }
+    Ok(Err(undef()))
+}
+
+/// Creates the frame of the `let` form and returns the environment of its body
+fn let_env(form: &Let, env: &Env) -> Result<Env, EvalErr> {
+    let globals = env.globals.clone();
+    if form.inner {
+        let frame = Frame::new(vec![None; form.size], env.frame.clone());
+        let inner = Env { frame: Some(frame.clone()), globals };
+        for (index, init) in &form.inits {
+            frame.set(0, *index, exec(init, &inner)?);
+        }
+        return Ok(inner);
+    }
+    let mut slots = vec![None; form.size];
+    for (index, init) in &form.inits {
+        slots[*index as usize] = Some(exec(init, env)?);
+    }
+    let frame = Frame::new(slots, env.frame.clone());
+    Ok(Env { frame: Some(frame), globals })
+}
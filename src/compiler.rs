@@ -0,0 +1,492 @@
+//! Compiler of expressions into the bytecode executed by the `vm` module.
+//!
+//! Special forms are translated into jumps and frame operations, so the VM never looks at their syntax.
+//! Variables bound by lambdas, `let` forms and internal definitions are resolved to slots of frames
+//! at compile time. Other variables are looked up by name in the global scope.
+//! Syntax errors are reported when the expression is compiled with the same errors as `eval` gives,
+//! also for forms in branches which are never taken and which `eval` doesn't look at.
+
+use crate::errors::EvalErr;
+use crate::eval::{is_cxr, SPECIAL_FORMS};
+use crate::functions::Function;
+use crate::lexical::{scan_defines, Params, Resolver};
+use crate::object::Object;
+use crate::service::{expect_1_arg, expect_args, list_to_vec, undef};
+use crate::source::{self, Location};
+use crate::symbol::Symbol;
+
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Pushes the constant with the index
+    Const(u32),
+    /// Pushes the variable from the slot of the frame at the depth, the last operand is the index of the name
+    Local(u32, u32, u32),
+    /// Pops a value into the slot of the frame at the depth
+    SetLocal(u32, u32),
+    /// Pushes the global variable with the name at the index
+    Global(u32),
+    /// Pops a value and binds it in the global scope
+    DefineGlobal(u32),
+    /// Pushes a closure of the nested lambda with the index
+    Closure(u32),
+    Pop,
+    Jump(u32),
+    /// Pops a value and jumps if it is false
+    JumpIfFalse(u32),
+    /// Jumps keeping the value on the stack if it is true, otherwise pops it
+    JumpIfTrue(u32),
+    /// Calls the function below the arguments, the last operand is the constant with the called expression
+    Call(u32, u32),
+    TailCall(u32, u32),
+    /// Calls the function like `apply` with the number of arguments
+    Apply(u32),
+    TailApply(u32),
+    /// Creates a frame with the number of slots, the first of them are taken from the stack
+    EnterFrame(u32, u32),
+    LeaveFrame,
+    /// Pops a file name and evaluates the file in the global scope
+    Load,
+    Return,
+}
+
+/// The compiled body of a lambda or of a top-level expression.
+pub struct Lambda {
+    pub name: String,
+    /// Formal arguments as they are written in the source
+    pub args: Rc<Object>,
+    /// Where the lambda is defined if it is known
+    pub location: Option<Location>,
+    pub params: Params,
+    /// The number of slots in the frame of the call, zero for top-level code which has no frame
+    pub frame_size: usize,
+    pub code: Vec<Op>,
+    pub constants: Vec<Rc<Object>>,
+    pub names: Vec<Symbol>,
+    pub lambdas: Vec<Rc<Lambda>>,
+}
+
+impl Lambda {
+    fn new(name: &str, args: Rc<Object>) -> Self {
+        Lambda {
+            name: name.to_string(),
+            params: Params::new(&args),
+            args,
+            location: None,
+            frame_size: 0,
+            code: vec![],
+            constants: vec![],
+            names: vec![],
+            lambdas: vec![],
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// Sets the target of the jump at the address to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len() as u32;
+        self.code[at] = match self.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            op => op,
+        };
+    }
+
+    fn constant(&mut self, obj: Rc<Object>) -> u32 {
+        self.constants.push(obj);
+        (self.constants.len() - 1) as u32
+    }
+
+    fn name(&mut self, name: Symbol) -> u32 {
+        if let Some(i) = self.names.iter().position(|n| *n == name) {
+            return i as u32;
+        }
+        self.names.push(name);
+        (self.names.len() - 1) as u32
+    }
+}
+
+/// Kinds of `let` forms
+#[derive(Clone, Copy, PartialEq)]
+enum LetKind {
+    Let,
+    LetStar,
+    LetRec,
+}
+
+struct Compiler {
+    frames: Resolver,
+}
+
+/// Compiles a top-level expression. Its definitions are bound in the global scope.
+pub fn compile(expr: &Rc<Object>) -> Result<Rc<Lambda>, EvalErr> {
+    let mut lambda = Lambda::new("#<toplevel>", Rc::new(Object::Nil));
+    let mut compiler = Compiler { frames: Resolver::default() };
+    compiler.compile(expr, true, &mut lambda)?;
+    lambda.emit(Op::Return);
+    Ok(Rc::new(lambda))
+}
+
+impl Compiler {
+    fn compile(&mut self, expr: &Rc<Object>, tail: bool, out: &mut Lambda) -> Result<(), EvalErr> {
+        match expr.as_ref() {
+            Object::Symbol(s) if is_cxr(s.as_str()) => {
+                let func = Rc::new(Object::from(Function::Dynamic(s.to_string())));
+                let i = out.constant(func);
+                out.emit(Op::Const(i));
+            }
+            Object::Symbol(s) => {
+                let op = match self.frames.resolve(*s) {
+                    Some((depth, index)) => Op::Local(depth, index, out.name(*s)),
+                    None => Op::Global(out.name(*s)),
+                };
+                out.emit(op);
+            }
+            Object::Pair(head, rest) => {
+                let args = list_to_vec(rest)?;
+                match head.as_ref() {
+                    Object::Symbol(s) if SPECIAL_FORMS.contains(&s.as_str()) => {
+                        self.compile_special(s.as_str(), rest, args, tail, out)?;
+                    }
+                    _ => {
+                        self.compile(head, false, out)?;
+                        for arg in &args {
+                            self.compile(arg, false, out)?;
+                        }
+                        let head = out.constant(head.clone());
+                        let argc = args.len() as u32;
+                        out.emit(if tail {
+                            Op::TailCall(argc, head)
+                        } else {
+                            Op::Call(argc, head)
+                        });
+                    }
+                }
+            }
+            _ => {
+                let i = out.constant(expr.clone());
+                out.emit(Op::Const(i));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the special form, `rest` is the rest of the form and `args` are its elements
+    fn compile_special(
+        &mut self, form: &str, rest: &Rc<Object>, args: Vec<Rc<Object>>, tail: bool,
+        out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        match form {
+            "quote" => {
+                let i = out.constant(expect_1_arg(args, "quote")?);
+                out.emit(Op::Const(i));
+            }
+            "if" => {
+                let args = expect_args(args, "if", 3)?;
+                self.compile(&args[0], false, out)?;
+                let to_else = out.emit(Op::JumpIfFalse(0));
+                self.compile(&args[1], tail, out)?;
+                let to_end = out.emit(Op::Jump(0));
+                out.patch(to_else);
+                self.compile(&args[2], tail, out)?;
+                out.patch(to_end);
+            }
+            "let" => self.compile_let(args, LetKind::Let, tail, out)?,
+            "let*" => self.compile_let(args, LetKind::LetStar, tail, out)?,
+            "letrec" => self.compile_let(args, LetKind::LetRec, tail, out)?,
+            "begin" => self.compile_body(&args, tail, out)?,
+            "define" => {
+                self.compile_define(args, source::location(rest), out)?;
+                let i = out.constant(Rc::new(Object::Nil));
+                out.emit(Op::Const(i));
+            }
+            "lambda" => {
+                if args.len() < 2 {
+                    return Err(EvalErr::TooFewArguments("lambda".to_string()));
+                }
+                if args.len() > 2 {
+                    return Err(EvalErr::TooManyArguments("lambda".to_string()));
+                }
+                let location = source::location(rest);
+                self.compile_lambda("#<lambda>", &args[0], &args[1..], location, out)?;
+            }
+            "and" => self.compile_and(&args, tail, out)?,
+            "or" => self.compile_or(&args, tail, out)?,
+            "cond" => self.compile_cond(args, tail, out)?,
+            "apply" => {
+                for arg in &args {
+                    self.compile(arg, false, out)?;
+                }
+                let argc = args.len() as u32;
+                out.emit(if tail {
+                    Op::TailApply(argc)
+                } else {
+                    Op::Apply(argc)
+                });
+            }
+            "load" => {
+                self.compile(&expect_1_arg(args, "load")?, false, out)?;
+                out.emit(Op::Load);
+            }
+            _ => unreachable!("unknown special form {}", form),
+        }
+        Ok(())
+    }
+
+    /// Compiles the sequence of expressions, the value of the last one is left on the stack
+    fn compile_body(
+        &mut self, body: &[Rc<Object>], tail: bool, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        if let [init @ .., last] = body {
+            for expr in init {
+                self.compile(expr, false, out)?;
+                out.emit(Op::Pop);
+            }
+            self.compile(last, tail, out)
+        } else {
+            let i = out.constant(undef());
+            out.emit(Op::Const(i));
+            Ok(())
+        }
+    }
+
+    /// Emits storing of the value on the stack to a defined variable
+    fn store(&mut self, name: Symbol, out: &mut Lambda) {
+        match self.frames.define(name) {
+            None => {
+                let i = out.name(name);
+                out.emit(Op::DefineGlobal(i));
+            }
+            Some(index) => {
+                out.emit(Op::SetLocal(0, index));
+            }
+        }
+    }
+
+    fn compile_define(
+        &mut self, args: Vec<Rc<Object>>, location: Option<Location>, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        let head = args
+            .first()
+            .ok_or_else(|| EvalErr::TooFewArguments("define".to_string()))?;
+        match head.as_ref() {
+            Object::Symbol(s) => {
+                if args.len() < 2 {
+                    return Err(EvalErr::TooFewArguments("define".to_string()));
+                } else if args.len() > 2 {
+                    return Err(EvalErr::TooManyArguments("define".to_string()));
+                }
+                self.compile(&args[1], false, out)?;
+                self.store(*s, out);
+            }
+            Object::Pair(name, fun_args) => {
+                if let Object::Symbol(s) = name.as_ref() {
+                    self.compile_lambda(s.as_str(), fun_args, &args[1..], location, out)?;
+                    self.store(*s, out);
+                } else {
+                    return Err(EvalErr::ExpectedSymbolForFunctionName(name.to_string()));
+                }
+            }
+            x => return Err(EvalErr::WrongDefineArgument(x.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Compiles a nested lambda and emits making its closure
+    fn compile_lambda(
+        &mut self, name: &str, args: &Rc<Object>, body: &[Rc<Object>], location: Option<Location>,
+        out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        Function::check_args(args)?;
+        if body.is_empty() {
+            return Err(EvalErr::EmptyFunctionBody());
+        }
+        let mut lambda = Lambda::new(name, args.clone());
+        lambda.location = location;
+        let mut slots = lambda.params.names.clone();
+        for expr in body {
+            scan_defines(expr, &mut slots);
+        }
+        self.frames.push(slots);
+        let result = self.compile_body(body, true, &mut lambda);
+        let slots = self.frames.pop();
+        result?;
+        lambda.emit(Op::Return);
+        lambda.frame_size = slots.len();
+        out.lambdas.push(Rc::new(lambda));
+        out.emit(Op::Closure((out.lambdas.len() - 1) as u32));
+        Ok(())
+    }
+
+    /// Each `let` form evaluates its body in a new frame.
+    /// Values of `let` are computed before the frame is created,
+    /// `let*` and `letrec` store them to the frame one by one.
+    fn compile_let(
+        &mut self, args: Vec<Rc<Object>>, kind: LetKind, tail: bool, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        if args.len() < 2 {
+            return Err(EvalErr::NeedAtLeastArgs("let".to_string(), 2, args.len()));
+        }
+        let mut bindings = vec![];
+        for binding in list_to_vec(&args[0])? {
+            let init_expr = list_to_vec(&binding)?;
+            if init_expr.len() < 2 {
+                return Err(EvalErr::LetNeedListForBinding(binding.to_string()));
+            }
+            match init_expr[0].as_ref() {
+                Object::Symbol(s) => bindings.push((*s, init_expr[1].clone())),
+                var => return Err(EvalErr::LetNeedSymbolForBinding(var.to_string())),
+            }
+        }
+        let body = &args[1..];
+        let mut slots = vec![];
+        let enter = match kind {
+            LetKind::Let => {
+                for (var, init) in &bindings {
+                    self.compile(init, false, out)?;
+                    slots.push(*var);
+                }
+                out.emit(Op::EnterFrame(0, bindings.len() as u32))
+            }
+            LetKind::LetStar => {
+                let enter = out.emit(Op::EnterFrame(0, 0));
+                self.frames.push(vec![]);
+                for (var, init) in &bindings {
+                    let result = self.compile(init, false, out);
+                    let index = self.frames.bind(*var);
+                    result?;
+                    out.emit(Op::SetLocal(0, index));
+                }
+                slots = self.frames.pop();
+                enter
+            }
+            LetKind::LetRec => {
+                let enter = out.emit(Op::EnterFrame(0, 0));
+                self.frames
+                    .push(bindings.iter().map(|(var, _)| *var).collect());
+                for (index, (_, init)) in bindings.iter().enumerate() {
+                    if let Err(err) = self.compile(init, false, out) {
+                        self.frames.pop();
+                        return Err(err);
+                    }
+                    out.emit(Op::SetLocal(0, index as u32));
+                }
+                slots = self.frames.pop();
+                enter
+            }
+        };
+        for expr in body {
+            scan_defines(expr, &mut slots);
+        }
+        self.frames.push(slots);
+        let result = self.compile_body(body, tail, out);
+        let slots = self.frames.pop();
+        result?;
+        out.code[enter] = match out.code[enter] {
+            Op::EnterFrame(_, n) => Op::EnterFrame(slots.len() as u32, n),
+            op => op,
+        };
+        if !tail {
+            out.emit(Op::LeaveFrame);
+        }
+        Ok(())
+    }
+
+    fn compile_and(
+        &mut self, args: &[Rc<Object>], tail: bool, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        let (last, init) = match args.split_last() {
+            Some(split) => split,
+            None => {
+                let i = out.constant(Rc::new(Object::from(true)));
+                out.emit(Op::Const(i));
+                return Ok(());
+            }
+        };
+        let mut to_false = vec![];
+        for expr in init {
+            self.compile(expr, false, out)?;
+            to_false.push(out.emit(Op::JumpIfFalse(0)));
+        }
+        self.compile(last, tail, out)?;
+        if !init.is_empty() {
+            let to_end = out.emit(Op::Jump(0));
+            for at in to_false {
+                out.patch(at);
+            }
+            let i = out.constant(Rc::new(Object::from(false)));
+            out.emit(Op::Const(i));
+            out.patch(to_end);
+        }
+        Ok(())
+    }
+
+    fn compile_or(
+        &mut self, args: &[Rc<Object>], tail: bool, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        let (last, init) = match args.split_last() {
+            Some(split) => split,
+            None => {
+                let i = out.constant(Rc::new(Object::from(false)));
+                out.emit(Op::Const(i));
+                return Ok(());
+            }
+        };
+        let mut to_end = vec![];
+        for expr in init {
+            self.compile(expr, false, out)?;
+            to_end.push(out.emit(Op::JumpIfTrue(0)));
+        }
+        self.compile(last, tail, out)?;
+        for at in to_end {
+            out.patch(at);
+        }
+        Ok(())
+    }
+
+    fn compile_cond(
+        &mut self, clauses: Vec<Rc<Object>>, tail: bool, out: &mut Lambda,
+    ) -> Result<(), EvalErr> {
+        if clauses.is_empty() {
+            return Err(EvalErr::CondNeedsClause());
+        }
+        let mut to_end = vec![];
+        let mut has_else = false;
+        for clause in clauses {
+            let vec = list_to_vec(&clause)?;
+            match vec.as_slice() {
+                [] => return Err(EvalErr::CondEmptyClause()),
+                [test, body @ ..] if test.is_symbol("else") => {
+                    self.compile_body(body, tail, out)?;
+                    has_else = true;
+                    break;
+                }
+                [test] => {
+                    self.compile(test, false, out)?;
+                    to_end.push(out.emit(Op::JumpIfTrue(0)));
+                }
+                [test, body @ ..] => {
+                    self.compile(test, false, out)?;
+                    let to_next = out.emit(Op::JumpIfFalse(0));
+                    self.compile_body(body, tail, out)?;
+                    to_end.push(out.emit(Op::Jump(0)));
+                    out.patch(to_next);
+                }
+            }
+        }
+        if !has_else {
+            let i = out.constant(undef());
+            out.emit(Op::Const(i));
+        }
+        for at in to_end {
+            out.patch(at);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Runtime state shared by all the code evaluated on behalf of one interpreter.
+//!
+//! Native functions don't know which interpreter calls them,
+//! so the state lives in a thread-local variable which is switched by `Context::enter`.
+//! Code evaluated outside of any interpreter uses the default context
+//! that writes to the standard output and has no limits.
+//!
+//! Limits are checked on every step of `eval`, so a native function that runs long
+//! or allocates a lot is stopped only when it returns or calls back into Scheme code.
+
+use crate::errors::EvalErr;
+use crate::memory;
+
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Restrictions on the evaluation of a single top-level expression.
+/// `None` means that the resource is not limited.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// The maximal number of evaluation steps
+    pub max_steps: Option<u64>,
+    /// The maximal wall-clock time
+    pub max_time: Option<Duration>,
+    /// The maximal number of objects allocated by the evaluation that are alive at once
+    pub max_heap: Option<u64>,
+    /// The maximal depth of nested evaluations, calls in tail positions don't count
+    pub max_depth: Option<u64>,
+    /// The maximal number of bytes written to the output
+    pub max_output: Option<u64>,
+}
+
+pub struct Context {
+    output: RefCell<Box<dyn Write>>,
+    limits: RefCell<Limits>,
+    steps: Cell<u64>,
+    started: Cell<Instant>,
+    heap_base: Cell<u64>,
+    depth_base: Cell<u64>,
+    output_size: Cell<u64>,
+    command_line: Vec<String>,
+    library_paths: Vec<PathBuf>,
+    sandboxed: bool,
+    interrupt: InterruptHandle,
+}
+
+/// The time limit is checked once per this number of steps
+const TIME_CHECK_INTERVAL: u64 = 256;
+
+/// Stops the evaluation running in the interpreter it belongs to.
+/// The handle can be sent to other threads and used from signal handlers.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the running evaluation stop with `EvalErr::Interrupted` at the next step.
+    /// If nothing is running the interrupt is discarded when the next evaluation starts.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Resets the flag, returns `true` if it was set.
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Rc<Context>> =
+        RefCell::new(Rc::new(Context::new(Box::new(std::io::stdout()), Limits::default())));
+    /// The depth of nested `eval` calls
+    static DEPTH: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Restores the previous context when dropped.
+pub struct ContextGuard {
+    previous: Option<Rc<Context>>,
+}
+
+/// Leaves the level of nested evaluation when dropped.
+pub struct DepthGuard;
+
+impl Context {
+    pub fn new(output: Box<dyn Write>, limits: Limits) -> Self {
+        Context {
+            output: RefCell::new(output),
+            limits: RefCell::new(limits),
+            steps: Cell::new(0),
+            started: Cell::new(Instant::now()),
+            heap_base: Cell::new(0),
+            depth_base: Cell::new(0),
+            output_size: Cell::new(0),
+            command_line: vec![],
+            library_paths: vec![],
+            sandboxed: false,
+            interrupt: InterruptHandle::default(),
+        }
+    }
+
+    /// Forbids the code to access files and the process, see `Interpreter::sandbox`.
+    pub fn with_sandbox(mut self, sandboxed: bool) -> Self {
+        self.sandboxed = sandboxed;
+        self
+    }
+
+    /// Sets the program name and arguments returned by `command-line`.
+    pub fn with_command_line(mut self, args: Vec<String>) -> Self {
+        self.command_line = args;
+        self
+    }
+
+    /// Sets the directories where `load` looks for files with relative names.
+    pub fn with_library_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.library_paths = paths;
+        self
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        self.limits.replace(limits);
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits.borrow().clone()
+    }
+
+    /// Makes the context current for the calling thread until the guard is dropped.
+    /// Counters of the limited resources start from zero and a pending interrupt is discarded.
+    pub fn enter(self: &Rc<Self>) -> ContextGuard {
+        self.interrupt.take();
+        self.steps.set(0);
+        self.started.set(Instant::now());
+        self.heap_base.set(memory::live_objects());
+        self.depth_base.set(DEPTH.with(Cell::get));
+        self.output_size.set(0);
+        let previous = CONTEXT.with(|c| c.replace(self.clone()));
+        ContextGuard { previous: Some(previous) }
+    }
+
+    fn tick(&self) -> Result<(), EvalErr> {
+        if self.interrupt.take() {
+            return Err(EvalErr::Interrupted());
+        }
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        let limits = self.limits.borrow();
+        if let Some(max) = limits.max_steps {
+            if steps > max {
+                return Err(EvalErr::LimitExceeded("steps", max));
+            }
+        }
+        if let Some(max) = limits.max_heap {
+            // objects made before the evaluation may be freed by it
+            if memory::live_objects().saturating_sub(self.heap_base.get()) > max {
+                return Err(EvalErr::LimitExceeded("objects", max));
+            }
+        }
+        if let Some(max) = limits.max_time {
+            if steps.is_multiple_of(TIME_CHECK_INTERVAL) && self.started.get().elapsed() > max {
+                return Err(EvalErr::LimitExceeded(
+                    "milliseconds",
+                    max.as_millis() as u64,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_depth(&self, depth: u64) -> Result<(), EvalErr> {
+        match self.limits.borrow().max_depth {
+            Some(max) if depth - self.depth_base.get() > max => {
+                Err(EvalErr::LimitExceeded("nested evaluations", max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, s: &str) -> Result<(), EvalErr> {
+        let size = self.output_size.get() + s.len() as u64;
+        if let Some(max) = self.limits.borrow().max_output {
+            if size > max {
+                return Err(EvalErr::LimitExceeded("bytes of output", max));
+            }
+        }
+        self.output_size.set(size);
+        (self.output.borrow_mut())
+            .write_all(s.as_bytes())
+            .map_err(|err| EvalErr::IoError(err.to_string()))
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            let current = CONTEXT.with(|c| c.replace(previous));
+            let _ = current.output.borrow_mut().flush();
+        }
+    }
+}
+
+/// Accounts one step of evaluation and checks that the limits are not exceeded.
+pub fn tick() -> Result<(), EvalErr> {
+    CONTEXT.with(|c| c.borrow().tick())
+}
+
+/// Enters a nested evaluation and checks that the depth limit is not exceeded.
+pub fn enter_eval() -> Result<DepthGuard, EvalErr> {
+    let depth = DEPTH.with(|d| {
+        d.set(d.get() + 1);
+        d.get()
+    });
+    let guard = DepthGuard;
+    CONTEXT.with(|c| c.borrow().check_depth(depth))?;
+    Ok(guard)
+}
+
+/// Checks if file and process primitives are forbidden
+pub fn is_sandboxed() -> bool {
+    CONTEXT.with(|c| c.borrow().sandboxed)
+}
+
+/// The handle interrupting the evaluations in the current context
+pub fn interrupt_handle() -> InterruptHandle {
+    CONTEXT.with(|c| c.borrow().interrupt.clone())
+}
+
+pub fn command_line() -> Vec<String> {
+    CONTEXT.with(|c| c.borrow().command_line.clone())
+}
+
+/// Finds a file to load. Relative names that don't exist in the working directory
+/// are searched in the library paths. The name is returned as is if nothing is found.
+pub fn find_file(name: &str) -> PathBuf {
+    let path = Path::new(name);
+    if path.is_absolute() || path.exists() {
+        return path.to_path_buf();
+    }
+    CONTEXT.with(|c| {
+        (c.borrow().library_paths.iter())
+            .map(|dir| dir.join(path))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| path.to_path_buf())
+    })
+}
+
+/// Writes a string to the output of the current context.
+pub fn write_output(s: &str) -> Result<(), EvalErr> {
+    CONTEXT.with(|c| c.borrow().write(s))
+}
@@ -0,0 +1,324 @@
+//! Conversions between Rust values and Scheme objects.
+//!
+//! `FromScheme` extracts a Rust value from an object and fails with an `EvalErr`
+//! if the object has an unsuitable type. `IntoScheme` goes the opposite way and never fails.
+//! `FromArgs` applies `FromScheme` to the arguments of a native function at once.
+//!
+//! Compound values use the following representation:
+//! * `Vec<T>` and tuples are proper lists;
+//! * `HashMap<K, V>` is an association list of pairs `(key . value)`;
+//! * `Option<T>` is `#f` for `None` and the value itself for `Some`.
+
+use crate::errors::EvalErr;
+use crate::object::{Number, Object};
+use crate::service::{check_pair, list_to_vec, undef, vec_to_list};
+use crate::symbol::Symbol;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::rc::Rc;
+
+pub trait FromScheme: Sized {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr>;
+}
+
+pub trait IntoScheme {
+    fn into_scheme(self) -> Rc<Object>;
+}
+
+/// Conversion of the whole list of arguments given to a native function.
+///
+/// Tuples check the number of arguments, `Vec<T>` accepts any number of them.
+pub trait FromArgs: Sized {
+    fn from_args(args: &[Rc<Object>], func: &str) -> Result<Self, EvalErr>;
+}
+
+fn mismatch(expected: &str, obj: &Object) -> EvalErr {
+    EvalErr::TypeMismatch(expected.to_string(), obj.to_string())
+}
+
+impl FromScheme for Rc<Object> {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        Ok(obj.clone())
+    }
+}
+
+impl FromScheme for Number {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Number(n) => Ok(*n),
+            x => Err(mismatch("number", x)),
+        }
+    }
+}
+
+impl FromScheme for i64 {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Number(Number::Integer(n)) => Ok(*n),
+            x => Err(mismatch("integer", x)),
+        }
+    }
+}
+
+/// Integers are silently converted to floats
+impl FromScheme for f64 {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Number(Number::Integer(n)) => Ok(*n as f64),
+            Object::Number(Number::Float(n)) => Ok(*n),
+            x => Err(mismatch("number", x)),
+        }
+    }
+}
+
+impl FromScheme for bool {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Boolean(b) => Ok(*b),
+            x => Err(mismatch("boolean", x)),
+        }
+    }
+}
+
+impl FromScheme for String {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::String(s) => Ok(s.clone()),
+            x => Err(mismatch("string", x)),
+        }
+    }
+}
+
+impl FromScheme for Symbol {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Symbol(s) => Ok(*s),
+            x => Err(mismatch("symbol", x)),
+        }
+    }
+}
+
+impl<T: FromScheme> FromScheme for Vec<T> {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        list_to_vec(obj)?.iter().map(T::from_scheme).collect()
+    }
+}
+
+/// `#f` becomes `None` so `Option<bool>` never gets `Some(false)`
+impl<T: FromScheme> FromScheme for Option<T> {
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        match obj.as_ref() {
+            Object::Boolean(false) => Ok(None),
+            _ => T::from_scheme(obj).map(Some),
+        }
+    }
+}
+
+impl<K, V, S> FromScheme for HashMap<K, V, S>
+where
+    K: FromScheme + Eq + Hash,
+    V: FromScheme,
+    S: BuildHasher + Default,
+{
+    fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+        let mut map = HashMap::with_hasher(S::default());
+        for entry in list_to_vec(obj)? {
+            let (key, value) = check_pair(&entry)?;
+            map.insert(K::from_scheme(key)?, V::from_scheme(value)?);
+        }
+        Ok(map)
+    }
+}
+
+impl IntoScheme for Rc<Object> {
+    fn into_scheme(self) -> Rc<Object> {
+        self
+    }
+}
+
+impl IntoScheme for Object {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(self)
+    }
+}
+
+/// Functions that have nothing to return produce the undefined value
+impl IntoScheme for () {
+    fn into_scheme(self) -> Rc<Object> {
+        undef()
+    }
+}
+
+impl IntoScheme for Number {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(self))
+    }
+}
+
+impl IntoScheme for i64 {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(Number::Integer(self)))
+    }
+}
+
+impl IntoScheme for f64 {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(Number::Float(self)))
+    }
+}
+
+impl IntoScheme for bool {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(self))
+    }
+}
+
+impl IntoScheme for String {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(self))
+    }
+}
+
+impl IntoScheme for &str {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(self.to_string()))
+    }
+}
+
+impl IntoScheme for Symbol {
+    fn into_scheme(self) -> Rc<Object> {
+        Rc::new(Object::from(self))
+    }
+}
+
+impl<T: IntoScheme> IntoScheme for Vec<T> {
+    fn into_scheme(self) -> Rc<Object> {
+        let vec = self.into_iter().map(T::into_scheme).collect::<Vec<_>>();
+        Rc::new(vec_to_list(&vec))
+    }
+}
+
+impl<T: IntoScheme> IntoScheme for Option<T> {
+    fn into_scheme(self) -> Rc<Object> {
+        self.map_or_else(|| Rc::new(Object::from(false)), T::into_scheme)
+    }
+}
+
+impl<K: IntoScheme, V: IntoScheme, S> IntoScheme for HashMap<K, V, S> {
+    fn into_scheme(self) -> Rc<Object> {
+        let entries = (self.into_iter())
+            .map(|(k, v)| Rc::new(Object::cons(k.into_scheme(), v.into_scheme())))
+            .collect::<Vec<_>>();
+        Rc::new(vec_to_list(&entries))
+    }
+}
+
+impl<T: FromScheme> FromArgs for Vec<T> {
+    fn from_args(args: &[Rc<Object>], _func: &str) -> Result<Self, EvalErr> {
+        args.iter().map(T::from_scheme).collect()
+    }
+}
+
+macro_rules! tuple_impls {
+    ($len:expr => $($name:ident : $idx:tt),+) => {
+        impl<$($name: FromScheme),+> FromScheme for ($($name,)+) {
+            fn from_scheme(obj: &Rc<Object>) -> Result<Self, EvalErr> {
+                let vec = list_to_vec(obj)?;
+                if vec.len() != $len {
+                    return Err(mismatch(concat!("list of length ", $len), obj));
+                }
+                Ok(($($name::from_scheme(&vec[$idx])?,)+))
+            }
+        }
+
+        impl<$($name: IntoScheme),+> IntoScheme for ($($name,)+) {
+            fn into_scheme(self) -> Rc<Object> {
+                Rc::new(vec_to_list(&[$(self.$idx.into_scheme()),+]))
+            }
+        }
+
+        impl<$($name: FromScheme),+> FromArgs for ($($name,)+) {
+            fn from_args(args: &[Rc<Object>], func: &str) -> Result<Self, EvalErr> {
+                if args.len() != $len {
+                    return Err(EvalErr::WrongAgrsNum(func.to_string(), $len, args.len()));
+                }
+                Ok(($($name::from_scheme(&args[$idx])?,)+))
+            }
+        }
+    };
+}
+
+tuple_impls!(1 => A: 0);
+tuple_impls!(2 => A: 0, B: 1);
+tuple_impls!(3 => A: 0, B: 1, C: 2);
+tuple_impls!(4 => A: 0, B: 1, C: 2, D: 3);
+
+/// Converts arguments of a native function to the required Rust types.
+///
+/// ```
+/// # use scheme::convert::from_args;
+/// # use scheme::convert::IntoScheme;
+/// let args = vec![1i64.into_scheme(), 2.5.into_scheme()];
+/// let (a, b): (i64, f64) = from_args(&args, "foo").unwrap();
+/// assert_eq!((a, b), (1, 2.5));
+/// ```
+pub fn from_args<T: FromArgs>(args: &[Rc<Object>], func: &str) -> Result<T, EvalErr> {
+    T::from_args(args, func)
+}
+
+#[cfg(test)]
+#[rustfmt::skip]
+mod tests {
+
+    use super::*;
+    use crate::parser::parse_expression;
+
+    fn parse(src: &str) -> Rc<Object> {
+        Rc::new(parse_expression(src).unwrap().pop().unwrap())
+    }
+
+    #[test]
+    fn test_from_scheme() {
+        assert_eq!(i64::from_scheme(&parse("42")), Ok(42));
+        assert_eq!(f64::from_scheme(&parse("42")), Ok(42.0));
+        assert_eq!(String::from_scheme(&parse("\"foo\"")), Ok("foo".to_string()));
+        assert_eq!(Vec::<i64>::from_scheme(&parse("(1 2 3)")), Ok(vec![1, 2, 3]));
+        assert_eq!(Option::<i64>::from_scheme(&parse("#f")), Ok(None));
+        assert_eq!(<(i64, bool)>::from_scheme(&parse("(1 #t)")), Ok((1, true)));
+
+        let map = HashMap::<String, i64>::from_scheme(&parse("((\"a\" . 1) (\"b\" . 2))"));
+        assert_eq!(map.unwrap().get("b"), Some(&2));
+
+        assert_eq!(
+            i64::from_scheme(&parse("1.5")),
+            Err(EvalErr::TypeMismatch("integer".to_string(), "1.5".to_string()))
+        );
+        assert_eq!(
+            Vec::<i64>::from_scheme(&parse("(1 . 2)")),
+            Err(EvalErr::ListRequired("(1 . 2)".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_into_scheme() {
+        assert_eq!(format!("{}", vec![1i64, 2, 3].into_scheme()), "(1 2 3)");
+        assert_eq!(format!("{}", (1i64, "a", 2.5).into_scheme()), "(1 \"a\" 2.5)");
+        assert_eq!(format!("{}", Some(true).into_scheme()), "#t");
+        assert_eq!(format!("{}", None::<i64>.into_scheme()), "#f");
+
+        let map = HashMap::from([("a", 1i64)]);
+        assert_eq!(format!("{}", map.into_scheme()), "((\"a\" . 1))");
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args = vec![1i64.into_scheme(), "x".into_scheme()];
+        assert_eq!(from_args::<(i64, String)>(&args, "f"), Ok((1, "x".to_string())));
+        assert_eq!(
+            from_args::<(i64,)>(&args, "f"),
+            Err(EvalErr::WrongAgrsNum("f".to_string(), 1, 2))
+        );
+        assert_eq!(from_args::<Vec<i64>>(&args[..1], "f"), Ok(vec![1]));
+    }
+}
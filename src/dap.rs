@@ -0,0 +1,427 @@
+//! Debug Adapter Protocol server, editors talk to it to debug scripts.
+//!
+//! Messages are read from the input and written to the output with the `Content-Length` header.
+//! `launch` remembers the script which is evaluated by the tree-walker after `configurationDone`.
+//! While the script is paused the requests are answered by the hook attached to the debugger,
+//! references to scopes and lists given to the client are valid until the script is resumed.
+//! What the script prints is sent to the client as `output` events.
+
+use crate::debugger::{self, Breakpoint, DebugHook, Pause, PauseReason, Resume, StackFrame};
+use crate::errors::EvalErr;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+use crate::protocol::Connection;
+use crate::scope::Scope;
+
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Scripts are evaluated in a single thread
+const THREAD_ID: i64 = 1;
+
+struct Launch {
+    program: String,
+    args: Vec<String>,
+    stop_on_entry: bool,
+}
+
+struct Adapter {
+    connection: RefCell<Connection>,
+    /// The sequence number of the last sent message
+    seq: Cell<i64>,
+    launch: RefCell<Option<Launch>>,
+    /// Ids of the breakpoints set by the client by the source path, `None` for procedures
+    breakpoints: RefCell<HashMap<Option<String>, Vec<usize>>>,
+    disconnected: Cell<bool>,
+}
+
+/// The hook answering requests while the script is paused
+struct Session {
+    adapter: Rc<Adapter>,
+    references: Vec<Reference>,
+    /// The script is started with a step, so the first pause is at its entry
+    entry: bool,
+}
+
+/// Values that the client can expand
+enum Reference {
+    Scope(Rc<Scope>),
+    List(Rc<Object>),
+}
+
+/// Sends what the script prints as `output` events
+struct OutputEvents(Rc<Adapter>);
+
+/// Serves the client until it disconnects or the input ends.
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Result<(), Box<dyn Error>> {
+    let adapter = Rc::new(Adapter {
+        connection: RefCell::new(Connection::new(input, output)),
+        seq: Cell::new(0),
+        launch: RefCell::new(None),
+        breakpoints: RefCell::new(HashMap::new()),
+        disconnected: Cell::new(false),
+    });
+    while !adapter.disconnected.get() {
+        let request = match adapter.read()? {
+            Some(request) => request,
+            None => break,
+        };
+        let result = match command(&request) {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsEvaluateForHovers": true,
+                });
+                adapter.respond(&request, Ok(capabilities))?;
+                adapter.event("initialized", json!({}))?;
+                continue;
+            }
+            "launch" => adapter.launch(&request["arguments"]),
+            "configurationDone" => {
+                adapter.respond(&request, Ok(json!({})))?;
+                adapter.run()?;
+                continue;
+            }
+            "disconnect" => {
+                adapter.disconnected.set(true);
+                Ok(json!({}))
+            }
+            _ => match adapter.common(&request) {
+                Some(result) => result,
+                None => Err(format!("{} is not supported now", command(&request))),
+            },
+        };
+        adapter.respond(&request, result)?;
+    }
+    Ok(())
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+/// Makes paths of breakpoints and scripts comparable
+fn canonical(path: &str) -> String {
+    match std::fs::canonicalize(path) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => path.to_string(),
+    }
+}
+
+impl Adapter {
+    fn read(&self) -> std::io::Result<Option<Value>> {
+        self.connection.borrow_mut().read()
+    }
+
+    fn respond(&self, request: &Value, result: Result<Value, String>) -> std::io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&self, event: &str, body: Value) -> std::io::Result<()> {
+        let event = json!({ "type": "event", "event": event, "body": body });
+        self.send(event)
+    }
+
+    fn send(&self, mut message: Value) -> std::io::Result<()> {
+        self.seq.set(self.seq.get() + 1);
+        message["seq"] = json!(self.seq.get());
+        self.connection.borrow_mut().send(&message)
+    }
+
+    fn launch(&self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("launch needs a program")?;
+        let launch = Launch {
+            program: canonical(program),
+            args: (args["args"].as_array().into_iter().flatten())
+                .filter_map(|arg| arg.as_str().map(str::to_string))
+                .collect(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+        };
+        self.launch.replace(Some(launch));
+        Ok(json!({}))
+    }
+
+    /// Evaluates the launched script, the requests are answered by the session while it is paused
+    fn run(self: &Rc<Self>) -> std::io::Result<()> {
+        let launch = match self.launch.borrow_mut().take() {
+            Some(launch) => launch,
+            None => return self.event("terminated", json!({})),
+        };
+        let mut command_line = vec![launch.program.clone()];
+        command_line.extend(launch.args.iter().cloned());
+        let interpreter = Interpreter::builder()
+            .command_line(command_line)
+            .stdout(OutputEvents(self.clone()))
+            .build();
+        let result = interpreter.and_then(|interpreter| {
+            debugger::attach(Session {
+                adapter: self.clone(),
+                references: vec![],
+                entry: launch.stop_on_entry,
+            });
+            if launch.stop_on_entry {
+                debugger::step_in();
+            }
+            let result = interpreter.eval_file(&launch.program);
+            debugger::detach();
+            result
+        });
+        if self.disconnected.get() {
+            return Ok(());
+        }
+        let code = match result {
+            Ok(()) => 0,
+            Err(err) => match err.downcast_ref::<EvalErr>() {
+                Some(EvalErr::Exit(code)) => *code,
+                _ => {
+                    let output = format!("Error: {}\n", err);
+                    self.event("output", json!({ "category": "stderr", "output": output }))?;
+                    1
+                }
+            },
+        };
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    /// Handles requests that are answered the same way whether the script is paused or not
+    fn common(&self, request: &Value) -> Option<Result<Value, String>> {
+        let args = &request["arguments"];
+        let result = match command(request) {
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "setBreakpoints" => {
+                let path = args["source"]["path"].as_str().map(canonical);
+                let lines = (args["breakpoints"].as_array().into_iter().flatten())
+                    .filter_map(|b| b["line"].as_u64())
+                    .map(|line| Breakpoint::Line { file: path.clone(), line: line as u32 });
+                let breakpoints = (self.replace_breakpoints(path.clone(), lines).into_iter())
+                    .map(|(id, b)| match b {
+                        Breakpoint::Line { line, .. } => {
+                            json!({ "id": id, "verified": true, "line": line })
+                        }
+                        Breakpoint::Procedure(_) => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                let names = (args["breakpoints"].as_array().into_iter().flatten())
+                    .filter_map(|b| b["name"].as_str())
+                    .map(|name| Breakpoint::Procedure(name.to_string()));
+                let breakpoints = (self.replace_breakpoints(None, names).into_iter())
+                    .map(|(id, _)| json!({ "id": id, "verified": true }))
+                    .collect::<Vec<_>>();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Replaces the breakpoints of the source or the procedure ones if the source is `None`
+    fn replace_breakpoints(
+        &self, source: Option<String>, breakpoints: impl Iterator<Item = Breakpoint>,
+    ) -> Vec<(usize, Breakpoint)> {
+        let mut ids = self.breakpoints.borrow_mut();
+        for id in ids.remove(&source).unwrap_or_default() {
+            debugger::remove_breakpoint(id);
+        }
+        let added = (breakpoints)
+            .map(|b| (debugger::add_breakpoint(b.clone()), b))
+            .collect::<Vec<_>>();
+        ids.insert(source, added.iter().map(|(id, _)| *id).collect());
+        added
+    }
+
+    fn is_procedure_breakpoint(&self, id: usize) -> bool {
+        (self.breakpoints.borrow().get(&None)).is_some_and(|ids| ids.contains(&id))
+    }
+}
+
+impl Session {
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    /// Lists can be expanded by the client, other values have no reference
+    fn value_reference(&mut self, value: &Rc<Object>) -> usize {
+        match value.as_ref() {
+            Object::Pair(..) => self.reference(Reference::List(value.clone())),
+            _ => 0,
+        }
+    }
+
+    fn variable(&mut self, name: &str, value: &Rc<Object>) -> Value {
+        let reference = self.value_reference(value);
+        json!({ "name": name, "value": value.to_string(), "variablesReference": reference })
+    }
+
+    fn frame<'a>(pause: &'a Pause, args: &Value) -> Result<&'a StackFrame, String> {
+        let id = args["frameId"].as_u64().unwrap_or(1) as usize;
+        (id.checked_sub(1))
+            .and_then(|i| pause.frames.get(i))
+            .ok_or_else(|| format!("no frame {}", id))
+    }
+
+    fn stack_trace(pause: &Pause) -> Value {
+        let frames = (pause.frames.iter().enumerate())
+            .map(|(i, frame)| {
+                let location = frame.location();
+                let mut json = json!({
+                    "id": i + 1,
+                    "name": frame.procedure.as_deref().unwrap_or("top level"),
+                    "line": location.as_ref().map_or(0, |l| l.line),
+                    "column": 1,
+                });
+                if let Some(file) = location.and_then(|l| l.file) {
+                    let name = Path::new(file.as_ref()).file_name().unwrap_or_default();
+                    json["source"] =
+                        json!({ "name": name.to_string_lossy(), "path": file.as_ref() });
+                }
+                json
+            })
+            .collect::<Vec<_>>();
+        json!({ "stackFrames": frames, "totalFrames": pause.frames.len() })
+    }
+
+    /// Local scopes from the innermost one and the top-level scope, the built-in one is skipped
+    fn scopes(&mut self, frame: &StackFrame) -> Value {
+        let mut chain = frame.scopes();
+        chain.pop();
+        let count = chain.len();
+        let scopes = (chain.into_iter().enumerate())
+            .map(|(i, scope)| {
+                let name = match i {
+                    _ if i + 1 == count => "Globals".to_string(),
+                    0 => "Locals".to_string(),
+                    _ => format!("Outer {}", i),
+                };
+                let reference = self.reference(Reference::Scope(scope));
+                json!({ "name": name, "variablesReference": reference, "expensive": i + 1 == count })
+            })
+            .collect::<Vec<_>>();
+        json!({ "scopes": scopes })
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let id = args["variablesReference"].as_u64().unwrap_or_default() as usize;
+        let values = match id.checked_sub(1).and_then(|i| self.references.get(i)) {
+            Some(Reference::Scope(scope)) => scope.bindings(),
+            Some(Reference::List(list)) => {
+                let mut values = vec![];
+                let mut rest = list.clone();
+                while let Object::Pair(head, tail) = rest.clone().as_ref() {
+                    values.push((values.len().to_string(), head.clone()));
+                    rest = tail.clone();
+                }
+                if !rest.is_nil() {
+                    values.push(("rest".to_string(), rest));
+                }
+                values
+            }
+            None => return Err(format!("no variables {}", id)),
+        };
+        let variables = (values.iter())
+            .map(|(name, value)| self.variable(name, value))
+            .collect::<Vec<_>>();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Answers the request, returns how to resume the script if the request says so
+    fn handle(
+        &mut self, pause: &Pause, request: &Value,
+    ) -> (Result<Value, String>, Option<Resume>) {
+        let args = &request["arguments"];
+        let resume = |resume| (Ok(json!({ "allThreadsContinued": true })), Some(resume));
+        let result = match command(request) {
+            "continue" => return resume(Resume::Continue),
+            "next" => return resume(Resume::StepOver),
+            "stepIn" => return resume(Resume::StepIn),
+            "stepOut" => return resume(Resume::StepOut),
+            "disconnect" => {
+                self.adapter.disconnected.set(true);
+                return (Ok(json!({})), Some(Resume::Abort));
+            }
+            "stackTrace" => Ok(Session::stack_trace(pause)),
+            "scopes" => Session::frame(pause, args).map(|frame| self.scopes(frame)),
+            "variables" => self.variables(args),
+            "evaluate" => Session::frame(pause, args).and_then(|frame| {
+                let expression = args["expression"].as_str().unwrap_or_default();
+                let value = frame.eval(expression).map_err(|e| e.to_string())?;
+                let reference = self.value_reference(&value);
+                Ok(json!({ "result": value.to_string(), "variablesReference": reference }))
+            }),
+            _ => match self.adapter.common(request) {
+                Some(result) => result,
+                None => Err(format!("{} is not supported now", command(request))),
+            },
+        };
+        (result, None)
+    }
+}
+
+impl DebugHook for Session {
+    fn paused(&mut self, pause: &Pause) -> Resume {
+        self.references.clear();
+        let reason = match pause.reason {
+            PauseReason::Breakpoint(id) if self.adapter.is_procedure_breakpoint(id) => {
+                "function breakpoint"
+            }
+            PauseReason::Breakpoint(_) => "breakpoint",
+            PauseReason::Step if self.entry => "entry",
+            PauseReason::Step => "step",
+        };
+        self.entry = false;
+        let mut stopped =
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let PauseReason::Breakpoint(id) = pause.reason {
+            stopped["hitBreakpointIds"] = json!([id]);
+        }
+        if self.adapter.event("stopped", stopped).is_err() {
+            return Resume::Abort;
+        }
+        loop {
+            let request = match self.adapter.read() {
+                Ok(Some(request)) => request,
+                _ => return Resume::Abort,
+            };
+            let (result, resume) = self.handle(pause, &request);
+            if self.adapter.respond(&request, result).is_err() {
+                return Resume::Abort;
+            }
+            if let Some(resume) = resume {
+                return resume;
+            }
+        }
+    }
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let output = String::from_utf8_lossy(buf);
+        self.0
+            .event("output", json!({ "category": "stdout", "output": output }))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+//! Step debugger of the tree-walking evaluator.
+//!
+//! A `DebugHook` attached to the thread is called when `eval::eval` pauses: at a breakpoint
+//! or at the step requested by the hook when it resumed the evaluation last time.
+//! A breakpoint on a procedure pauses at the first expression of its body, a breakpoint
+//! on a line pauses at the outermost form written on the line. Only the tree-walker
+//! is paused, code evaluated by the other engines runs as if no hook is attached.
+//!
+//! While a hook is attached `eval` keeps a frame for every nested evaluation.
+//! The body of a procedure is evaluated in the frame where the procedure is called,
+//! so calls in tail positions replace the procedure of the frame. The stack shown
+//! to the hook merges frames by the procedures they evaluate.
+
+use crate::errors::EvalErr;
+use crate::eval::eval;
+use crate::object::Object;
+use crate::parser::parse_expression;
+use crate::scope::Scope;
+use crate::source::{self, Location};
+
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+
+thread_local! {
+    /// Set when a hook is attached and the evaluation is not paused
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    static DEBUGGER: RefCell<Debugger> = RefCell::new(Debugger::default());
+}
+
+/// Receives control when the evaluation pauses.
+pub trait DebugHook {
+    /// Called when the evaluation pauses, it goes on as the returned value says.
+    /// The debugger doesn't pause in the expressions evaluated by the hook.
+    fn paused(&mut self, pause: &Pause) -> Resume;
+}
+
+impl<F: FnMut(&Pause) -> Resume> DebugHook for F {
+    fn paused(&mut self, pause: &Pause) -> Resume {
+        self(pause)
+    }
+}
+
+/// How the paused evaluation goes on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until a breakpoint
+    Continue,
+    /// Pause at the next step
+    StepIn,
+    /// Pause at the next step of the current procedure or its callers
+    StepOver,
+    /// Pause at the next step of the caller of the current procedure
+    StepOut,
+    /// Stop the evaluation with `EvalErr::Interrupted`
+    Abort,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseReason {
+    /// The breakpoint with the id is hit
+    Breakpoint(usize),
+    /// The step requested by the hook is made
+    Step,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Pauses at the first expression of the body when the procedure is called
+    Procedure(String),
+    /// Pauses at the outermost form written on the line, a breakpoint without a file
+    /// matches every file, otherwise the file matches paths ending with it
+    Line { file: Option<String>, line: u32 },
+}
+
+/// The state of the paused evaluation
+pub struct Pause {
+    pub reason: PauseReason,
+    /// Frames of the evaluated procedures, the innermost first
+    pub frames: Vec<StackFrame>,
+}
+
+#[derive(Clone)]
+pub struct StackFrame {
+    /// The procedure evaluated in the frame, `None` for the top level
+    pub procedure: Option<String>,
+    /// The expression that is evaluated now
+    pub expr: Rc<Object>,
+    pub scope: Rc<Scope>,
+}
+
+#[derive(Default)]
+struct Debugger {
+    hook: Option<Box<dyn DebugHook>>,
+    attached: bool,
+    paused: bool,
+    breakpoints: Vec<(usize, Breakpoint)>,
+    last_id: usize,
+    mode: Mode,
+    frames: Vec<Frame>,
+    /// The breakpoint of the procedure that was just called, the next step pauses at it
+    entered: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Mode {
+    #[default]
+    Run,
+    StepIn,
+    /// Pause in outer frames or in the frame at the depth while it evaluates the same call
+    StepOver(usize, u32),
+    /// Pause in frames outer than the depth
+    StepOut(usize),
+}
+
+/// An expression with the scope it is evaluated in
+type Position = (Rc<Object>, Rc<Scope>);
+
+#[derive(Default)]
+struct Frame {
+    procedure: Option<String>,
+    /// The number of procedures called in the frame
+    calls: u32,
+    position: Option<Position>,
+    /// The position where the first procedure was called, it belongs to the caller
+    call_site: Option<Position>,
+}
+
+/// The frame of a nested evaluation, removed when dropped
+pub(crate) struct FrameGuard(bool);
+
+impl FrameGuard {
+    /// Checks if the steps of the frame should be passed to `step`
+    #[inline]
+    pub(crate) fn is_tracked(&self) -> bool {
+        self.0
+    }
+}
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = DEBUGGER.try_with(|d| {
+                let mut d = d.borrow_mut();
+                d.frames.pop();
+                if d.frames.is_empty() {
+                    // the step continues at the next top-level expression
+                    if let Mode::StepOver(..) | Mode::StepOut(_) = d.mode {
+                        d.mode = Mode::StepIn;
+                    }
+                    d.entered = None;
+                }
+            });
+        }
+    }
+}
+
+/// Starts a frame of a nested evaluation, it is tracked only if a hook is attached.
+#[inline]
+pub(crate) fn enter_frame() -> FrameGuard {
+    if !ACTIVE.with(Cell::get) {
+        return FrameGuard(false);
+    }
+    DEBUGGER.with(|d| d.borrow_mut().frames.push(Frame::default()));
+    FrameGuard(true)
+}
+
+/// Marks the innermost frame as evaluating the body of the procedure.
+pub(crate) fn enter_procedure(name: &str) {
+    if ACTIVE.with(Cell::get) {
+        DEBUGGER.with(|d| d.borrow_mut().enter_procedure(name));
+    }
+}
+
+/// Makes a step of the innermost frame, pauses if it is needed.
+pub(crate) fn step(obj: &Rc<Object>, scope: &Rc<Scope>) -> Result<(), EvalErr> {
+    match DEBUGGER.with(|d| d.borrow_mut().step(obj, scope)) {
+        Some(reason) => pause(reason),
+        None => Ok(()),
+    }
+}
+
+fn pause(reason: PauseReason) -> Result<(), EvalErr> {
+    let paused = DEBUGGER.with(|d| {
+        let mut d = d.borrow_mut();
+        let hook = d.hook.take()?;
+        d.paused = true;
+        Some((hook, d.stack()))
+    });
+    let (mut hook, frames) = match paused {
+        Some(paused) => paused,
+        None => return Ok(()),
+    };
+    ACTIVE.with(|a| a.set(false));
+    let resume = hook.paused(&Pause { reason, frames });
+    DEBUGGER.with(|d| {
+        let mut d = d.borrow_mut();
+        d.paused = false;
+        // the hook may be replaced or detached while the evaluation is paused
+        if d.attached && d.hook.is_none() {
+            d.hook = Some(hook);
+        }
+        ACTIVE.with(|a| a.set(d.attached));
+        d.resume(resume)
+    })
+}
+
+impl Debugger {
+    fn enter_procedure(&mut self, name: &str) {
+        if let Some(frame) = self.frames.last_mut() {
+            if frame.procedure.is_none() {
+                frame.call_site = frame.position.take();
+            }
+            frame.procedure = Some(name.to_string());
+            frame.calls += 1;
+        }
+        let breakpoint = (self.breakpoints.iter())
+            .find(|(_, b)| matches!(b, Breakpoint::Procedure(p) if p == name));
+        if let Some((id, _)) = breakpoint {
+            self.entered = Some(*id);
+        }
+    }
+
+    fn step(&mut self, obj: &Rc<Object>, scope: &Rc<Scope>) -> Option<PauseReason> {
+        let depth = self.frames.len();
+        let frame = self.frames.last_mut()?;
+        let previous = frame.position.replace((obj.clone(), scope.clone()));
+        let calls = frame.calls;
+        if let Some(id) = self.entered.take() {
+            return Some(PauseReason::Breakpoint(id));
+        }
+        let tail = match obj.as_ref() {
+            Object::Pair(_, tail) => tail,
+            _ => return None,
+        };
+        let stepped = match self.mode {
+            Mode::Run => false,
+            Mode::StepIn => true,
+            Mode::StepOver(d, c) => depth < d || (depth == d && calls == c),
+            Mode::StepOut(d) => depth < d,
+        };
+        if stepped {
+            return Some(PauseReason::Step);
+        }
+        self.line_breakpoint(tail, previous)
+    }
+
+    /// Finds a breakpoint on the line of the form unless the previous step of the frame
+    /// or the form of the outer frame evaluating the same procedure are on the same line
+    fn line_breakpoint(
+        &self, tail: &Rc<Object>, previous: Option<Position>,
+    ) -> Option<PauseReason> {
+        let mut lines = self
+            .breakpoints
+            .iter()
+            .filter(|(_, b)| b.is_line())
+            .peekable();
+        lines.peek()?;
+        let location = source::location(tail)?;
+        let (id, _) = lines.find(|(_, b)| b.matches(&location))?;
+        let outer = match self.frames.as_slice() {
+            [.., outer, frame] if frame.calls == 0 => outer.position.clone(),
+            _ => None,
+        };
+        let same_line = |position: Option<Position>| {
+            position.and_then(|(expr, _)| expr_location(&expr)).as_ref() == Some(&location)
+        };
+        if same_line(previous) || same_line(outer) {
+            return None;
+        }
+        Some(PauseReason::Breakpoint(*id))
+    }
+
+    fn resume(&mut self, resume: Resume) -> Result<(), EvalErr> {
+        let depth = self.frames.len();
+        self.mode = match resume {
+            Resume::Continue => Mode::Run,
+            Resume::StepIn => Mode::StepIn,
+            Resume::StepOver => Mode::StepOver(depth, self.frames.last().map_or(0, |f| f.calls)),
+            Resume::StepOut => {
+                let procedure = self.frames.iter().rposition(|f| f.procedure.is_some());
+                Mode::StepOut(procedure.unwrap_or(0) + 1)
+            }
+            Resume::Abort => {
+                self.mode = Mode::Run;
+                return Err(EvalErr::Interrupted());
+            }
+        };
+        Ok(())
+    }
+
+    /// Merges the frames by procedures, the innermost first
+    fn stack(&self) -> Vec<StackFrame> {
+        let mut stack = vec![];
+        let mut current: Option<Position> = None;
+        for frame in self.frames.iter().rev() {
+            if current.is_none() {
+                current = frame.position.clone();
+            }
+            if frame.procedure.is_some() {
+                if let Some((expr, scope)) = current.take() {
+                    let procedure = frame.procedure.clone();
+                    stack.push(StackFrame { procedure, expr, scope });
+                }
+                current = frame.call_site.clone();
+            }
+        }
+        if let Some((expr, scope)) = current {
+            stack.push(StackFrame { procedure: None, expr, scope });
+        }
+        stack
+    }
+}
+
+fn expr_location(expr: &Rc<Object>) -> Option<Location> {
+    match expr.as_ref() {
+        Object::Pair(_, tail) => source::location(tail),
+        _ => None,
+    }
+}
+
+/// Attaches the hook to the evaluations of the current thread replacing the previous one.
+pub fn attach<H: DebugHook + 'static>(hook: H) {
+    DEBUGGER.with(|d| {
+        let mut d = d.borrow_mut();
+        d.hook = Some(Box::new(hook));
+        d.attached = true;
+        ACTIVE.with(|a| a.set(!d.paused));
+    });
+}
+
+/// Detaches the hook, breakpoints are kept.
+pub fn detach() {
+    DEBUGGER.with(|d| {
+        let mut d = d.borrow_mut();
+        d.hook = None;
+        d.attached = false;
+        d.mode = Mode::Run;
+    });
+    ACTIVE.with(|a| a.set(false));
+}
+
+pub fn is_attached() -> bool {
+    DEBUGGER.with(|d| d.borrow().attached)
+}
+
+/// Adds the breakpoint and returns its id.
+pub fn add_breakpoint(breakpoint: Breakpoint) -> usize {
+    DEBUGGER.with(|d| {
+        let mut d = d.borrow_mut();
+        d.last_id += 1;
+        let id = d.last_id;
+        d.breakpoints.push((id, breakpoint));
+        id
+    })
+}
+
+/// Removes the breakpoint, returns `false` if there is no breakpoint with the id.
+pub fn remove_breakpoint(id: usize) -> bool {
+    DEBUGGER.with(|d| {
+        let breakpoints = &mut d.borrow_mut().breakpoints;
+        let count = breakpoints.len();
+        breakpoints.retain(|(i, _)| *i != id);
+        breakpoints.len() != count
+    })
+}
+
+pub fn clear_breakpoints() {
+    DEBUGGER.with(|d| d.borrow_mut().breakpoints.clear());
+}
+
+/// Breakpoints with their ids in the order they were added.
+pub fn breakpoints() -> Vec<(usize, Breakpoint)> {
+    DEBUGGER.with(|d| d.borrow().breakpoints.clone())
+}
+
+/// Makes the evaluation pause at its next step.
+pub fn step_in() {
+    DEBUGGER.with(|d| d.borrow_mut().mode = Mode::StepIn);
+}
+
+/// Cancels the step requested when the evaluation was resumed last time,
+/// the evaluation runs until a breakpoint.
+pub fn cancel_step() {
+    DEBUGGER.with(|d| d.borrow_mut().mode = Mode::Run);
+}
+
+impl Breakpoint {
+    /// Reads a breakpoint written as `name`, `line` or `file:line`.
+    pub fn parse(target: &str) -> Self {
+        let (file, line) = match target.rfind(':') {
+            Some(i) if i > 0 => (Some(&target[..i]), &target[i + 1..]),
+            _ => (None, target),
+        };
+        match line.parse() {
+            Ok(line) => Breakpoint::Line { file: file.map(str::to_string), line },
+            Err(_) => Breakpoint::Procedure(target.to_string()),
+        }
+    }
+
+    fn is_line(&self) -> bool {
+        matches!(self, Breakpoint::Line { .. })
+    }
+
+    fn matches(&self, location: &Location) -> bool {
+        match self {
+            Breakpoint::Line { file, line } if *line == location.line => {
+                match (file, &location.file) {
+                    (None, _) => true,
+                    (Some(file), Some(path)) => Path::new(path.as_ref()).ends_with(file),
+                    (Some(_), None) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl StackFrame {
+    /// Where the expression is written if it was read by the parser
+    pub fn location(&self) -> Option<Location> {
+        expr_location(&self.expr)
+    }
+
+    /// Scopes visible in the frame from the innermost one to the global one.
+    pub fn scopes(&self) -> Vec<Rc<Scope>> {
+        let mut scopes = vec![self.scope.clone()];
+        while let Some(parent) = scopes.last().unwrap().parent() {
+            scopes.push(parent.clone());
+        }
+        scopes
+    }
+
+    /// Evaluates the expressions from the string in the scope of the frame
+    /// and returns the value of the last one.
+    pub fn eval(&self, source: &str) -> Result<Rc<Object>, Box<dyn Error>> {
+        let mut result = Rc::new(Object::Nil);
+        for obj in parse_expression(source)? {
+            result = eval(&Rc::new(obj), &self.scope)?;
+        }
+        Ok(result)
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Procedure(name) => write!(f, "{}", name),
+            Breakpoint::Line { file: Some(file), line } => write!(f, "{}:{}", file, line),
+            Breakpoint::Line { file: None, line } => write!(f, "line {}", line),
+        }
+    }
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.procedure.as_deref().unwrap_or("top level"))?;
+        if let Some(location) = self.location() {
+            write!(f, " at {}", location)?;
+        }
+        write!(f, ": {}", self.expr)
+    }
+}
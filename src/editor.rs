@@ -0,0 +1,110 @@
+//! Interactive front-end of the REPL with line editing.
+//!
+//! Provides history that is kept between sessions, completion of names visible
+//! in the interpreter's scope and highlighting of matching parentheses.
+//! Ctrl-C interrupts the running evaluation or discards the unfinished input.
+
+use crate::eval::SPECIAL_FORMS;
+use crate::repl::Repl;
+use crate::scope::Scope;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const HISTORY_FILE: &str = ".scheme_history";
+
+/// Characters that can't be a part of a symbol
+const DELIMITERS: &str = "()'\" \t\n";
+
+struct SchemeHelper {
+    scope: Rc<Scope>,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl Completer for SchemeHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c| DELIMITERS.contains(c))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let mut names = self.scope.names();
+        names.extend(SPECIAL_FORMS.iter().map(|s| s.to_string()));
+        names.retain(|name| name.starts_with(prefix));
+        names.sort();
+        names.dedup();
+        Ok((start, names))
+    }
+}
+
+impl Highlighter for SchemeHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Hinter for SchemeHelper {
+    type Hint = String;
+}
+
+impl Validator for SchemeHelper {}
+
+impl Helper for SchemeHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Runs the REPL reading the input from the terminal.
+pub fn run(repl: &mut Repl) -> rustyline::Result<()> {
+    let mut editor = Editor::new()?;
+    editor.set_helper(Some(SchemeHelper {
+        scope: repl.interpreter().scope().clone(),
+        brackets: MatchingBracketHighlighter::new(),
+    }));
+    let history = history_path();
+    if let Some(path) = &history {
+        // the file doesn't exist at the first run
+        let _ = editor.load_history(path);
+    }
+    // The terminal doesn't send SIGINT while a line is edited,
+    // so the handler is invoked only when an evaluation is running.
+    let handle = repl.interpreter().interrupt_handle();
+    let _ = ctrlc::set_handler(move || handle.interrupt());
+
+    loop {
+        match editor.readline(repl.prompt()) {
+            Ok(line) => {
+                editor.add_history_entry(line.as_str())?;
+                for result in repl.feed(&line) {
+                    println!("{}", result);
+                }
+                if repl.exit_code().is_some() {
+                    break;
+                }
+            }
+            Err(ReadlineError::Interrupted) => repl.reset(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
@@ -37,6 +37,27 @@ pub enum EvalErr {
     CondNeedsClause(),
     CondEmptyClause(),
     EmptyFunctionBody(),
+    TypeMismatch(String, String),
+    LimitExceeded(&'static str, u64),
+    IoError(String),
+    Interrupted(),
+    LoadError(String, String),
+    Exit(i32),
+}
+
+impl ParseErr {
+    /// Checks if the error is caused by the end of input in the middle of an expression,
+    /// so the source may become correct when more input is added.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseErr::UnclosedString
+                | ParseErr::Unexpected_EOF
+                | ParseErr::Unexpected_EOF_AfterPars
+                | ParseErr::Unexpected_EOF_AfterDot
+                | ParseErr::ClosingParExpected_EOF
+        )
+    }
 }
 
 impl Error for EvalErr {}
@@ -112,7 +133,19 @@ impl Display for EvalErr {
             EvalErr::CondEmptyClause() =>
                 write!(f, "Empty clause for 'cond'"),
             EvalErr::EmptyFunctionBody() =>
-                write!(f, "Empty function body")
+                write!(f, "Empty function body"),
+            EvalErr::TypeMismatch(expected, obj) =>
+                write!(f, "{} expected, got {}", expected, obj),
+            EvalErr::LimitExceeded(resource, limit) =>
+                write!(f, "Evaluation exceeded the limit of {} {}", limit, resource),
+            EvalErr::IoError(msg) =>
+                write!(f, "I/O error: {}", msg),
+            EvalErr::Interrupted() =>
+                write!(f, "Evaluation interrupted"),
+            EvalErr::LoadError(file, reason) =>
+                write!(f, "Cannot load '{}': {}", file, reason),
+            EvalErr::Exit(code) =>
+                write!(f, "Exit with code {}", code)
         }
     }
 }
@@ -1,12 +1,36 @@
+use crate::context;
+use crate::convert::FromScheme;
+use crate::debugger;
 use crate::errors::EvalErr;
 use crate::functions::*;
 use crate::logic::*;
 use crate::object::*;
+use crate::parser::parse_source;
+use crate::profiler::Activation;
 use crate::scope::*;
 use crate::service::*;
+use crate::source;
+use crate::symbol::Symbol;
 
 use std::rc::Rc;
 
+/// Names of the syntactic forms recognized by `eval` itself
+pub const SPECIAL_FORMS: &[&str] = &[
+    "quote", "if", "let", "let*", "letrec", "begin", "define", "lambda", "and", "or", "cond",
+    "apply", "load",
+];
+
+/// Checks the form of a `let` binding and returns its variable and initial expression
+pub(crate) fn let_binding(binding: &Object) -> Result<(Symbol, Rc<Object>), EvalErr> {
+    match list_to_vec(binding)?.as_slice() {
+        [var, init, ..] => match var.as_ref() {
+            Object::Symbol(s) => Ok((*s, init.clone())),
+            var => Err(EvalErr::LetNeedSymbolForBinding(var.to_string())),
+        },
+        _ => Err(EvalErr::LetNeedListForBinding(binding.to_string())),
+    }
+}
+
 fn fn_let(let_args: List, scope: &Rc<Scope>, star: bool, rec: bool) -> Result<CallResult, EvalErr> {
     if let_args.len() < 2 {
         return Err(EvalErr::NeedAtLeastArgs(
@@ -17,28 +41,19 @@ fn fn_let(let_args: List, scope: &Rc<Scope>, star: bool, rec: bool) -> Result<Ca
     }
     let root_scope = Rc::new(Scope::from_scope(scope));
     let mut init_scope = root_scope.clone();
-    let args = list_to_vec(let_args.get(0).unwrap())?;
+    let args = list_to_vec(let_args.first().unwrap())?;
     let mut bindings = vec![];
     for arg in args {
-        let init_expr = list_to_vec(arg.as_ref())?;
-        if init_expr.len() >= 2 {
-            let var = init_expr.get(0).unwrap().as_ref();
-            if let Object::Symbol(s) = var {
-                let value = eval(init_expr.get(1).unwrap(), &init_scope)?;
-                if star {
-                    init_scope = Rc::new(Scope::from_scope(&init_scope));
-                    init_scope.bind(s, value.clone());
-                }
-                if rec {
-                    root_scope.bind(s, value.clone());
-                }
-                bindings.push((s.to_string(), value));
-            } else {
-                return Err(EvalErr::LetNeedSymbolForBinding(var.to_string()));
-            }
-        } else {
-            return Err(EvalErr::LetNeedListForBinding(arg.to_string()));
+        let (s, init) = let_binding(&arg)?;
+        let value = eval(&init, &init_scope)?;
+        if star {
+            init_scope = Rc::new(Scope::from_scope(&init_scope));
+            init_scope.bind(s, value.clone());
         }
+        if rec {
+            root_scope.bind(s, value.clone());
+        }
+        bindings.push((s, value));
     }
     fn_begin(
         &let_args[1..],
@@ -62,7 +77,8 @@ pub fn fn_begin(args: &[Rc<Object>], scope: &Rc<Scope>) -> Result<CallResult, Ev
 /// `(define id expr)` and `(define (id args...) expr...)`
 ///
 /// Both syntax handled by this function. Defined value added to a current scope
-fn fn_define(args: List, scope: &Rc<Scope>) -> Result<(), EvalErr> {
+/// `tail` is the rest of the form, it gives the location of the definition, see the `source` module.
+fn fn_define(args: List, scope: &Rc<Scope>, tail: &Rc<Object>) -> Result<(), EvalErr> {
     let head = &args[0];
     match head.as_ref() {
         // (define x expr)
@@ -81,10 +97,11 @@ fn fn_define(args: List, scope: &Rc<Scope>) -> Result<(), EvalErr> {
                 scope.bind(
                     s,
                     Rc::new(Function::new(
-                        s.clone(),
+                        s.to_string(),
                         Rc::clone(fun_args),
                         args[1..].to_vec(),
                         Rc::clone(scope),
+                        source::location(tail),
                     )?),
                 );
             } else {
@@ -97,7 +114,7 @@ fn fn_define(args: List, scope: &Rc<Scope>) -> Result<(), EvalErr> {
 }
 
 #[inline]
-fn lambda(args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
+fn lambda(args: List, scope: &Rc<Scope>, tail: &Rc<Object>) -> Result<CallResult, EvalErr> {
     if args.len() < 2 {
         return Err(EvalErr::TooFewArguments("lambda".to_string()));
     }
@@ -109,32 +126,75 @@ fn lambda(args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
         Rc::clone(&args[0]),
         args[1..].to_vec(),
         Rc::clone(scope),
+        source::location(tail),
     )?)))
 }
 
+/// Reads expressions from the file given to `load`.
+/// Relative names are also searched in the library paths of the interpreter.
+/// A sandboxed interpreter behaves as if `load` is not defined.
+pub fn read_loaded_file(name: &Rc<Object>) -> Result<Vec<Object>, EvalErr> {
+    if context::is_sandboxed() {
+        return Err(EvalErr::UnboundVariable("load".to_string()));
+    }
+    let name = String::from_scheme(name)?;
+    let error = |reason: String| EvalErr::LoadError(name.clone(), reason);
+    let path = context::find_file(&name);
+    let src = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    parse_source(&src, Some(&name)).map_err(|e| error(e.to_string()))
+}
+
+/// `(load "file")` evaluates all expressions from the file in the current scope.
+fn fn_load(args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
+    let name = eval(&expect_1_arg(args, "load")?, scope)?;
+    for expr in read_loaded_file(&name)? {
+        eval(&Rc::new(expr), scope)?;
+    }
+    Ok(CallResult::Object(undef()))
+}
+
+/// Names like `cadr` are recognized as compositions of `car` and `cdr` and can't be redefined
+pub fn is_cxr(s: &str) -> bool {
+    (s.starts_with('c') && s.ends_with('r'))
+        && (s.len() >= 3 && s.len() <= 6)
+        && s[1..s.len() - 1].chars().all(|c| c == 'a' || c == 'd')
+}
+
+#[inline]
 pub fn eval(obj: &Rc<Object>, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr> {
+    eval_tail(obj, scope, Activation::default())
+}
+
+/// Evaluates the expression in the tail position of the procedure entered by the activation,
+/// so calls in the tail position replace the procedure in the profile.
+pub(crate) fn eval_tail(
+    obj: &Rc<Object>, scope: &Rc<Scope>, mut activation: Activation,
+) -> Result<Rc<Object>, EvalErr> {
+    let _depth = context::enter_eval()?;
+    let frame = debugger::enter_frame();
     let mut obj = obj.clone();
     let mut scope = scope.clone();
-    loop {
+    let value = loop {
+        context::tick()?;
+        if frame.is_tracked() {
+            debugger::step(&obj, &scope)?;
+        }
         match obj.as_ref() {
             // resolve a symbol
             Object::Symbol(s) => {
-                return if (s.starts_with('c') && s.ends_with('r'))
-                    && (s.len() >= 3 && s.len() <= 6)
-                    && s[1..s.len() - 1].chars().all(|c| c == 'a' || c == 'd')
-                {
-                    Ok(Rc::new(Object::Function(Function::Dynamic(s.clone()))))
+                break if is_cxr(s.as_str()) {
+                    Rc::new(Object::from(Function::Dynamic(s.to_string())))
                 } else {
-                    (scope.get(s)).ok_or_else(|| EvalErr::UnboundVariable(s.to_string()))
+                    (scope.get(s)).ok_or_else(|| EvalErr::UnboundVariable(s.to_string()))?
                 };
             }
             // invoke a function
-            Object::Pair(func, args) => {
-                if let Ok(args) = list_to_vec(args) {
-                    let result = invoke(func, args, &scope)?;
+            Object::Pair(func, tail) => {
+                if let Ok(args) = list_to_vec(tail) {
+                    let result = invoke(func, tail, args, &scope, &mut activation)?;
                     match result {
                         CallResult::Object(obj) => {
-                            return Ok(obj);
+                            break obj;
                         }
                         CallResult::TailCall(obj2, scope2) => {
                             obj = obj2;
@@ -142,52 +202,73 @@ pub fn eval(obj: &Rc<Object>, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr>
                         }
                     }
                 } else {
-                    return Err(EvalErr::ListRequired(args.to_string()));
+                    return Err(EvalErr::ListRequired(tail.to_string()));
                 }
             }
             // other values evaluates to itself
-            _ => return Ok(obj),
+            _ => break obj,
         }
-    }
+    };
+    activation.returned(&value)?;
+    Ok(value)
 }
 
-fn invoke(obj: &Rc<Object>, args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
-    // special forms
+/// Evaluates the form with the head `obj`, `tail` is the rest of the form and `args` are its elements
+fn invoke(
+    obj: &Rc<Object>, tail: &Rc<Object>, args: List, scope: &Rc<Scope>, activation: &mut Activation,
+) -> Result<CallResult, EvalErr> {
     if let Object::Symbol(s) = obj.as_ref() {
-        if s == "quote" {
-            return Ok(CallResult::Object(expect_1_arg(args, "quote")?));
-        } else if s == "if" {
-            return fn_if(args, scope);
-        } else if s == "let" {
-            return fn_let(args, scope, false, false);
-        } else if s == "let*" {
-            return fn_let(args, scope, true, false);
-        } else if s == "letrec" {
-            return fn_let(args, scope, false, true);
-        } else if s == "begin" {
-            return fn_begin(args.as_slice(), scope);
-        } else if s == "define" {
-            fn_define(args, scope)?;
-            return Ok(CallResult::Object(Rc::new(Object::Nil)));
-        } else if s == "lambda" {
-            return lambda(args, scope);
-        } else if s == "and" {
-            return logic_and(args, scope);
-        } else if s == "or" {
-            return logic_or(args, scope);
-        } else if s == "cond" {
-            return cond(args, scope);
-        } else if s == "apply" {
-            return fn_apply(eval_args(args, scope)?);
+        if SPECIAL_FORMS.contains(&s.as_str()) {
+            return special_form(s.as_str(), tail, args, scope, activation);
         }
     }
     if let Object::Function(fun) = eval(obj, scope)?.as_ref() {
-        fun.call(eval_args(args, scope)?)
+        call(fun, eval_args(args, scope)?, activation)
     } else {
         Err(EvalErr::IllegalObjectAsAFunction(obj.to_string()))
     }
 }
 
+/// Special forms are evaluated by a separate function to keep the frame of `invoke` small,
+/// it stays on the Rust stack while arguments of calls are evaluated.
+fn special_form(
+    form: &str, tail: &Rc<Object>, args: List, scope: &Rc<Scope>, activation: &mut Activation,
+) -> Result<CallResult, EvalErr> {
+    match form {
+        "quote" => Ok(CallResult::Object(expect_1_arg(args, "quote")?)),
+        "if" => fn_if(args, scope),
+        "let" => fn_let(args, scope, false, false),
+        "let*" => fn_let(args, scope, true, false),
+        "letrec" => fn_let(args, scope, false, true),
+        "begin" => fn_begin(args.as_slice(), scope),
+        "define" => {
+            fn_define(args, scope, tail)?;
+            Ok(CallResult::Object(Rc::new(Object::Nil)))
+        }
+        "lambda" => lambda(args, scope, tail),
+        "and" => logic_and(args, scope),
+        "or" => logic_or(args, scope),
+        "cond" => cond(args, scope),
+        "apply" => {
+            let (func, args) = apply_args(eval_args(args, scope)?)?;
+            match func.as_ref() {
+                Object::Function(fun) => call(fun, args, activation),
+                _ => unreachable!(),
+            }
+        }
+        "load" => fn_load(args, scope),
+        _ => unreachable!("unknown special form {}", form),
+    }
+}
+
+/// Calls the function, a procedure whose body continues in the loop of `eval` replaces
+/// the one entered by the activation.
+#[inline]
+fn call(fun: &Function, args: List, activation: &mut Activation) -> Result<CallResult, EvalErr> {
+    fun.enter(&args, activation)?;
+    fun.call(args)
+}
+
 #[inline]
 fn eval_args(args: List, scope: &Rc<Scope>) -> Result<List, EvalErr> {
     let mut result = Vec::new();
@@ -0,0 +1,206 @@
+//! Expansion of derived expression types into more primitive forms.
+//!
+//! The evaluator handles derived forms directly so the expansion is not used for evaluation.
+//! It shows how the forms could be defined as macros and serves as a reference for their semantics.
+
+use crate::errors::EvalErr;
+use crate::eval::let_binding;
+use crate::object::Object;
+use crate::service::{list_to_vec, vec_to_list};
+use crate::symbol::Symbol;
+
+use std::rc::Rc;
+
+fn symbol(s: &str) -> Rc<Object> {
+    Rc::new(Object::from(Symbol::intern(s)))
+}
+
+/// Makes a list with a symbol at the head
+fn form(head: &str, items: &[Rc<Object>]) -> Rc<Object> {
+    Rc::new(Object::cons(symbol(head), Rc::new(vec_to_list(items))))
+}
+
+/// `lambda` takes a single expression, a body of several ones is wrapped in `begin`
+fn lambda(params: Rc<Object>, body: &[Rc<Object>]) -> Rc<Object> {
+    match body {
+        [expr] => form("lambda", &[params, expr.clone()]),
+        _ => form("lambda", &[params, form("begin", body)]),
+    }
+}
+
+/// `(let ((v e) ...) body ...)` => `((lambda (v ...) body ...) e ...)`
+fn expand_let(args: &[Rc<Object>]) -> Result<Rc<Object>, EvalErr> {
+    let mut vars = Vec::new();
+    let mut inits = Vec::new();
+    for binding in list_to_vec(&args[0])? {
+        let (var, init) = let_binding(&binding)?;
+        vars.push(Rc::new(Object::from(var)));
+        inits.push(init);
+    }
+    let mut call = vec![lambda(Rc::new(vec_to_list(&vars)), &args[1..])];
+    call.extend(inits);
+    Ok(Rc::new(vec_to_list(&call)))
+}
+
+/// `(let* (b1 b2 ...) body ...)` => `(let (b1) (let* (b2 ...) body ...))`
+fn expand_let_star(args: &[Rc<Object>]) -> Result<Rc<Object>, EvalErr> {
+    let bindings = list_to_vec(&args[0])?;
+    if bindings.len() <= 1 {
+        return Ok(form("let", args));
+    }
+    let mut inner = vec![Rc::new(vec_to_list(&bindings[1..]))];
+    inner.extend_from_slice(&args[1..]);
+    let outer = vec![Rc::new(vec_to_list(&bindings[..1])), form("let*", &inner)];
+    Ok(form("let", &outer))
+}
+
+/// `(letrec ((v e) ...) body ...)` => `(let () (define v e) ... body ...)`
+fn expand_letrec(args: &[Rc<Object>]) -> Result<Rc<Object>, EvalErr> {
+    let mut body = vec![Rc::new(Object::Nil)];
+    for binding in list_to_vec(&args[0])? {
+        let (var, init) = let_binding(&binding)?;
+        body.push(form("define", &[Rc::new(Object::from(var)), init]));
+    }
+    body.extend_from_slice(&args[1..]);
+    Ok(form("let", &body))
+}
+
+/// `(let ((t test)) (if t t alternative))`, the temporary can't capture a variable
+/// of the alternative because it is uninterned
+fn test_or(test: &Rc<Object>, alternative: Rc<Object>) -> Rc<Object> {
+    let temp = Rc::new(Object::from(Symbol::uninterned("t")));
+    let binding = Rc::new(vec_to_list(&[temp.clone(), test.clone()]));
+    let body = form("if", &[temp.clone(), temp, alternative]);
+    form("let", &[Rc::new(vec_to_list(&[binding])), body])
+}
+
+/// `(cond (test body ...) clause ...)` => `(if test (begin body ...) (cond clause ...))`,
+/// a clause without a body is expanded like `or`, `(begin)` is the value if no clause is true
+fn expand_cond(args: &[Rc<Object>]) -> Result<Rc<Object>, EvalErr> {
+    if args.is_empty() {
+        return Err(EvalErr::CondNeedsClause());
+    }
+    let clause = list_to_vec(&args[0])?;
+    let rest = if args.len() > 1 {
+        form("cond", &args[1..])
+    } else {
+        form("begin", &[])
+    };
+    match clause.as_slice() {
+        [] => Err(EvalErr::CondEmptyClause()),
+        [test, body @ ..] if test.is_symbol("else") => Ok(form("begin", body)),
+        [test] => Ok(test_or(test, rest)),
+        [test, body @ ..] => Ok(form("if", &[test.clone(), form("begin", body), rest])),
+    }
+}
+
+/// `(and a b ...)` => `(if a (and b ...) #f)`
+fn expand_and(args: &[Rc<Object>]) -> Rc<Object> {
+    match args {
+        [] => Rc::new(Object::from(true)),
+        [x] => x.clone(),
+        [x, rest @ ..] => form(
+            "if",
+            &[x.clone(), form("and", rest), Rc::new(Object::from(false))],
+        ),
+    }
+}
+
+/// `(or a b ...)` => `(let ((t a)) (if t t (or b ...)))`
+fn expand_or(args: &[Rc<Object>]) -> Rc<Object> {
+    match args {
+        [] => Rc::new(Object::from(false)),
+        [x] => x.clone(),
+        [x, rest @ ..] => test_or(x, form("or", rest)),
+    }
+}
+
+/// `(define (name . args) body ...)` => `(define name (lambda args body ...))`
+fn expand_define(args: &[Rc<Object>]) -> Option<Rc<Object>> {
+    match args.first().map(|x| x.as_ref()) {
+        Some(Object::Pair(name, params)) if args.len() > 1 => Some(form(
+            "define",
+            &[name.clone(), lambda(params.clone(), &args[1..])],
+        )),
+        _ => None,
+    }
+}
+
+/// Performs one step of expansion of the expression if it is a derived form.
+/// Returns `None` if the expression should be left as it is.
+pub fn expand(expr: &Rc<Object>) -> Result<Option<Rc<Object>>, EvalErr> {
+    let (name, args) = match expr.as_ref() {
+        Object::Pair(head, tail) => match head.as_ref() {
+            Object::Symbol(s) => (s.as_str(), list_to_vec(tail)?),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let need_bindings = matches!(name, "let" | "let*" | "letrec");
+    if need_bindings && args.len() < 2 {
+        return Err(EvalErr::NeedAtLeastArgs(name.to_string(), 2, args.len()));
+    }
+    Ok(match name {
+        "let" => Some(expand_let(&args)?),
+        "let*" => Some(expand_let_star(&args)?),
+        "letrec" => Some(expand_letrec(&args)?),
+        "cond" => Some(expand_cond(&args)?),
+        "and" => Some(expand_and(&args)),
+        "or" => Some(expand_or(&args)),
+        "define" => expand_define(&args),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+#[rustfmt::skip]
+mod tests {
+    use super::*;
+    use crate::eval::eval;
+    use crate::parser::parse_expression;
+    use crate::scope::Scope;
+
+    fn assert_expand(source: &str, expected: &str) {
+        let expr = Rc::new(parse_expression(source).unwrap().pop().unwrap());
+        let result = expand(&expr).unwrap().map_or("#f".to_string(), |obj| obj.to_string());
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_expand("(let ((x 1) (y 2)) (+ x y))", "((lambda (x y) (+ x y)) 1 2)");
+        assert_expand("(let* ((x 1) (y x)) y)", "(let ((x 1)) (let* ((y x)) y))");
+        assert_expand("(let* ((x 1)) x)", "(let ((x 1)) x)");
+        assert_expand("(letrec ((f (lambda () f))) (f))", "(let () (define f (lambda () f)) (f))");
+        assert_expand("(cond ((> x 0) 'pos) ((< x 0) 'neg))", "(if (> x 0) (begin (quote pos)) (cond ((< x 0) (quote neg))))");
+        assert_expand("(cond (x) (else y))", "(let ((t x)) (if t t (cond (else y))))");
+        assert_expand("(cond (else 1 2))", "(begin 1 2)");
+        assert_expand("(cond (x 1))", "(if x (begin 1) (begin))");
+        assert_expand("(and a b c)", "(if a (and b c) #f)");
+        assert_expand("(or a b)", "(let ((t a)) (if t t (or b)))");
+        assert_expand("(let ((x 1)) (display x) x)", "((lambda (x) (begin (display x) x)) 1)");
+        assert_expand("(define (f x . y) x y)", "(define f (lambda (x . y) (begin x y)))");
+        assert_expand("(define (f) 1)", "(define f (lambda () 1))");
+        assert_expand("(define x 1)", "#f");
+        assert_expand("(f x)", "#f");
+    }
+
+    #[test]
+    fn test_expansion_body() {
+        let scope = Rc::new(Scope::from_global());
+        let source = "(define (f x) (define y (* x 2)) (+ x y))";
+        let expr = Rc::new(parse_expression(source).unwrap().pop().unwrap());
+        eval(&expand(&expr).unwrap().unwrap(), &scope).unwrap();
+        let expr = Rc::new(parse_expression("(let ((x 1)) (f x) (f 2))").unwrap().pop().unwrap());
+        assert_eq!(eval(&expand(&expr).unwrap().unwrap(), &scope).unwrap().to_string(), "6");
+    }
+
+    #[test]
+    fn test_expansion_temporary() {
+        let scope = Rc::new(Scope::from_global());
+        scope.bind("t", Rc::new(Object::make_int(5)));
+        let expr = Rc::new(parse_expression("(or #f t)").unwrap().pop().unwrap());
+        let expanded = expand(&expr).unwrap().unwrap();
+        assert_eq!(eval(&expanded, &scope).unwrap().to_string(), "5");
+    }
+}
@@ -0,0 +1,96 @@
+//! Opaque host objects.
+//!
+//! A foreign object wraps an arbitrary Rust value so it can be passed through Scheme code
+//! to the native functions that know how to handle it. Scheme code can only print such objects,
+//! compare them by identity and check their type with predicates made by `predicate`.
+
+use crate::errors::EvalErr;
+use crate::functions::Function;
+use crate::object::{List, Object};
+use crate::service::expect_1_arg;
+
+use std::any::{type_name, Any};
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct Foreign {
+    name: String,
+    value: Rc<dyn Any>,
+}
+
+impl Foreign {
+    /// Wraps a value, the name of its Rust type is used for printing.
+    pub fn new<T: Any>(value: T) -> Self {
+        Self::with_name(type_name::<T>(), value)
+    }
+
+    pub fn with_name<T: Any>(name: &str, value: T) -> Self {
+        Foreign { name: name.to_string(), value: Rc::new(value) }
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        self.value.clone().downcast::<T>().ok()
+    }
+}
+
+/// Foreign objects are equal only if they wrap the same value
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            Rc::as_ptr(&self.value) as *const (),
+            Rc::as_ptr(&other.value) as *const (),
+        )
+    }
+}
+
+impl Object {
+    pub fn make_foreign<T: Any>(value: T) -> Object {
+        Object::from(Foreign::new(value))
+    }
+}
+
+impl From<Foreign> for Object {
+    fn from(value: Foreign) -> Self {
+        Object::Foreign(value).counted()
+    }
+}
+
+/// Extracts a value of the given type from a foreign object.
+/// Intended for native functions that take foreign objects as arguments.
+pub fn downcast<T: Any>(obj: &Object) -> Result<Rc<T>, EvalErr> {
+    match obj {
+        Object::Foreign(f) => f.downcast::<T>(),
+        _ => None,
+    }
+    .ok_or_else(|| EvalErr::TypeMismatch(type_name::<T>().to_string(), obj.to_string()))
+}
+
+fn is_foreign<T: Any>(args: List) -> Result<Rc<Object>, EvalErr> {
+    let arg = expect_1_arg(args, type_name::<T>())?;
+    let result = matches!(arg.as_ref(), Object::Foreign(f) if f.is::<T>());
+    Ok(Rc::new(Object::from(result)))
+}
+
+/// Makes a function that checks if its argument is a foreign object of type `T`.
+///
+/// ```
+/// # use scheme::foreign::predicate;
+/// # use scheme::object::Object;
+/// # use scheme::interpreter::Interpreter;
+/// struct Socket;
+/// let interpreter = Interpreter::builder().no_prelude().build().unwrap();
+/// interpreter.set_global("socket?", predicate::<Socket>());
+/// interpreter.set_global("sock", Object::make_foreign(Socket));
+/// assert_eq!(interpreter.eval_str("(list (socket? sock) (socket? 1))").unwrap().to_string(), "(#t #f)");
+/// ```
+pub fn predicate<T: Any>() -> Object {
+    Function::from_pointer(is_foreign::<T>)
+}
@@ -1,10 +1,17 @@
+use crate::analyze;
+use crate::debugger;
 use crate::errors::EvalErr;
-use crate::eval::{eval, fn_begin};
+use crate::eval::{eval_tail, fn_begin};
+use crate::gc;
 use crate::object::{List, Object};
+use crate::profiler::Activation;
 use crate::scope::Scope;
 use crate::service::{list_to_vec, vec_to_list};
+use crate::source::Location;
+use crate::vm::{self, Closure};
 
 use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
 type RustFn = fn(List) -> Result<Rc<Object>, EvalErr>;
@@ -12,7 +19,87 @@ type RustFn = fn(List) -> Result<Rc<Object>, EvalErr>;
 pub enum Function {
     Dynamic(String),
     Pointer(RustFn),
-    Object { name: String, args: Rc<Object>, body: List, scope: Rc<Scope> },
+    /// A function of the global scope implemented in Rust
+    Builtin(&'static Builtin),
+    Object {
+        name: String,
+        args: Rc<Object>,
+        body: Body,
+        scope: Rc<Scope>,
+        /// Where the function is defined if it is known
+        location: Option<Location>,
+    },
+    /// A function compiled to bytecode
+    Compiled(Closure),
+}
+
+/// The numbers of arguments a procedure accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` if the number of arguments is not limited
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    /// The arity of a procedure with the parameters
+    pub(crate) fn of(mut params: &Object) -> Arity {
+        let mut min = 0;
+        while let Object::Pair(_, tail) = params {
+            min += 1;
+            params = tail;
+        }
+        let max = if params.is_nil() { Some(min) } else { None };
+        Arity { min, max }
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min && self.max.is_none_or(|max| n <= max)
+    }
+
+    /// Returns the error for a call of the named procedure with `n` arguments if it doesn't accept them
+    fn check(&self, name: &str, n: usize) -> Result<(), EvalErr> {
+        match self.max {
+            _ if self.accepts(n) => Ok(()),
+            Some(max) if max == self.min => Err(EvalErr::WrongAgrsNum(name.to_string(), max, n)),
+            _ if n < self.min => Err(EvalErr::NeedAtLeastArgs(name.to_string(), self.min, n)),
+            _ => Err(EvalErr::TooManyArguments(name.to_string())),
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// A built-in function with its name and arity. The arguments are checked against the arity
+/// before the function is called, so the function itself doesn't check their number.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    func: RustFn,
+}
+
+impl Builtin {
+    pub(crate) const fn new(
+        name: &'static str, func: RustFn, min: usize, max: Option<usize>,
+    ) -> Self {
+        Builtin { name, arity: Arity { min, max }, func }
+    }
+}
+
+/// The body of a function defined in Scheme
+pub enum Body {
+    /// Expressions evaluated by `eval`
+    Source(List),
+    /// A lambda analyzed by `analyze`, the scope of the function is the global one
+    Analyzed(analyze::Closure),
 }
 
 pub enum CallResult {
@@ -27,12 +114,54 @@ impl Function {
 
             Function::Pointer(f) => Ok(CallResult::Object(f(call_args)?)),
 
-            Function::Object { name, args: formal_args, body, scope } => {
+            Function::Builtin(builtin) => {
+                builtin.arity.check(builtin.name, call_args.len())?;
+                Ok(CallResult::Object((builtin.func)(call_args)?))
+            }
+
+            Function::Compiled(closure) => Ok(CallResult::Object(vm::call(closure, call_args)?)),
+
+            Function::Object {
+                name, args: formal_args, body: Body::Source(body), scope, ..
+            } => {
                 let scope = &Rc::new(Scope::from_scope(scope));
                 Function::bind_args(name, call_args, formal_args, scope)?;
                 fn_begin(body, scope)
             }
+
+            Function::Object { body: Body::Analyzed(closure), scope, .. } => {
+                let value = analyze::call(closure, scope, call_args)?;
+                Ok(CallResult::Object(value))
+            }
+        }
+    }
+
+    /// Calls the function and evaluates the tail call if it is returned.
+    pub fn invoke(&self, call_args: List) -> Result<Rc<Object>, EvalErr> {
+        let _frame = debugger::enter_frame();
+        let mut activation = Activation::default();
+        self.enter(&call_args, &mut activation)?;
+        match self.call(call_args)? {
+            CallResult::Object(obj) => {
+                activation.returned(&obj)?;
+                Ok(obj)
+            }
+            CallResult::TailCall(obj, scope) => eval_tail(&obj, &scope, activation),
+        }
+    }
+
+    /// Enters a function whose body is evaluated by `eval` in the profile and the debugger,
+    /// prints the call if the function is traced.
+    /// Analyzed and compiled functions are entered by their engines.
+    pub(crate) fn enter(
+        &self, args: &[Rc<Object>], activation: &mut Activation,
+    ) -> Result<(), EvalErr> {
+        activation.trace(self, args)?;
+        if let Function::Object { name, location, body: Body::Source(_), .. } = self {
+            activation.enter(name, location.as_ref());
+            debugger::enter_procedure(name);
         }
+        Ok(())
     }
 
     fn bind_args(
@@ -68,7 +197,7 @@ impl Function {
         Ok(())
     }
 
-    fn check_args(mut list: &Rc<Object>) -> Result<(), EvalErr> {
+    pub(crate) fn check_args(mut list: &Rc<Object>) -> Result<(), EvalErr> {
         let mut vec = Vec::new();
         while let Object::Pair(head, tail) = list.as_ref() {
             vec.push(head);
@@ -91,19 +220,21 @@ impl Function {
         Ok(())
     }
 
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(
-        name: String, args: Rc<Object>, body: List, scope: Rc<Scope>,
+        name: String, args: Rc<Object>, body: List, scope: Rc<Scope>, location: Option<Location>,
     ) -> Result<Object, EvalErr> {
         Function::check_args(&args)?;
         if body.is_empty() {
             return Err(EvalErr::EmptyFunctionBody());
         }
-        let func = Function::Object { name, args, body, scope };
-        Ok(Object::Function(func))
+        gc::track_scope(&scope);
+        let func = Function::Object { name, args, body: Body::Source(body), scope, location };
+        Ok(Object::from(func))
     }
 
     pub fn from_pointer(f: RustFn) -> Object {
-        Object::Function(Function::Pointer(f))
+        Object::from(Function::Pointer(f))
     }
 }
 
@@ -111,18 +242,28 @@ impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Function::Dynamic(s1), Function::Dynamic(s2)) => s1 == s2,
-            (Function::Pointer(f1), Function::Pointer(f2)) => f1 == f2,
+            (Function::Pointer(f1), Function::Pointer(f2)) => std::ptr::fn_addr_eq(*f1, *f2),
+            (Function::Builtin(b1), Function::Builtin(b2)) => std::ptr::eq(*b1, *b2),
             _ => std::ptr::eq(self, other),
         }
     }
 }
 
 pub fn fn_apply(vec: List) -> Result<CallResult, EvalErr> {
+    let (func, args) = apply_args(vec)?;
+    match func.as_ref() {
+        Object::Function(fun) => fun.call(args),
+        _ => unreachable!(),
+    }
+}
+
+/// Splits arguments of `apply` to the function and the arguments it should be called with.
+pub fn apply_args(vec: List) -> Result<(Rc<Object>, List), EvalErr> {
     if vec.len() < 2 {
         return Err(EvalErr::NeedAtLeastArgs("apply".to_string(), 2, vec.len()));
     }
     let first = &vec[0];
-    if let Object::Function(fun) = first.as_ref() {
+    if let Object::Function(_) = first.as_ref() {
         // concatenate first arguments with the last one presented as a list
         // e.g. (1 2 3 '(4 5)) => (1 2 3 4 5)
         let last = vec.last().unwrap();
@@ -132,7 +273,7 @@ pub fn fn_apply(vec: List) -> Result<CallResult, EvalErr> {
                 args.push(arg.clone());
             }
             args.extend(last);
-            Ok(fun.call(args)?)
+            Ok((first.clone(), args))
         } else {
             Err(EvalErr::ApplyNeedsProperList(last.to_string()))
         }
@@ -142,9 +283,6 @@ pub fn fn_apply(vec: List) -> Result<CallResult, EvalErr> {
 }
 
 pub fn fn_map(args: List) -> Result<Rc<Object>, EvalErr> {
-    if args.len() < 2 {
-        return Err(EvalErr::NeedAtLeastArgs("map".to_string(), 2, args.len()));
-    }
     let func = &args[0];
     if let Object::Function(f) = func.as_ref() {
         // first check that all arguments are lists of the same size
@@ -152,10 +290,10 @@ pub fn fn_map(args: List) -> Result<Rc<Object>, EvalErr> {
         let mut inputs = Vec::new();
         for arg in args[1..].iter() {
             let vec = list_to_vec(arg)?;
-            if len.is_none() {
-                len = Some(vec.len());
-            } else if len.unwrap() != vec.len() {
-                return Err(EvalErr::UnequalMapLists());
+            match len {
+                None => len = Some(vec.len()),
+                Some(n) if n != vec.len() => return Err(EvalErr::UnequalMapLists()),
+                _ => {}
             }
             inputs.push(vec);
         }
@@ -163,10 +301,7 @@ pub fn fn_map(args: List) -> Result<Rc<Object>, EvalErr> {
         let mut result = Vec::new();
         for i in 0..len.unwrap() {
             let args = inputs.iter().map(|v| v[i].clone()).collect();
-            match f.call(args)? {
-                CallResult::Object(obj) => result.push(obj),
-                CallResult::TailCall(obj, scope) => result.push(eval(&obj, &scope)?),
-            };
+            result.push(f.invoke(args)?);
         }
         Ok(Rc::new(vec_to_list(&result)))
     } else {
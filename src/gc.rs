@@ -0,0 +1,304 @@
+//! The backup cycle collector for closures and environments.
+//!
+//! Objects are reference counted, so an environment holding a closure that was created in it
+//! (every local recursive `define` does that) is never freed. Such cycles always pass through
+//! an environment captured by a closure, so these environments are remembered as candidates.
+//!
+//! The collector finds the scopes, frames, pairs and functions reachable from the candidates
+//! and counts the references among them. An object referenced more times than that is held
+//! from outside: by the interpreter, by Rust code or by the evaluation in progress.
+//! Everything not reachable from such objects is garbage, its bindings are removed
+//! to break the cycles and the reference counting frees the rest.
+//!
+//! The collection runs on `(gc)` and automatically when enough environments were captured
+//! since the last one. The state is per thread, shared by all interpreters of the thread.
+
+use crate::convert::IntoScheme;
+use crate::errors::EvalErr;
+use crate::functions::{Body, Function};
+use crate::lexical::Frame;
+use crate::object::{List, Object};
+use crate::scope::Scope;
+use crate::service::expect_args;
+use crate::symbol::Symbol;
+
+use ahash::RandomState;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Counters of the collector of the current thread
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// The number of collections done
+    pub collections: u64,
+    /// The number of environments freed by all the collections
+    pub freed: u64,
+    /// The number of environments that may be a part of a cycle
+    pub tracked: usize,
+}
+
+/// The number of captured environments that triggers the first automatic collection
+const MIN_THRESHOLD: usize = 10_000;
+
+enum Candidate {
+    Scope(Weak<Scope>),
+    Frame(Weak<Frame>),
+}
+
+struct Collector {
+    candidates: Vec<Candidate>,
+    /// Environments captured since the last collection
+    captured: usize,
+    /// The collection runs when this number of environments are captured,
+    /// it grows with the live part of the graph so the work is proportional to the allocations
+    threshold: usize,
+    stats: GcStats,
+}
+
+thread_local! {
+    static COLLECTOR: RefCell<Collector> = RefCell::new(Collector {
+        candidates: vec![],
+        captured: 0,
+        threshold: MIN_THRESHOLD,
+        stats: GcStats::default(),
+    });
+    static COLLECTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A node of the graph of objects and environments
+pub(crate) enum Node {
+    Object(Rc<Object>),
+    Scope(Rc<Scope>),
+    Frame(Rc<Frame>),
+}
+
+impl Node {
+    pub(crate) fn id(&self) -> *const () {
+        match self {
+            Node::Object(obj) => Rc::as_ptr(obj) as *const (),
+            Node::Scope(scope) => Rc::as_ptr(scope) as *const (),
+            Node::Frame(frame) => Rc::as_ptr(frame) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Object(obj) => Rc::strong_count(obj),
+            Node::Scope(scope) => Rc::strong_count(scope),
+            Node::Frame(frame) => Rc::strong_count(frame),
+        }
+    }
+
+    /// Adds the nodes referenced by this one
+    pub(crate) fn children(&self, out: &mut Vec<Node>) {
+        match self {
+            Node::Object(obj) => match obj.as_ref() {
+                Object::Pair(car, cdr) => {
+                    out.push(Node::Object(car.clone()));
+                    out.push(Node::Object(cdr.clone()));
+                }
+                Object::Function(Function::Object { body, scope, .. }) => {
+                    out.push(Node::Scope(scope.clone()));
+                    if let Body::Analyzed(closure) = body {
+                        out.extend(closure.env().cloned().map(Node::Frame));
+                    }
+                }
+                Object::Function(Function::Compiled(closure)) => {
+                    out.push(Node::Scope(closure.globals().clone()));
+                    out.extend(closure.env().cloned().map(Node::Frame));
+                }
+                _ => {}
+            },
+            Node::Scope(scope) => {
+                out.extend(scope.values().into_iter().map(Node::Object));
+                out.extend(scope.parent().cloned().map(Node::Scope));
+            }
+            Node::Frame(frame) => {
+                out.extend(frame.values().into_iter().map(Node::Object));
+                out.extend(frame.parent().map(Node::Frame));
+            }
+        }
+    }
+
+    fn is_environment(&self) -> bool {
+        !matches!(self, Node::Object(_))
+    }
+
+    fn clear(&self) {
+        match self {
+            Node::Object(_) => {}
+            Node::Scope(scope) => scope.clear(),
+            Node::Frame(frame) => frame.clear(),
+        }
+    }
+
+    fn candidate(&self) -> Option<Candidate> {
+        match self {
+            Node::Object(_) => None,
+            Node::Scope(scope) => Some(Candidate::Scope(Rc::downgrade(scope))),
+            Node::Frame(frame) => Some(Candidate::Frame(Rc::downgrade(frame))),
+        }
+    }
+
+    /// Objects other than pairs and closures can't lead to an environment
+    fn is_container(&self) -> bool {
+        match self {
+            Node::Object(obj) => matches!(
+                obj.as_ref(),
+                Object::Pair(..)
+                    | Object::Function(Function::Object { .. })
+                    | Object::Function(Function::Compiled(_))
+            ),
+            _ => true,
+        }
+    }
+}
+
+struct Entry {
+    node: Node,
+    /// References from the other nodes of the graph
+    internal: usize,
+    children: Vec<usize>,
+    live: bool,
+}
+
+/// Remembers the scope captured by a closure.
+pub fn track_scope(scope: &Rc<Scope>) {
+    track(Rc::as_ptr(scope) as *const (), || {
+        Candidate::Scope(Rc::downgrade(scope))
+    });
+}
+
+/// Remembers the frame captured by a closure.
+pub(crate) fn track_frame(frame: &Rc<Frame>) {
+    track(Rc::as_ptr(frame) as *const (), || {
+        Candidate::Frame(Rc::downgrade(frame))
+    });
+}
+
+fn track(id: *const (), candidate: impl FnOnce() -> Candidate) {
+    let collect_now = COLLECTOR.with(|c| {
+        let mut c = c.borrow_mut();
+        let last = c.candidates.last().map(|last| match last {
+            Candidate::Scope(scope) => scope.as_ptr() as *const (),
+            Candidate::Frame(frame) => frame.as_ptr() as *const (),
+        });
+        if last == Some(id) {
+            return false;
+        }
+        c.candidates.push(candidate());
+        c.captured += 1;
+        c.captured >= c.threshold
+    });
+    if collect_now {
+        collect();
+    }
+}
+
+/// Frees environments and closures referencing each other which are not used anymore.
+/// Returns the number of freed environments.
+pub fn collect() -> usize {
+    if COLLECTING.with(|c| c.replace(true)) {
+        return 0;
+    }
+    let candidates = COLLECTOR.with(|c| std::mem::take(&mut c.borrow_mut().candidates));
+    let roots = candidates
+        .into_iter()
+        .filter_map(|candidate| match candidate {
+            Candidate::Scope(scope) => scope.upgrade().map(Node::Scope),
+            Candidate::Frame(frame) => frame.upgrade().map(Node::Frame),
+        });
+
+    // Every node is held once by its entry, so the rest of the strong count
+    // are the references from the other nodes and from outside
+    let mut entries: Vec<Entry> = vec![];
+    let mut index: HashMap<*const (), usize, RandomState> = HashMap::default();
+    let mut add = |node: Node, entries: &mut Vec<Entry>| -> usize {
+        *index.entry(node.id()).or_insert_with(|| {
+            entries.push(Entry { node, internal: 0, children: vec![], live: false });
+            entries.len() - 1
+        })
+    };
+    for root in roots {
+        add(root, &mut entries);
+    }
+    let root_count = entries.len();
+    let mut children = vec![];
+    let mut i = 0;
+    while i < entries.len() {
+        entries[i].node.children(&mut children);
+        for child in children.drain(..).filter(Node::is_container) {
+            let j = add(child, &mut entries);
+            entries[j].internal += 1;
+            entries[i].children.push(j);
+        }
+        i += 1;
+    }
+
+    let mut work: Vec<usize> = (0..entries.len())
+        .filter(|&i| entries[i].node.strong_count() > entries[i].internal + 1)
+        .collect();
+    while let Some(i) = work.pop() {
+        if !entries[i].live {
+            entries[i].live = true;
+            work.extend(entries[i].children.iter().copied());
+        }
+    }
+
+    let mut freed = 0;
+    for entry in entries
+        .iter()
+        .filter(|e| e.node.is_environment() && !e.live)
+    {
+        entry.node.clear();
+        freed += 1;
+    }
+    let mut survivors = (entries[..root_count].iter())
+        .filter(|e| e.live)
+        .filter_map(|e| e.node.candidate())
+        .collect::<Vec<_>>();
+    let live = entries.iter().filter(|e| e.live).count();
+    drop(entries);
+
+    COLLECTOR.with(|c| {
+        let mut c = c.borrow_mut();
+        // Environments captured while the garbage was dropped stay tracked
+        survivors.append(&mut c.candidates);
+        c.candidates = survivors;
+        c.captured = 0;
+        c.threshold = live.max(MIN_THRESHOLD);
+        c.stats.collections += 1;
+        c.stats.freed += freed as u64;
+    });
+    COLLECTING.with(|c| c.set(false));
+    freed
+}
+
+/// Counters of the collector of the current thread
+pub fn stats() -> GcStats {
+    COLLECTOR.with(|c| {
+        let c = c.borrow();
+        GcStats { tracked: c.candidates.len(), ..c.stats }
+    })
+}
+
+pub fn fn_gc(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "gc", 0)?;
+    Ok(Rc::new(Object::make_int(collect() as i64)))
+}
+
+/// Returns the counters of the collector as an association list
+pub fn gc_stats(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "gc-stats", 0)?;
+    let stats = stats();
+    let counters = [
+        ("collections", stats.collections as i64),
+        ("freed", stats.freed as i64),
+        ("tracked", stats.tracked as i64),
+    ];
+    let pairs = (counters.iter()).map(|&(name, value)| {
+        Object::cons(Symbol::intern(name).into_scheme(), value.into_scheme())
+    });
+    Ok(pairs.collect::<Vec<_>>().into_scheme())
+}
@@ -0,0 +1,302 @@
+//! A high-level interface for programs that embed the interpreter.
+//!
+//! ```
+//! use scheme::interpreter::Interpreter;
+//!
+//! let interpreter = Interpreter::builder().no_prelude().build().unwrap();
+//! interpreter.eval_str("(define (square x) (* x x))").unwrap();
+//! let result = interpreter.call("square", vec![interpreter.eval_str("5").unwrap()]);
+//! assert_eq!(result.unwrap().to_string(), "25");
+//! ```
+
+pub use crate::context::{InterruptHandle, Limits};
+
+use crate::context::Context;
+use crate::convert::IntoScheme;
+use crate::errors::EvalErr;
+use crate::memory::{HeapDump, MemoryStats};
+use crate::object::{List, Object};
+use crate::parser::parse_source;
+use crate::scope::Scope;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The evaluator used by an interpreter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    /// Walks the tree of the expression, see `eval::eval`
+    #[default]
+    TreeWalker,
+    /// Analyzes the syntax of the expression once and then executes it, see `analyze::eval`
+    Analyzer,
+    /// Compiles the expression to bytecode and runs it on the VM, see `vm::eval`
+    Bytecode,
+}
+
+impl Engine {
+    /// Evaluates the expression in the scope.
+    pub fn eval(self, expr: &Rc<Object>, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr> {
+        match self {
+            Engine::TreeWalker => crate::eval::eval(expr, scope),
+            Engine::Analyzer => crate::analyze::eval(expr, scope),
+            Engine::Bytecode => crate::vm::eval(expr, scope),
+        }
+    }
+}
+
+/// The source of the code evaluated when an interpreter is created.
+enum Prelude {
+    None,
+    Builtin,
+    Source(String),
+    File(PathBuf),
+}
+
+pub struct InterpreterBuilder {
+    prelude: Prelude,
+    output: Option<Box<dyn Write>>,
+    limits: Limits,
+    command_line: Vec<String>,
+    library_paths: Vec<PathBuf>,
+    sandbox: bool,
+    engine: Engine,
+}
+
+/// An independent instance of the interpreter with its own global scope and output.
+pub struct Interpreter {
+    scope: Rc<Scope>,
+    context: Rc<Context>,
+    prelude: Prelude,
+    engine: Engine,
+}
+
+impl InterpreterBuilder {
+    /// Don't load any prelude so only built-in functions are available.
+    pub fn no_prelude(mut self) -> Self {
+        self.prelude = Prelude::None;
+        self
+    }
+
+    /// Evaluates the given source code instead of the built-in prelude.
+    pub fn prelude_source(mut self, source: &str) -> Self {
+        self.prelude = Prelude::Source(source.to_string());
+        self
+    }
+
+    /// Loads the prelude from the given file.
+    pub fn prelude_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.prelude = Prelude::File(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Redirects everything that Scheme code prints to the given writer.
+    pub fn stdout<W: Write + 'static>(mut self, output: W) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The program name and its arguments available through `command-line`.
+    pub fn command_line(mut self, args: Vec<String>) -> Self {
+        self.command_line = args;
+        self
+    }
+
+    /// Adds a directory where `load` looks for files with relative names.
+    /// Directories are searched in the order they are added.
+    pub fn library_path<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.library_paths.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Makes an environment for untrusted code.
+    /// Functions that access files, environment variables or terminate the process are not defined
+    /// and `load` is forbidden. The prelude is still loaded, resource limits should be set separately.
+    pub fn sandbox(mut self) -> Self {
+        self.sandbox = true;
+        self
+    }
+
+    /// Selects the evaluator, the tree-walker is used by default.
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Creates the interpreter and evaluates the prelude.
+    /// Resource limits are not applied to the prelude.
+    pub fn build(self) -> Result<Interpreter, Box<dyn Error>> {
+        let output = self.output.unwrap_or_else(|| Box::new(std::io::stdout()));
+        let context = Context::new(output, Limits::default())
+            .with_command_line(self.command_line)
+            .with_library_paths(self.library_paths)
+            .with_sandbox(self.sandbox);
+        let scope = if self.sandbox {
+            Scope::from_safe_global()
+        } else {
+            Scope::from_global()
+        };
+        let interpreter = Interpreter {
+            scope: Rc::new(scope),
+            context: Rc::new(context),
+            prelude: self.prelude,
+            engine: self.engine,
+        };
+        interpreter.load_prelude()?;
+        interpreter.set_limits(self.limits);
+        Ok(interpreter)
+    }
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        InterpreterBuilder {
+            prelude: Prelude::Builtin,
+            output: None,
+            limits: Limits::default(),
+            command_line: vec![],
+            library_paths: vec![],
+            sandbox: false,
+            engine: Engine::default(),
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+
+    /// Creates an interpreter with the default settings.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::builder().build()
+    }
+
+    /// The scope where top-level definitions are stored.
+    pub fn scope(&self) -> &Rc<Scope> {
+        &self.scope
+    }
+
+    /// Evaluates all expressions from the string and returns the value of the last one.
+    pub fn eval_str(&self, source: &str) -> Result<Rc<Object>, Box<dyn Error>> {
+        self.eval_source(source, None)
+    }
+
+    /// Evaluates the source read from the file, the name is used for the locations of definitions.
+    fn eval_source(&self, source: &str, file: Option<&str>) -> Result<Rc<Object>, Box<dyn Error>> {
+        let _guard = self.context.enter();
+        let mut result = Rc::new(Object::Nil);
+        for obj in parse_source(source, file)? {
+            result = self.engine.eval(&Rc::new(obj), &self.scope)?;
+        }
+        Ok(result)
+    }
+
+    /// Evaluates an already parsed expression.
+    pub fn eval(&self, expr: &Rc<Object>) -> Result<Rc<Object>, EvalErr> {
+        let _guard = self.context.enter();
+        self.engine.eval(expr, &self.scope)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let file = path.as_ref().to_string_lossy();
+        let content = std::fs::read_to_string(path.as_ref());
+        let src = content.map_err(|_| format!("file '{}' cannot be opened", file))?;
+        self.eval_source(&src, Some(&file))?;
+        Ok(())
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Calls a globally defined function with the given arguments.
+    pub fn call(&self, name: &str, args: List) -> Result<Rc<Object>, EvalErr> {
+        let _guard = self.context.enter();
+        let func = self
+            .get_global(name)
+            .ok_or_else(|| EvalErr::UnboundVariable(name.to_string()))?;
+        match func.as_ref() {
+            Object::Function(f) => f.invoke(args),
+            x => Err(EvalErr::IllegalObjectAsAFunction(x.to_string())),
+        }
+    }
+
+    /// Removes all top-level definitions and evaluates the prelude again.
+    /// The scope object stays the same so references to it remain valid.
+    pub fn reset(&self) -> Result<(), Box<dyn Error>> {
+        self.scope.clear();
+        let limits = self.context.limits();
+        self.set_limits(Limits::default());
+        let result = self.load_prelude();
+        self.set_limits(limits);
+        result
+    }
+
+    fn load_prelude(&self) -> Result<(), Box<dyn Error>> {
+        match &self.prelude {
+            Prelude::None => {}
+            Prelude::Builtin => {
+                self.eval_source(crate::PRELUDE, Some("prelude.scm"))?;
+            }
+            Prelude::Source(src) => {
+                self.eval_str(src)?;
+            }
+            Prelude::File(path) => self.eval_file(path)?,
+        }
+        Ok(())
+    }
+
+    /// Returns a handle that stops the running evaluation with `EvalErr::Interrupted`.
+    /// The scope stays usable after the evaluation is interrupted.
+    ///
+    /// ```
+    /// use scheme::errors::EvalErr;
+    /// use scheme::interpreter::Interpreter;
+    ///
+    /// let interpreter = Interpreter::new().unwrap();
+    /// let handle = interpreter.interrupt_handle();
+    /// std::thread::spawn(move || handle.interrupt());
+    /// let result = interpreter.eval_str("(define (loop) (loop)) (loop)");
+    /// assert_eq!(result.unwrap_err().to_string(), EvalErr::Interrupted().to_string());
+    /// ```
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.context.interrupt_handle()
+    }
+
+    /// Replaces the resource limits applied to every following evaluation.
+    pub fn set_limits(&self, limits: Limits) {
+        self.context.set_limits(limits);
+    }
+
+    /// Frees the closures and environments referencing each other that are not used anymore,
+    /// returns the number of freed environments. See the `gc` module.
+    pub fn gc(&self) -> usize {
+        crate::gc::collect()
+    }
+
+    /// Allocation statistics of the thread the interpreter runs on, see the `memory` module.
+    pub fn stats(&self) -> MemoryStats {
+        crate::memory::stats()
+    }
+
+    /// Counts the objects retained by the top-level bindings by type.
+    pub fn heap_dump(&self) -> HeapDump {
+        crate::memory::heap_dump(&self.scope)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Rc<Object>> {
+        self.scope.get(name)
+    }
+
+    pub fn set_global<T: IntoScheme>(&self, name: &str, value: T) {
+        self.scope.bind(name, value.into_scheme());
+    }
+}
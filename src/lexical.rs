@@ -0,0 +1,218 @@
+//! Lexical addressing of local variables shared by the analyzer and the bytecode compiler.
+//!
+//! Variables bound by lambdas, `let` forms and internal definitions are resolved at compile time
+//! to the depth of their frame and the index of the slot in it.
+//! Frames are vectors, so calls don't allocate hash maps. Only globals are looked up by name.
+
+use crate::errors::EvalErr;
+use crate::memory;
+use crate::object::{List, Object};
+use crate::service::{list_to_vec, vec_to_list};
+use crate::symbol::Symbol;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Local variables of a call or of a `let` form. Empty slots are not assigned yet.
+pub struct Frame {
+    slots: RefCell<Vec<Option<Rc<Object>>>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    pub fn new(slots: Vec<Option<Rc<Object>>>, parent: Option<Rc<Frame>>) -> Rc<Frame> {
+        memory::frame_created();
+        Rc::new(Frame { slots: RefCell::new(slots), parent })
+    }
+
+    pub fn parent(&self) -> Option<Rc<Frame>> {
+        self.parent.clone()
+    }
+
+    fn at(&self, depth: u32) -> &Frame {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame.parent.as_ref().unwrap();
+        }
+        frame
+    }
+
+    /// The value of the slot or `None` if it is not assigned yet
+    pub fn get(&self, depth: u32, index: u32) -> Option<Rc<Object>> {
+        self.at(depth).slots.borrow()[index as usize].clone()
+    }
+
+    pub fn set(&self, depth: u32, index: u32, value: Rc<Object>) {
+        self.at(depth).slots.borrow_mut()[index as usize] = Some(value);
+    }
+
+    /// Values of the assigned slots
+    pub fn values(&self) -> List {
+        self.slots.borrow().iter().flatten().cloned().collect()
+    }
+
+    /// The estimated size of the frame itself in bytes, the values are not included
+    pub fn size(&self) -> usize {
+        let slot = std::mem::size_of::<Option<Rc<Object>>>();
+        std::mem::size_of::<Frame>() + self.slots.borrow().capacity() * slot
+    }
+
+    /// Unassigns all slots, the parent frame stays
+    pub fn clear(&self) {
+        for slot in self.slots.borrow_mut().iter_mut() {
+            *slot = None;
+        }
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        memory::frame_dropped();
+    }
+}
+
+/// Parameters of a lambda
+pub struct Params {
+    /// Names of the slots of the parameters, the rest parameter is the last one
+    pub names: Vec<Symbol>,
+    pub required: usize,
+    pub rest: bool,
+}
+
+impl Params {
+    /// Parameters from the formal arguments which are already checked
+    pub fn new(mut args: &Rc<Object>) -> Self {
+        let mut names = vec![];
+        while let Object::Pair(param, rest) = args.as_ref() {
+            if let Object::Symbol(s) = param.as_ref() {
+                names.push(*s);
+            }
+            args = rest;
+        }
+        let required = names.len();
+        let rest = match args.as_ref() {
+            Object::Symbol(s) => {
+                names.push(*s);
+                true
+            }
+            _ => false,
+        };
+        Params { names, required, rest }
+    }
+
+    /// Makes the slots of a call frame of the size with the arguments in the first slots.
+    /// Errors name the called function.
+    pub fn bind(
+        &self, name: &str, args: List, size: usize,
+    ) -> Result<Vec<Option<Rc<Object>>>, EvalErr> {
+        if args.len() < self.required {
+            return Err(EvalErr::TooFewArguments(name.to_string()));
+        }
+        if args.len() > self.required && !self.rest {
+            return Err(EvalErr::TooManyArguments(name.to_string()));
+        }
+        let mut slots = Vec::with_capacity(size);
+        if self.rest {
+            slots.extend(args[..self.required].iter().cloned().map(Some));
+            slots.push(Some(Rc::new(vec_to_list(&args[self.required..]))));
+        } else {
+            slots.extend(args.into_iter().map(Some));
+        }
+        slots.resize(size, None);
+        Ok(slots)
+    }
+}
+
+/// Names of the slots of lexically enclosing frames at compile time
+#[derive(Default)]
+pub struct Resolver {
+    /// The innermost frame is the last
+    frames: Vec<Vec<Symbol>>,
+}
+
+impl Resolver {
+    pub fn push(&mut self, names: Vec<Symbol>) {
+        self.frames.push(names);
+    }
+
+    pub fn pop(&mut self) -> Vec<Symbol> {
+        self.frames.pop().unwrap()
+    }
+
+    /// Finds the depth of the frame and the slot of a local variable.
+    /// A variable bound several times in a frame refers to the last binding.
+    pub fn resolve(&self, name: Symbol) -> Option<(u32, u32)> {
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if let Some(index) = frame.iter().rposition(|n| *n == name) {
+                return Some((depth as u32, index as u32));
+            }
+        }
+        None
+    }
+
+    /// Adds a new slot to the innermost frame
+    pub fn bind(&mut self, name: Symbol) -> u32 {
+        let frame = self.frames.last_mut().unwrap();
+        frame.push(name);
+        (frame.len() - 1) as u32
+    }
+
+    /// The slot of the innermost frame where a definition is stored.
+    /// Top-level definitions have no slot, they are global.
+    pub fn define(&mut self, name: Symbol) -> Option<u32> {
+        let frame = self.frames.last_mut()?;
+        match frame.iter().rposition(|n| *n == name) {
+            Some(index) => Some(index as u32),
+            None => {
+                frame.push(name);
+                Some((frame.len() - 1) as u32)
+            }
+        }
+    }
+}
+
+/// Finds variables defined directly in the body, not in nested lambdas and `let` bodies.
+pub fn scan_defines(expr: &Object, names: &mut Vec<Symbol>) {
+    let mut add = |name: &Object| {
+        if let Object::Symbol(s) = name {
+            if !names.contains(s) {
+                names.push(*s);
+            }
+        }
+    };
+    let (head, mut tail) = match expr {
+        Object::Pair(head, tail) => (head, tail),
+        _ => return,
+    };
+    if let Object::Symbol(s) = head.as_ref() {
+        match s.as_str() {
+            "quote" | "lambda" | "let*" | "letrec" => return,
+            "define" => match tail.as_ref() {
+                Object::Pair(target, value) => match target.as_ref() {
+                    Object::Pair(name, _) => return add(name),
+                    _ => {
+                        add(target);
+                        tail = value;
+                    }
+                },
+                _ => return,
+            },
+            "let" => {
+                if let Object::Pair(bindings, _) = tail.as_ref() {
+                    for binding in list_to_vec(bindings).unwrap_or_default() {
+                        if let Object::Pair(_, init) = binding.as_ref() {
+                            scan_defines(init, names);
+                        }
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+    }
+    scan_defines(head, names);
+    while let Object::Pair(item, rest) = tail.as_ref() {
+        scan_defines(item, names);
+        tail = rest;
+    }
+}
@@ -1,50 +1,101 @@
+pub mod analyze;
+pub mod convert;
+#[cfg(feature = "ide")]
+pub mod dap;
+pub mod debugger;
 pub mod errors;
 pub mod eval;
+pub mod expand;
+pub mod foreign;
 pub mod functions;
+pub mod gc;
+pub mod interpreter;
+pub mod lint;
+#[cfg(feature = "ide")]
+pub mod lsp;
+pub mod memory;
 pub mod object;
 pub mod parser;
+pub mod pretty;
+pub mod profiler;
+pub mod repl;
 pub mod scope;
+pub mod source;
+pub mod symbol;
+pub mod vm;
 
+mod compiler;
+mod context;
+#[cfg(feature = "readline")]
+mod editor;
+mod lexical;
 mod lists;
 mod logic;
 mod math;
+mod ports;
+#[cfg(feature = "ide")]
+mod protocol;
 mod service;
+mod system;
+mod trace;
 
 use std::error::Error;
-use std::io::BufRead;
 use std::rc::Rc;
 
+use interpreter::Interpreter;
 use object::Object;
 use scope::Scope;
 
+/// Library functions written in Scheme which are loaded by default.
+pub const PRELUDE: &str = include_str!("../prelude.scm");
+
+/// Returns the handle interrupting `eval_expr` and `eval_file` called on the current thread
+/// outside of an `Interpreter`.
+pub fn interrupt_handle() -> interpreter::InterruptHandle {
+    context::interrupt_handle()
+}
+
 pub fn eval_expr(expr: &str, scope: &Rc<Scope>) -> Result<Rc<Object>, Box<dyn Error>> {
-    let iter = parser::parse_expression(expr)?.into_iter();
-    let mut result = Rc::new(Object::Nil);
-    for obj in iter {
-        result = eval::eval(&Rc::new(obj), scope)?;
-    }
-    Ok(result)
+    eval_source(expr, None, scope)
 }
 
 pub fn eval_file(file: &str, scope: &Rc<Scope>) -> Result<(), Box<dyn Error>> {
     let content = std::fs::read_to_string(file);
     let src = content.map_err(|_| format!("file '{}' cannot be opened", file))?;
-    eval_expr(&src, scope)?;
+    eval_source(&src, Some(file), scope)?;
     Ok(())
 }
 
-pub fn repl() {
-    let scope = Rc::new(Scope::from_global());
+fn eval_source(
+    source: &str, file: Option<&str>, scope: &Rc<Scope>,
+) -> Result<Rc<Object>, Box<dyn Error>> {
+    let mut result = Rc::new(Object::Nil);
+    for obj in parser::parse_source(source, file)? {
+        result = eval::eval(&Rc::new(obj), scope)?;
+    }
+    Ok(result)
+}
 
-    if let Some(err) = eval_file("prelude.scm", &scope).err() {
-        println!("Error in 'prelude.scm': {}", err)
+pub fn repl() {
+    if let Some(code) = run_repl(Interpreter::new().unwrap()) {
+        std::process::exit(code);
     }
+}
 
-    // Read-Eval-Print Loop
-    (std::io::stdin().lock().lines())
-        .map(|str| match eval_expr(&str.unwrap(), &scope) {
-            Ok(ok) => ok.to_string(),
-            Err(err) => "Error: ".to_string() + &err.to_string(),
-        })
-        .for_each(|result| println!("{}", result));
+/// Runs the REPL with the given interpreter until the input ends or `exit` is called.
+/// Line editing is used if the standard input is a terminal.
+/// Returns the status passed to `exit`.
+pub fn run_repl(interpreter: Interpreter) -> Option<i32> {
+    let mut repl = repl::Repl::new(interpreter);
+    let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    #[cfg(feature = "readline")]
+    if interactive {
+        if let Err(err) = editor::run(&mut repl) {
+            println!("Error: {}", err);
+        }
+        return repl.exit_code();
+    }
+    repl.run(repl::StdinLines::default(), std::io::stdout(), interactive)
+        .unwrap();
+    repl.exit_code()
 }
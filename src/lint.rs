@@ -0,0 +1,387 @@
+//! Static checks of Scheme sources: the parsed forms are walked without evaluating them.
+//!
+//! Names are resolved in the scopes made by `lambda`, `let` and the bodies with internal
+//! `define`s, the rest of them are looked up in the global scope given to `lint`.
+//! Definitions of files given to `load` are not known. Special forms are checked
+//! with the same functions that report their errors at runtime.
+
+use crate::errors::EvalErr;
+use crate::eval::{is_cxr, let_binding, SPECIAL_FORMS};
+use crate::functions::{Arity, Function};
+use crate::object::Object;
+use crate::parser::parse_lines;
+use crate::scope::Scope;
+use crate::service::{expect_args, list_to_vec};
+use crate::source::{self, Location};
+use crate::symbol::Symbol;
+
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A problem found in the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Parameter,
+    Variable,
+    Definition,
+}
+
+struct Binding {
+    name: Symbol,
+    kind: Kind,
+    /// The arity if the value is a known procedure
+    arity: Option<Arity>,
+    location: Option<Location>,
+    used: bool,
+}
+
+struct Linter<'a> {
+    globals: &'a Scope,
+    /// Local scopes from the outermost one, the first one holds the top-level definitions
+    scopes: Vec<Vec<Binding>>,
+    /// The location of the innermost form with a known one
+    location: Option<Location>,
+    warnings: Vec<Warning>,
+}
+
+/// Checks the source, returns the warnings sorted by lines.
+/// A parse error is the only warning returned for a source that can't be parsed.
+pub fn lint(source: &str, file: Option<&str>, globals: &Scope) -> Vec<Warning> {
+    let forms = match parse_lines(source, file) {
+        Ok(forms) => forms.into_iter().map(Rc::new).collect::<Vec<_>>(),
+        Err((err, line)) => {
+            let location = Some(Location { file: file.map(Rc::from), line });
+            return vec![Warning { location, message: err.to_string() }];
+        }
+    };
+    let mut linter = Linter {
+        globals,
+        scopes: vec![vec![]],
+        location: None,
+        warnings: vec![],
+    };
+    linter.body(&forms);
+    let mut warnings = linter.warnings;
+    warnings.sort_by_key(|warning| warning.location.as_ref().map(|location| location.line));
+    warnings
+}
+
+/// The name and the arity of the procedure defined by the `define` form
+fn definition(expr: &Object) -> Option<(Symbol, Option<Arity>)> {
+    match expr {
+        Object::Pair(head, tail) if head.is_symbol("define") => match tail.as_ref() {
+            Object::Pair(target, value) => match target.as_ref() {
+                Object::Symbol(s) => match value.as_ref() {
+                    Object::Pair(value, _) => Some((*s, lambda_arity(value))),
+                    _ => Some((*s, None)),
+                },
+                Object::Pair(name, params) => match name.as_ref() {
+                    Object::Symbol(s) => Some((*s, Some(Arity::of(params)))),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The arity of the procedure made by the expression if it is a `lambda` form
+fn lambda_arity(expr: &Object) -> Option<Arity> {
+    match expr {
+        Object::Pair(head, tail) if head.is_symbol("lambda") => match tail.as_ref() {
+            Object::Pair(params, _) => Some(Arity::of(params)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The arity of a global value if it is a procedure with a known one
+fn global_arity(value: &Object) -> Option<Arity> {
+    match value {
+        Object::Function(Function::Object { args, .. }) => Some(Arity::of(args)),
+        Object::Function(Function::Builtin(builtin)) => Some(builtin.arity),
+        _ => None,
+    }
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, message: String) {
+        let location = self.location.clone();
+        self.warnings.push(Warning { location, message });
+    }
+
+    fn error(&mut self, err: EvalErr) {
+        self.warn(err.to_string());
+    }
+
+    /// Binds the name in the innermost scope
+    fn bind(&mut self, name: Symbol, kind: Kind, arity: Option<Arity>, location: Option<Location>) {
+        let shadowed = if SPECIAL_FORMS.contains(&name.as_str()) {
+            Some("special form")
+        } else if is_cxr(name.as_str()) || self.globals.get(name).is_some() {
+            Some("built-in")
+        } else {
+            None
+        };
+        if let Some(shadowed) = shadowed {
+            let message = format!("{} shadows a {}", name, shadowed);
+            self.warnings
+                .push(Warning { location: location.clone(), message });
+        }
+        let binding = Binding { name, kind, arity, location, used: false };
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    /// Leaves the innermost scope and reports its unused parameters and variables
+    fn pop(&mut self) {
+        for binding in self.scopes.pop().unwrap() {
+            let kind = match binding.kind {
+                Kind::Parameter => "parameter",
+                Kind::Variable => "variable",
+                Kind::Definition => continue,
+            };
+            if !binding.used && !binding.name.as_str().starts_with('_') {
+                let message = format!("Unused {} {}", kind, binding.name);
+                self.warnings
+                    .push(Warning { location: binding.location, message });
+            }
+        }
+    }
+
+    /// Resolves the name, returns the arity if it is a known procedure
+    fn reference(&mut self, name: Symbol) -> Option<Arity> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.iter_mut().rev().find(|b| b.name == name) {
+                binding.used = true;
+                return binding.arity;
+            }
+        }
+        if is_cxr(name.as_str()) {
+            return Some(Arity { min: 1, max: Some(1) });
+        }
+        match self.globals.get(name) {
+            Some(value) => global_arity(&value),
+            None => {
+                self.error(EvalErr::UnboundVariable(name.to_string()));
+                None
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Rc<Object>]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Rc<Object>) {
+        match expr.as_ref() {
+            Object::Symbol(s) => {
+                self.reference(*s);
+            }
+            Object::Pair(head, tail) => {
+                let outer = self.location.clone();
+                if let Some(location) = source::location(tail) {
+                    self.location = Some(location);
+                }
+                self.form(head, tail);
+                self.location = outer;
+            }
+            _ => {}
+        }
+    }
+
+    fn form(&mut self, head: &Rc<Object>, tail: &Rc<Object>) {
+        let args = match list_to_vec(tail) {
+            Ok(args) => args,
+            Err(err) => return self.error(err),
+        };
+        match head.as_ref() {
+            Object::Symbol(s) if SPECIAL_FORMS.contains(&s.as_str()) => {
+                return self.special_form(s.as_str(), &args);
+            }
+            Object::Symbol(s) => match self.reference(*s) {
+                Some(arity) if !arity.accepts(args.len()) => self.warn(format!(
+                    "Wrong number of arguments for {}: {}, expected {}",
+                    s,
+                    args.len(),
+                    arity
+                )),
+                _ => {}
+            },
+            _ => self.expr(head),
+        }
+        self.exprs(&args);
+    }
+
+    fn special_form(&mut self, form: &str, args: &[Rc<Object>]) {
+        match form {
+            "quote" => {
+                if let Err(err) = expect_args(args.to_vec(), "quote", 1) {
+                    self.error(err);
+                }
+            }
+            "if" => {
+                if args.len() > 3 {
+                    self.warn(format!("Too many branches for if: {}", args.len() - 1));
+                } else if let Err(err) = expect_args(args.to_vec(), "if", 3) {
+                    self.error(err);
+                }
+                self.exprs(args);
+            }
+            "let" | "let*" | "letrec" => self.let_form(form, args),
+            "define" => self.define(args),
+            "lambda" => match args {
+                [params, body] => self.lambda(params, std::slice::from_ref(body)),
+                [_, _, ..] => self.error(EvalErr::TooManyArguments("lambda".to_string())),
+                _ => self.error(EvalErr::TooFewArguments("lambda".to_string())),
+            },
+            "cond" => self.cond(args),
+            _ => self.exprs(args),
+        }
+    }
+
+    /// Checks the expressions of a body, its definitions are bound in the innermost scope beforehand
+    fn body(&mut self, body: &[Rc<Object>]) {
+        for expr in body {
+            if let Some((name, arity)) = definition(expr) {
+                let location = match expr.as_ref() {
+                    Object::Pair(_, tail) => source::location(tail),
+                    _ => None,
+                };
+                let scope = self.scopes.last().unwrap();
+                let first = (scope.iter())
+                    .find(|binding| binding.name == name && binding.kind == Kind::Definition);
+                if let Some(first) = first {
+                    let message = match &first.location {
+                        Some(first) => format!("{} is already defined at {}", name, first),
+                        None => format!("{} is already defined", name),
+                    };
+                    self.warnings
+                        .push(Warning { location: location.clone(), message });
+                }
+                self.bind(name, Kind::Definition, arity, location);
+            }
+        }
+        self.exprs(body);
+    }
+
+    fn define(&mut self, args: &[Rc<Object>]) {
+        let name = match args.first().map(|arg| arg.as_ref()) {
+            // (define x expr)
+            Some(Object::Symbol(s)) => {
+                if args.len() < 2 {
+                    self.error(EvalErr::TooFewArguments("define".to_string()));
+                } else if args.len() > 2 {
+                    self.error(EvalErr::TooManyArguments("define".to_string()));
+                }
+                self.exprs(&args[1..]);
+                *s
+            }
+            // (define (name args) body)
+            Some(Object::Pair(name, params)) => match name.as_ref() {
+                Object::Symbol(s) => {
+                    self.lambda(params, &args[1..]);
+                    *s
+                }
+                _ => return self.error(EvalErr::ExpectedSymbolForFunctionName(name.to_string())),
+            },
+            Some(x) => return self.error(EvalErr::WrongDefineArgument(x.to_string())),
+            None => return self.error(EvalErr::TooFewArguments("define".to_string())),
+        };
+        // definitions which are not directly in a body are bound when they are met
+        let scope = self.scopes.last().unwrap();
+        if !scope.iter().any(|binding| binding.name == name) {
+            self.bind(name, Kind::Definition, None, self.location.clone());
+        }
+    }
+
+    fn lambda(&mut self, params: &Rc<Object>, body: &[Rc<Object>]) {
+        if let Err(err) = Function::check_args(params) {
+            return self.error(err);
+        }
+        if body.is_empty() {
+            self.error(EvalErr::EmptyFunctionBody());
+        }
+        self.scopes.push(vec![]);
+        let mut params = params.as_ref();
+        while let Object::Pair(param, tail) = params {
+            if let Object::Symbol(s) = param.as_ref() {
+                self.bind(*s, Kind::Parameter, None, self.location.clone());
+            }
+            params = tail;
+        }
+        if let Object::Symbol(rest) = params {
+            self.bind(*rest, Kind::Parameter, None, self.location.clone());
+        }
+        self.body(body);
+        self.pop();
+    }
+
+    fn let_form(&mut self, form: &str, args: &[Rc<Object>]) {
+        if args.len() < 2 {
+            return self.error(EvalErr::NeedAtLeastArgs("let".to_string(), 2, args.len()));
+        }
+        let bindings = list_to_vec(&args[0]).and_then(|bindings| {
+            (bindings.iter())
+                .map(|binding| let_binding(binding))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let bindings = match bindings {
+            Ok(bindings) => bindings,
+            Err(err) => return self.error(err),
+        };
+        let location = self.location.clone();
+        if form == "let" {
+            for (_, init) in &bindings {
+                self.expr(init);
+            }
+        }
+        self.scopes.push(vec![]);
+        for (name, init) in &bindings {
+            if form == "let*" {
+                self.expr(init);
+            }
+            self.bind(*name, Kind::Variable, lambda_arity(init), location.clone());
+        }
+        if form == "letrec" {
+            for (_, init) in &bindings {
+                self.expr(init);
+            }
+        }
+        self.body(&args[1..]);
+        self.pop();
+    }
+
+    fn cond(&mut self, clauses: &[Rc<Object>]) {
+        if clauses.is_empty() {
+            self.error(EvalErr::CondNeedsClause());
+        }
+        for clause in clauses {
+            match list_to_vec(clause) {
+                Err(err) => self.error(err),
+                Ok(exprs) if exprs.is_empty() => self.error(EvalErr::CondEmptyClause()),
+                Ok(exprs) if exprs[0].is_symbol("else") => self.exprs(&exprs[1..]),
+                Ok(exprs) => self.exprs(&exprs),
+            }
+        }
+    }
+}
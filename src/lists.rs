@@ -1,3 +1,7 @@
+use crate::convert::{from_args, FromScheme, IntoScheme};
+use crate::logic::object_equal;
+use crate::math::get_float;
+use crate::object::Number::{self, Float, Integer};
 use crate::object::{List, Object};
 use crate::service::*;
 
@@ -15,8 +19,8 @@ pub fn cadr(name: &str, obj: List) -> Result<Rc<Object>, EvalErr> {
 }
 
 pub fn cons(obj: List) -> Result<Rc<Object>, EvalErr> {
-    let (car, cdr) = expect_2_args(obj, "cons")?;
-    Ok(Rc::new(Object::Pair(car, cdr)))
+    let (car, cdr) = from_args(&obj, "cons")?;
+    Ok(Rc::new(Object::cons(car, cdr)))
 }
 
 pub fn list(obj: List) -> Result<Rc<Object>, EvalErr> {
@@ -24,23 +28,332 @@ pub fn list(obj: List) -> Result<Rc<Object>, EvalErr> {
 }
 
 pub fn length(obj: List) -> Result<Rc<Object>, EvalErr> {
-    let len = list_to_vec(expect_1_arg(obj, "length")?.as_ref())?.len();
-    Ok(Rc::new(Object::make_int(len as i64)))
+    let list = Vec::<Rc<Object>>::from_scheme(&expect_1_arg(obj, "length")?)?;
+    Ok((list.len() as i64).into_scheme())
 }
 
 pub fn is_pair(obj: List) -> Result<Rc<Object>, EvalErr> {
-    Ok(Rc::new(Object::Boolean(matches!(
-        expect_1_arg(obj, "pair?")?.as_ref(),
-        Object::Pair(..)
-    ))))
+    let arg = expect_1_arg(obj, "pair?")?;
+    Ok(matches!(arg.as_ref(), Object::Pair(..)).into_scheme())
 }
 
 pub fn is_list(obj: List) -> Result<Rc<Object>, EvalErr> {
     let arg = expect_1_arg(obj, "list?")?;
-    Ok(Rc::new(Object::Boolean(list_to_vec(&arg).is_ok())))
+    Ok(list_to_vec(&arg).is_ok().into_scheme())
 }
 
 pub fn is_null(obj: List) -> Result<Rc<Object>, EvalErr> {
     let arg = expect_1_arg(obj, "null?")?;
-    Ok(Rc::new(Object::Boolean(arg.is_nil())))
+    Ok(arg.is_nil().into_scheme())
+}
+
+/// Calls a Scheme procedure passed to a native function
+fn call(func: &Object, args: List) -> Result<Rc<Object>, EvalErr> {
+    match func {
+        Object::Function(f) => f.invoke(args),
+        x => Err(EvalErr::IllegalObjectAsAFunction(x.to_string())),
+    }
+}
+
+/// Checks a count or an index given to a function
+fn non_negative(n: i64) -> Result<usize, EvalErr> {
+    if n < 0 {
+        let expected = "non-negative integer".to_string();
+        return Err(EvalErr::TypeMismatch(expected, n.to_string()));
+    }
+    Ok(n as usize)
+}
+
+/// Converts all the list arguments of a function like `fold` or `any` to vectors
+/// and returns the length of the shortest one.
+/// Multi-list functions stop as soon as any of the lists runs out.
+fn multiple_lists(args: &[Rc<Object>]) -> Result<(Vec<List>, usize), EvalErr> {
+    let lists = args
+        .iter()
+        .map(|arg| list_to_vec(arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    Ok((lists, len))
+}
+
+/// Takes i-th elements of all the lists
+fn nth_elements(lists: &[List], i: usize) -> List {
+    lists.iter().map(|list| list[i].clone()).collect()
+}
+
+fn is_equal(eq: &Option<Rc<Object>>, x: &Rc<Object>, y: &Rc<Object>) -> Result<bool, EvalErr> {
+    match eq {
+        Some(f) => Ok(call(f, vec![x.clone(), y.clone()])?.is_true()),
+        None => Ok(object_equal(x, y)),
+    }
+}
+
+/// `(list-copy list)` copies the pairs of the list, the tail of an improper list is shared
+pub fn list_copy(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (mut list,): (Rc<Object>,) = from_args(&args, "list-copy")?;
+    let mut items = Vec::new();
+    while let Object::Pair(x, tail) = list.clone().as_ref() {
+        items.push(x.clone());
+        list = tail.clone();
+    }
+    Ok(items
+        .into_iter()
+        .rfold(list, |tail, x| Rc::new(Object::cons(x, tail))))
+}
+
+/// `(iota count [start [step]])` returns the list of `count` numbers from `start` by `step`
+pub fn iota(args: List) -> Result<Rc<Object>, EvalErr> {
+    let count = non_negative(i64::from_scheme(&args[0])?)?;
+    let number = |i: usize| args.get(i).map(Number::from_scheme).transpose();
+    let start = number(1)?.unwrap_or(Integer(0));
+    let step = number(2)?.unwrap_or(Integer(1));
+    let items = (0..count as i64)
+        .map(|i| match (start, step) {
+            (Integer(a), Integer(b)) => Integer(a + i * b).into_scheme(),
+            (a, b) => Float(get_float(&a) + i as f64 * get_float(&b)).into_scheme(),
+        })
+        .collect::<Vec<_>>();
+    Ok(Rc::new(vec_to_list(&items)))
+}
+
+/// `(append list ...)`
+///
+/// All arguments except the last one must be proper lists.
+/// The last argument may be any object and it is shared with the result.
+pub fn append(args: List) -> Result<Rc<Object>, EvalErr> {
+    if let [init @ .., last] = args.as_slice() {
+        let mut items = Vec::new();
+        for list in init {
+            items.extend(list_to_vec(list)?);
+        }
+        Ok(items
+            .into_iter()
+            .rfold(last.clone(), |tail, x| Rc::new(Object::cons(x, tail))))
+    } else {
+        Ok(Rc::new(Object::Nil))
+    }
+}
+
+fn filter_list(args: List, name: &str, keep: bool) -> Result<Rc<Object>, EvalErr> {
+    let (pred, list): (Rc<Object>, Vec<Rc<Object>>) = from_args(&args, name)?;
+    let mut result = Vec::new();
+    for x in list {
+        if call(&pred, vec![x.clone()])?.is_true() == keep {
+            result.push(x);
+        }
+    }
+    Ok(Rc::new(vec_to_list(&result)))
+}
+
+/// `(filter pred list)` returns all the elements of the list that satisfy the predicate
+pub fn filter(args: List) -> Result<Rc<Object>, EvalErr> {
+    filter_list(args, "filter", true)
+}
+
+/// `(remove pred list)` returns all the elements of the list that don't satisfy the predicate
+pub fn remove(args: List) -> Result<Rc<Object>, EvalErr> {
+    filter_list(args, "remove", false)
+}
+
+/// `(partition pred list)` divides the list into elements that satisfy the predicate
+/// and the rest. Multiple values are not supported so both lists are returned as a list.
+pub fn partition(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (pred, list): (Rc<Object>, Vec<Rc<Object>>) = from_args(&args, "partition")?;
+    let (mut yes, mut no) = (Vec::new(), Vec::new());
+    for x in list {
+        if call(&pred, vec![x.clone()])?.is_true() {
+            yes.push(x);
+        } else {
+            no.push(x);
+        }
+    }
+    Ok(vec![vec_to_list(&yes), vec_to_list(&no)].into_scheme())
+}
+
+/// `(delete x list [=])` removes all the elements equal to `x`
+pub fn delete(args: List) -> Result<Rc<Object>, EvalErr> {
+    let eq = args.get(2).cloned();
+    let mut result = Vec::new();
+    for y in list_to_vec(&args[1])? {
+        if !is_equal(&eq, &args[0], &y)? {
+            result.push(y);
+        }
+    }
+    Ok(Rc::new(vec_to_list(&result)))
+}
+
+/// `(delete-duplicates list [=])` keeps only the first occurrence of every element
+pub fn delete_duplicates(args: List) -> Result<Rc<Object>, EvalErr> {
+    let eq = args.get(1).cloned();
+    let mut result: List = Vec::new();
+    for x in list_to_vec(&args[0])? {
+        let mut found = false;
+        for y in result.iter() {
+            if is_equal(&eq, y, &x)? {
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            result.push(x);
+        }
+    }
+    Ok(Rc::new(vec_to_list(&result)))
+}
+
+/// `(any pred list1 list2 ...)` returns the first true value returned by the predicate
+pub fn any(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[1..])?;
+    for i in 0..len {
+        let result = call(&args[0], nth_elements(&lists, i))?;
+        if result.is_true() {
+            return Ok(result);
+        }
+    }
+    Ok(false.into_scheme())
+}
+
+/// `(every pred list1 list2 ...)` returns the last value returned by the predicate
+/// if all of them are true, or `#t` for empty lists
+pub fn every(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[1..])?;
+    let mut result = true.into_scheme();
+    for i in 0..len {
+        result = call(&args[0], nth_elements(&lists, i))?;
+        if !result.is_true() {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// `(find pred list)` returns the first element that satisfies the predicate or `#f`
+pub fn find(args: List) -> Result<Rc<Object>, EvalErr> {
+    let tail = find_tail(args)?;
+    match tail.as_ref() {
+        Object::Pair(x, _) => Ok(x.clone()),
+        _ => Ok(tail),
+    }
+}
+
+/// `(find-tail pred list)` returns the first pair whose car satisfies the predicate or `#f`
+pub fn find_tail(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (pred, mut list): (Rc<Object>, Rc<Object>) = from_args(&args, "find-tail")?;
+    while let Object::Pair(x, tail) = list.as_ref() {
+        if call(&pred, vec![x.clone()])?.is_true() {
+            return Ok(list);
+        }
+        list = tail.clone();
+    }
+    if !list.is_nil() {
+        return Err(EvalErr::ListRequired(args[1].to_string()));
+    }
+    Ok(false.into_scheme())
+}
+
+/// `(count pred list1 list2 ...)` counts how many times the predicate returns true
+pub fn count(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[1..])?;
+    let mut n = 0;
+    for i in 0..len {
+        if call(&args[0], nth_elements(&lists, i))?.is_true() {
+            n += 1;
+        }
+    }
+    Ok((n as i64).into_scheme())
+}
+
+/// `(list-index pred list1 list2 ...)` returns the index of the first elements
+/// that satisfy the predicate or `#f`
+pub fn list_index(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[1..])?;
+    for i in 0..len {
+        if call(&args[0], nth_elements(&lists, i))?.is_true() {
+            return Ok((i as i64).into_scheme());
+        }
+    }
+    Ok(false.into_scheme())
+}
+
+/// Splits the list after `k` elements. The rest of the list can be an improper list.
+fn split_list(args: List, name: &str) -> Result<(List, Rc<Object>), EvalErr> {
+    let (mut list, k): (Rc<Object>, i64) = from_args(&args, name)?;
+    let k = non_negative(k)?;
+    let mut head = Vec::new();
+    for _ in 0..k {
+        let (x, tail) = check_pair(&list)?;
+        head.push(x.clone());
+        list = tail.clone();
+    }
+    Ok((head, list))
+}
+
+/// `(take list k)` returns the first `k` elements of the list
+pub fn take(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (head, _) = split_list(args, "take")?;
+    Ok(Rc::new(vec_to_list(&head)))
+}
+
+/// `(drop list k)` returns all but the first `k` elements of the list
+pub fn drop(args: List) -> Result<Rc<Object>, EvalErr> {
+    Ok(split_list(args, "drop")?.1)
+}
+
+/// `(split-at list k)` returns the list of the results of `take` and `drop`
+pub fn split_at(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (head, tail) = split_list(args, "split-at")?;
+    Ok(vec![Rc::new(vec_to_list(&head)), tail].into_scheme())
+}
+
+/// `(append-map f list1 list2 ...)` appends the results of mapping `f` over the lists
+pub fn append_map(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[1..])?;
+    let mut results = Vec::new();
+    for i in 0..len {
+        results.push(call(&args[0], nth_elements(&lists, i))?);
+    }
+    results.push(Rc::new(Object::Nil));
+    append(results)
+}
+
+/// `(fold kons knil list1 list2 ...)` calls `(kons e1 e2 ... acc)`
+/// for elements from left to right
+pub fn fold(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[2..])?;
+    let mut acc = args[1].clone();
+    for i in 0..len {
+        let mut call_args = nth_elements(&lists, i);
+        call_args.push(acc);
+        acc = call(&args[0], call_args)?;
+    }
+    Ok(acc)
+}
+
+/// `(fold-right kons knil list1 list2 ...)` is the same as `fold`
+/// but it goes through the lists from right to left
+pub fn fold_right(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (lists, len) = multiple_lists(&args[2..])?;
+    let mut acc = args[1].clone();
+    for i in (0..len).rev() {
+        let mut call_args = nth_elements(&lists, i);
+        call_args.push(acc);
+        acc = call(&args[0], call_args)?;
+    }
+    Ok(acc)
+}
+
+/// `(reduce f ridentity list)` is `fold` with the first element as the initial value.
+/// `ridentity` is returned only for an empty list.
+pub fn reduce(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (func, ridentity, list): (Rc<Object>, Rc<Object>, Vec<Rc<Object>>) =
+        from_args(&args, "reduce")?;
+    if let [first, rest @ ..] = list.as_slice() {
+        let mut acc = first.clone();
+        for x in rest {
+            acc = call(&func, vec![x.clone(), acc])?;
+        }
+        Ok(acc)
+    } else {
+        Ok(ridentity)
+    }
 }
@@ -9,7 +9,7 @@ use crate::service::*;
 use std::rc::Rc;
 
 fn make_boolean(b: bool) -> Rc<Object> {
-    Rc::new(Object::Boolean(b))
+    Rc::new(Object::from(b))
 }
 
 pub fn fn_if(args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
@@ -30,7 +30,7 @@ pub fn cond(cond_list: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
         }
         let predicate = &vec[0];
         let mut is_true = false;
-        if &Object::Symbol("else".to_string()) == predicate.as_ref() {
+        if predicate.is_symbol("else") {
             is_true = true;
         } else {
             let x = eval(predicate, scope)?;
@@ -87,7 +87,7 @@ pub fn logic_or(args: List, scope: &Rc<Scope>) -> Result<CallResult, EvalErr> {
     Ok(CallResult::TailCall(result, scope.clone()))
 }
 
-fn object_equal(obj1: &Rc<Object>, obj2: &Rc<Object>) -> bool {
+pub fn object_equal(obj1: &Rc<Object>, obj2: &Rc<Object>) -> bool {
     match (obj1.as_ref(), obj2.as_ref()) {
         (Object::Number(x), Object::Number(y)) => num_equal(x, y),
         (Object::Pair(car1, cdr1), Object::Pair(car2, cdr2)) => {
@@ -0,0 +1,575 @@
+//! Language Server Protocol server, editors talk to it to check and navigate Scheme sources.
+//!
+//! Documents are synchronized in full and parsed on every change, parse errors are published
+//! as diagnostics. The parser records only the lines of lists, so definitions are looked up
+//! in the text from those lines. While a document has a parse error, the definitions
+//! of its last correct version are used. Hover and completion also know the special forms
+//! and the globals of an interpreter with the prelude loaded.
+
+use crate::eval::SPECIAL_FORMS;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+use crate::parser::{self, is_symbol_char};
+use crate::protocol::Connection;
+use crate::repl::describe;
+use crate::source;
+use crate::symbol::Symbol;
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Kinds of symbols and completion items defined by the protocol
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+
+/// A position in the text, the column is counted in characters.
+/// The protocol counts columns in UTF-16 code units, they are converted when messages are made.
+#[derive(Clone, Copy)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+type Range = (Position, Position);
+
+struct Text {
+    lines: Vec<Vec<char>>,
+}
+
+/// Characters of the text from a position that are not in comments and string literals,
+/// quotes of the strings are kept and the end of every line is `'\n'`
+struct Code<'a> {
+    lines: &'a [Vec<char>],
+    position: Position,
+    in_string: bool,
+}
+
+/// A name defined with `define`
+struct Definition {
+    name: String,
+    /// The formal arguments if a procedure is defined
+    args: Option<Rc<Object>>,
+    /// The whole form
+    range: Range,
+    /// The name in the form
+    selection: Range,
+    /// Definitions in the body
+    children: Vec<Definition>,
+}
+
+/// Finds definitions in a parsed program, the text is searched forward since the forms are in order
+struct Outline<'a> {
+    text: &'a Text,
+    cursor: Position,
+}
+
+struct Document {
+    text: Rc<Text>,
+    definitions: Vec<Definition>,
+    /// The text the ranges of the definitions point into, it is the text of the last correct
+    /// version while the document has a parse error
+    outline: Rc<Text>,
+    /// The parse error and its line counted from zero
+    error: Option<(String, usize)>,
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<String, Document>,
+    interpreter: Interpreter,
+    shutdown: bool,
+}
+
+type Failure = (i64, String);
+
+/// Serves the client until it sends `exit` or the input ends.
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Result<(), Box<dyn Error>> {
+    let mut server = Server {
+        connection: Connection::new(input, output),
+        documents: HashMap::new(),
+        interpreter: Interpreter::new()?,
+        shutdown: false,
+    };
+    while let Some(message) = server.connection.read()? {
+        // responses to requests of the server are not expected
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            None => continue,
+        };
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id,
+            None if method == "exit" => break,
+            None => {
+                server.notified(method, params)?;
+                continue;
+            }
+        };
+        let result = match server.shutdown {
+            true => Err((INVALID_REQUEST, "the server is shut down".to_string())),
+            false => server.handle(method, params),
+        };
+        server.respond(id, result)?;
+    }
+    Ok(())
+}
+
+impl Position {
+    fn new(line: usize, column: usize) -> Self {
+        Position { line, column }
+    }
+}
+
+impl Text {
+    fn new(source: &str) -> Self {
+        let lines = (source.split('\n'))
+            .map(|line| line.strip_suffix('\r').unwrap_or(line).chars().collect())
+            .collect();
+        Text { lines }
+    }
+
+    fn code(&self, from: Position) -> Code<'_> {
+        Code { lines: &self.lines, position: from, in_string: false }
+    }
+
+    fn end(&self) -> Position {
+        let line = self.lines.len() - 1;
+        Position::new(line, self.lines[line].len())
+    }
+
+    /// Converts the position sent by the client
+    fn position(&self, position: &Value) -> Position {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+        let chars = self.lines.get(line).map_or(&[][..], |chars| &chars[..]);
+        let mut units = 0;
+        let column = (chars.iter())
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= character
+            })
+            .count();
+        Position::new(line, column)
+    }
+
+    fn json_position(&self, position: Position) -> Value {
+        let chars = &self.lines[position.line][..position.column];
+        let character = chars.iter().map(|c| c.len_utf16()).sum::<usize>();
+        json!({ "line": position.line, "character": character })
+    }
+
+    fn json_range(&self, range: Range) -> Value {
+        json!({ "start": self.json_position(range.0), "end": self.json_position(range.1) })
+    }
+
+    fn line_range(&self, line: usize) -> Range {
+        let line = line.min(self.lines.len() - 1);
+        (
+            Position::new(line, 0),
+            Position::new(line, self.lines[line].len()),
+        )
+    }
+
+    /// Returns the symbol around the position and its range, the position may be right after it
+    fn symbol_at(&self, position: Position) -> Option<(String, Range)> {
+        let chars = self.lines.get(position.line)?;
+        let mut start = position.column.min(chars.len());
+        while start > 0 && is_symbol_char(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = position.column.min(chars.len());
+        while end < chars.len() && is_symbol_char(chars[end]) {
+            end += 1;
+        }
+        let range = (
+            Position::new(position.line, start),
+            Position::new(position.line, end),
+        );
+        Some((chars[start..end].iter().collect(), range)).filter(|_| start < end)
+    }
+
+    /// Finds the symbol in the code after the position
+    fn find(&self, from: Position, name: &str) -> Option<Range> {
+        let mut symbol = String::new();
+        let mut start = from;
+        for (position, c) in self.code(from) {
+            if is_symbol_char(c) {
+                if symbol.is_empty() {
+                    start = position;
+                }
+                symbol.push(c);
+            } else if symbol == name {
+                return Some((start, position));
+            } else {
+                symbol.clear();
+            }
+        }
+        None
+    }
+
+    /// Returns the position after the list that is opened at the position
+    fn list_end(&self, open: Position) -> Position {
+        let mut depth = 0;
+        for (position, c) in self.code(open) {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 1 => return Position::new(position.line, position.column + 1),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        self.end()
+    }
+
+    /// Finds the parenthesis opening the form that starts with the symbol at the position
+    fn open_paren(&self, symbol: Position) -> Option<Position> {
+        let chars = &self.lines[symbol.line][..symbol.column];
+        let column = chars.iter().rposition(|c| !c.is_whitespace())?;
+        Some(Position::new(symbol.line, column)).filter(|_| chars[column] == '(')
+    }
+}
+
+impl Iterator for Code<'_> {
+    type Item = (Position, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let chars = self.lines.get(self.position.line)?;
+            let position = self.position;
+            let c = match chars.get(position.column) {
+                Some(&c) => c,
+                None => {
+                    self.position = Position::new(position.line + 1, 0);
+                    if self.in_string {
+                        continue;
+                    }
+                    return Some((position, '\n'));
+                }
+            };
+            self.position.column += 1;
+            match c {
+                '"' => {
+                    self.in_string = !self.in_string;
+                    return Some((position, c));
+                }
+                _ if self.in_string => {}
+                ';' => self.position.column = chars.len(),
+                _ => return Some((position, c)),
+            }
+        }
+    }
+}
+
+impl Definition {
+    fn describe(&self) -> String {
+        match &self.args {
+            Some(args) => {
+                let name = Rc::new(Object::from(Symbol::intern(&self.name)));
+                format!(
+                    "{}: procedure {}",
+                    self.name,
+                    Object::cons(name, args.clone())
+                )
+            }
+            None => format!("{}: variable", self.name),
+        }
+    }
+
+    fn symbol(&self, text: &Text) -> Value {
+        let children = (self.children.iter())
+            .map(|child| child.symbol(text))
+            .collect::<Vec<_>>();
+        json!({
+            "name": self.name,
+            "detail": self.describe(),
+            "kind": if self.args.is_some() { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE },
+            "range": text.json_range(self.range),
+            "selectionRange": text.json_range(self.selection),
+            "children": children,
+        })
+    }
+
+    /// The definition and the ones in its body
+    fn all(&self) -> Vec<&Definition> {
+        let mut all = vec![self];
+        all.extend(self.children.iter().flat_map(Definition::all));
+        all
+    }
+}
+
+impl Outline<'_> {
+    /// Collects the definitions in the expression, quoted lists are skipped
+    fn definitions(&mut self, expr: &Object, definitions: &mut Vec<Definition>) {
+        let (head, tail) = match expr {
+            Object::Pair(head, tail) => (head, tail),
+            _ => return,
+        };
+        if head.is_symbol("quote") {
+            return;
+        }
+        if head.is_symbol("define") {
+            if let Some(definition) = self.definition(tail) {
+                definitions.push(definition);
+                return;
+            }
+        }
+        let mut rest = expr;
+        while let Object::Pair(head, tail) = rest {
+            self.definitions(head, definitions);
+            rest = tail;
+        }
+    }
+
+    /// Makes the definition from the arguments of `define`
+    fn definition(&mut self, tail: &Rc<Object>) -> Option<Definition> {
+        let (target, body) = match tail.as_ref() {
+            Object::Pair(target, body) => (target, body),
+            _ => return None,
+        };
+        let (name, args) = match target.as_ref() {
+            Object::Symbol(name) => (name, lambda_args(body)),
+            Object::Pair(name, args) => match name.as_ref() {
+                Object::Symbol(name) => (name, Some(args.clone())),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if let Some(location) = source::location(tail) {
+            let line = location.line as usize - 1;
+            if line > self.cursor.line {
+                self.cursor = Position::new(line, 0);
+            }
+        }
+        let keyword = self.text.find(self.cursor, "define")?;
+        let selection = self.text.find(keyword.1, name.as_str())?;
+        self.cursor = selection.1;
+        let mut children = vec![];
+        let mut rest = body.as_ref();
+        while let Object::Pair(head, tail) = rest {
+            self.definitions(head, &mut children);
+            rest = tail;
+        }
+        let range = match self.text.open_paren(keyword.0) {
+            Some(open) => (open, self.text.list_end(open)),
+            None => (keyword.0, selection.1),
+        };
+        let name = name.to_string();
+        Some(Definition { name, args, range, selection, children })
+    }
+}
+
+/// Returns the formal arguments if the body of `define` is a lambda
+fn lambda_args(body: &Object) -> Option<Rc<Object>> {
+    match body {
+        Object::Pair(value, rest) if rest.is_nil() => match value.as_ref() {
+            Object::Pair(head, tail) if head.is_symbol("lambda") => match tail.as_ref() {
+                Object::Pair(args, _) => Some(args.clone()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Document {
+    /// Parses the source, the definitions of the previous version are kept if it can't be parsed
+    fn new(source: &str, previous: Option<Document>) -> Self {
+        let text = Rc::new(Text::new(source));
+        match parser::parse_lines(source, None) {
+            Ok(program) => {
+                let mut outline = Outline { text: &text, cursor: Position::new(0, 0) };
+                let mut definitions = vec![];
+                for expr in &program {
+                    outline.definitions(expr, &mut definitions);
+                }
+                Document { outline: text.clone(), text, definitions, error: None }
+            }
+            Err((err, line)) => {
+                let (definitions, outline) = match previous {
+                    Some(previous) => (previous.definitions, previous.outline),
+                    None => (vec![], text.clone()),
+                };
+                let error = Some((err.to_string(), line as usize - 1));
+                Document { text, definitions, outline, error }
+            }
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.iter().flat_map(Definition::all)
+    }
+
+    fn diagnostics(&self) -> Value {
+        let diagnostics = (self.error.iter())
+            .map(|(message, line)| {
+                let range = self.text.json_range(self.text.line_range(*line));
+                json!({ "range": range, "severity": 1, "source": "scheme", "message": message })
+            })
+            .collect::<Vec<_>>();
+        json!(diagnostics)
+    }
+}
+
+impl Server {
+    fn respond(&mut self, id: &Value, result: Result<Value, Failure>) -> std::io::Result<()> {
+        let mut response = json!({ "jsonrpc": "2.0", "id": id });
+        match result {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({ "code": code, "message": message }),
+        }
+        self.connection.send(&response)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> std::io::Result<()> {
+        let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        self.connection.send(&notification)
+    }
+
+    fn notified(&mut self, method: &str, params: &Value) -> std::io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            "textDocument/didChange" => (params["contentChanges"].as_array())
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                let params = json!({ "uri": uri, "diagnostics": [] });
+                return self.notify("textDocument/publishDiagnostics", params);
+            }
+            _ => return Ok(()),
+        };
+        if let Some(text) = text {
+            let document = Document::new(text, self.documents.remove(uri));
+            let params = json!({ "uri": uri, "diagnostics": document.diagnostics() });
+            self.documents.insert(uri.to_string(), document);
+            self.notify("textDocument/publishDiagnostics", params)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, Failure> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "scheme" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => {
+                let document = self.document(params)?;
+                let symbols = (document.definitions.iter())
+                    .map(|definition| definition.symbol(&document.outline))
+                    .collect::<Vec<_>>();
+                Ok(json!(symbols))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("{} is not supported", method))),
+        }
+    }
+
+    fn document(&self, params: &Value) -> Result<&Document, Failure> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        (self.documents.get(uri)).ok_or_else(|| (INVALID_PARAMS, format!("{} is not opened", uri)))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, Failure> {
+        let document = self.document(params)?;
+        let position = document.text.position(&params["position"]);
+        let name = match document.text.symbol_at(position) {
+            Some((name, _)) => name,
+            None => return Ok(Value::Null),
+        };
+        let uri = &params["textDocument"]["uri"];
+        let locations = (document.all())
+            .filter(|definition| definition.name == name)
+            .map(|definition| json!({ "uri": uri, "range": document.outline.json_range(definition.selection) }))
+            .collect::<Vec<_>>();
+        Ok(json!(locations))
+    }
+
+    /// Describes a name defined in the document, a special form or a global
+    fn describe(&self, document: &Document, name: &str) -> Option<String> {
+        if let Some(definition) = document.all().find(|definition| definition.name == name) {
+            return Some(definition.describe());
+        }
+        if SPECIAL_FORMS.contains(&name) {
+            return Some(format!("{}: special form", name));
+        }
+        let value = self.interpreter.scope().get(name)?;
+        Some(describe(name, &value))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, Failure> {
+        let document = self.document(params)?;
+        let position = document.text.position(&params["position"]);
+        let hover = (document.text.symbol_at(position)).and_then(|(name, range)| {
+            let description = self.describe(document, &name)?;
+            let contents = json!({ "kind": "plaintext", "value": description });
+            Some(json!({ "contents": contents, "range": document.text.json_range(range) }))
+        });
+        Ok(hover.unwrap_or(Value::Null))
+    }
+
+    /// Completes the symbol before the position with the names of the document and the globals
+    fn completion(&self, params: &Value) -> Result<Value, Failure> {
+        let document = self.document(params)?;
+        let position = document.text.position(&params["position"]);
+        let prefix = match document.text.symbol_at(position) {
+            Some((name, (start, _))) => name.chars().take(position.column - start.column).collect(),
+            None => String::new(),
+        };
+        let scope = self.interpreter.scope();
+        let names = (document.all())
+            .map(|definition| {
+                let kind = match definition.args {
+                    Some(_) => COMPLETION_FUNCTION,
+                    None => COMPLETION_VARIABLE,
+                };
+                (definition.name.clone(), kind)
+            })
+            .chain(
+                SPECIAL_FORMS
+                    .iter()
+                    .map(|name| (name.to_string(), COMPLETION_KEYWORD)),
+            )
+            .chain(scope.names().into_iter().map(|name| {
+                let kind = match scope.get(name.as_str()).as_deref() {
+                    Some(Object::Function(_)) => COMPLETION_FUNCTION,
+                    _ => COMPLETION_VARIABLE,
+                };
+                (name, kind)
+            }));
+        let mut seen = HashSet::new();
+        let mut items = (names)
+            .filter(|(name, _)| name.starts_with(&prefix) && seen.insert(name.clone()))
+            .map(|(name, kind)| {
+                let detail = self.describe(document, &name);
+                json!({ "label": name, "kind": kind, "detail": detail })
+            })
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| a["label"].as_str().cmp(&b["label"].as_str()));
+        Ok(json!(items))
+    }
+}
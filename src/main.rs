@@ -1,3 +1,214 @@
+use scheme::errors::EvalErr;
+use scheme::interpreter::{Engine, Interpreter};
+use scheme::pretty;
+use scheme::profiler;
+
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "\
+Usage: scheme [options] [script [arguments...]]
+
+Without a script and expressions to evaluate the REPL is started.
+
+Options:
+  -e <expr>     evaluate the expressions, may be given several times
+  -i            start the REPL after the script and the expressions are evaluated
+  -I <dir>      add a directory where `load` looks for files
+  --no-prelude  don't load the prelude
+  --vm          compile the code to bytecode and run it on the VM
+  --lint <file> check the file without evaluating it, print the warnings
+                and exit with a non-zero status if there are any
+  --fmt <file>  print the file formatted, the comments are kept
+  --profile <file>
+                profile the procedures, print the report to stderr at exit
+                and write the folded stacks for flame graphs to the file
+  --dap         serve the Debug Adapter Protocol on the standard input and output
+  --lsp         serve the Language Server Protocol on the standard input and output
+  --            pass the remaining arguments to the program without a script
+  -h, --help    show this message";
+
+/// Exit code for errors during evaluation
+const EXIT_ERROR: i32 = 1;
+/// Exit code for invalid command-line arguments
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Default)]
+struct Options {
+    exprs: Vec<String>,
+    interactive: bool,
+    no_prelude: bool,
+    vm: bool,
+    profile: Option<PathBuf>,
+    lint: Option<String>,
+    fmt: Option<String>,
+    dap: bool,
+    lsp: bool,
+    library_paths: Vec<PathBuf>,
+    script: Option<String>,
+    args: Vec<String>,
+    help: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => {
+                let expr = args.next().ok_or("-e requires an expression")?;
+                options.exprs.push(expr);
+            }
+            "-I" => {
+                let dir = args.next().ok_or("-I requires a directory")?;
+                options.library_paths.push(dir.into());
+            }
+            "-i" => options.interactive = true,
+            "--no-prelude" => options.no_prelude = true,
+            "--vm" => options.vm = true,
+            "--profile" => {
+                let file = args.next().ok_or("--profile requires a file")?;
+                options.profile = Some(file.into());
+            }
+            "--lint" => {
+                let file = args.next().ok_or("--lint requires a file")?;
+                options.lint = Some(file);
+            }
+            "--fmt" => {
+                let file = args.next().ok_or("--fmt requires a file")?;
+                options.fmt = Some(file);
+            }
+            "--dap" => options.dap = true,
+            "--lsp" => options.lsp = true,
+            "-h" | "--help" => options.help = true,
+            "--" => break,
+            s if s.starts_with('-') && s != "-" => return Err(format!("unknown option {}", s)),
+            _ => {
+                options.script = Some(arg);
+                break;
+            }
+        }
+    }
+    options.args = args.collect();
+    Ok(options)
+}
+
+/// Returns the exit status if the program should stop with it
+fn run(options: Options, program: String) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    if options.dap || options.lsp {
+        return serve(&options).map(|()| None);
+    }
+    if let Some(file) = &options.fmt {
+        return format(file).map(|()| None);
+    }
+    let mut command_line = vec![options.script.clone().unwrap_or(program)];
+    command_line.extend(options.args.iter().cloned());
+    let mut builder = Interpreter::builder().command_line(command_line);
+    if options.no_prelude {
+        builder = builder.no_prelude();
+    }
+    if options.vm {
+        builder = builder.engine(Engine::Bytecode);
+    }
+    for dir in &options.library_paths {
+        builder = builder.library_path(dir);
+    }
+    let interpreter = builder.build()?;
+    if let Some(file) = &options.lint {
+        return lint(file, &interpreter);
+    }
+    if options.profile.is_some() {
+        profiler::start();
+    }
+    let result = evaluate(&options, interpreter);
+    if let Some(file) = &options.profile {
+        let profile = profiler::stop();
+        eprintln!("{}", profile);
+        std::fs::write(file, profile.folded())
+            .map_err(|e| format!("can't write the profile to '{}': {}", file.display(), e))?;
+    }
+    result
+}
+
+/// Prints the warnings of the linter for the file, returns the error status if there are any
+fn lint(file: &str, interpreter: &Interpreter) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    let source =
+        std::fs::read_to_string(file).map_err(|_| format!("file '{}' cannot be opened", file))?;
+    let warnings = scheme::lint::lint(&source, Some(file), interpreter.scope());
+    for warning in &warnings {
+        println!("{}", warning);
+    }
+    Ok(if warnings.is_empty() {
+        None
+    } else {
+        Some(EXIT_ERROR)
+    })
+}
+
+/// Prints the formatted source of the file
+fn format(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source =
+        std::fs::read_to_string(file).map_err(|_| format!("file '{}' cannot be opened", file))?;
+    let formatted = pretty::format_source(&source, pretty::DEFAULT_WIDTH)
+        .map_err(|(err, line)| format!("{}:{}: {}", file, line, err))?;
+    print!("{}", formatted);
+    Ok(())
+}
+
+/// Serves the debug adapter or the language server to an editor
+#[cfg(feature = "ide")]
+fn serve(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let input = Box::new(std::io::stdin().lock());
+    let output = Box::new(std::io::stdout());
+    if options.lsp {
+        scheme::lsp::serve(input, output)
+    } else {
+        scheme::dap::serve(input, output)
+    }
+}
+
+#[cfg(not(feature = "ide"))]
+fn serve(_: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    Err("the debug adapter and the language server are not included in this build".into())
+}
+
+/// Evaluates the expressions and the script and starts the REPL if it is needed
+fn evaluate(
+    options: &Options, interpreter: Interpreter,
+) -> Result<Option<i32>, Box<dyn std::error::Error>> {
+    for expr in &options.exprs {
+        interpreter.eval_str(expr)?;
+    }
+    if let Some(script) = &options.script {
+        interpreter.eval_file(script)?;
+    }
+    if options.interactive || (options.script.is_none() && options.exprs.is_empty()) {
+        return Ok(scheme::run_repl(interpreter));
+    }
+    Ok(None)
+}
+
 fn main() {
-    scheme::repl();
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| "scheme".to_string());
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("scheme: {}\n\n{}", err, USAGE);
+            exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    match run(options, program) {
+        Ok(code) => exit(code.unwrap_or(0)),
+        Err(err) => match err.downcast_ref::<EvalErr>() {
+            Some(EvalErr::Exit(code)) => exit(*code),
+            _ => {
+                eprintln!("Error: {}", err);
+                exit(EXIT_ERROR);
+            }
+        },
+    }
 }
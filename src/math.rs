@@ -1,29 +1,35 @@
+use crate::convert::{from_args, FromScheme, IntoScheme};
 use crate::errors::EvalErr;
 use crate::object::Number::{Float, Integer};
-use crate::object::Object::Boolean;
 use crate::object::{List, Number, Object};
-use crate::service::{expect_1_arg, expect_2_args};
+use crate::service::expect_1_arg;
 
 use std::rc::Rc;
 
 pub fn is_number(args: List) -> Result<Rc<Object>, EvalErr> {
     let arg = expect_1_arg(args, "number?")?;
-    Ok(Rc::new(Boolean(matches!(arg.as_ref(), Object::Number(_)))))
+    Ok(matches!(arg.as_ref(), Object::Number(_)).into_scheme())
 }
 
 pub fn is_integer(args: List) -> Result<Rc<Object>, EvalErr> {
     let arg = expect_1_arg(args, "integer?")?;
-    Ok(Rc::new(Boolean(matches!(
-        arg.as_ref(),
-        Object::Number(Number::Integer(_))
-    ))))
+    Ok(matches!(arg.as_ref(), Object::Number(Integer(_))).into_scheme())
 }
 
 pub fn is_real(args: List) -> Result<Rc<Object>, EvalErr> {
     is_number(args)
 }
 
-fn get_float(num: &Number) -> f64 {
+/// Converts all arguments of a numeric function to numbers
+fn numbers(args: &[Rc<Object>], name: &str) -> Result<Vec<Number>, EvalErr> {
+    (args.iter())
+        .map(|arg| {
+            Number::from_scheme(arg).map_err(|_| EvalErr::NumericArgsRequiredFor(name.to_string()))
+        })
+        .collect()
+}
+
+pub(crate) fn get_float(num: &Number) -> f64 {
     match num {
         Integer(x) => *x as f64,
         Float(x) => *x,
@@ -33,26 +39,8 @@ fn get_float(num: &Number) -> f64 {
 fn num_predicate(
     vec: List, name: &str, f: fn(&Number, &Number) -> bool,
 ) -> Result<Rc<Object>, EvalErr> {
-    if vec.len() < 2 {
-        Err(EvalErr::NeedAtLeastArgs(name.to_string(), 2, vec.len()))
-    } else if let Object::Number(first) = vec[0].as_ref() {
-        let mut last = first;
-        let mut result = true;
-        for x in &vec[1..] {
-            if let Object::Number(x) = x.as_ref() {
-                if !f(last, x) {
-                    result = false;
-                    break;
-                }
-                last = x;
-            } else {
-                return Err(EvalErr::NumericArgsRequiredFor(name.to_string()));
-            }
-        }
-        Ok(Rc::new(Boolean(result)))
-    } else {
-        Err(EvalErr::NumericArgsRequiredFor(name.to_string()))
-    }
+    let nums = numbers(&vec, name)?;
+    Ok(nums.windows(2).all(|w| f(&w[0], &w[1])).into_scheme())
 }
 
 pub fn num_equal(n1: &Number, n2: &Number) -> bool {
@@ -76,98 +64,83 @@ pub fn num_greater(args: List) -> Result<Rc<Object>, EvalErr> {
 
 pub fn num_plus(args: List) -> Result<Rc<Object>, EvalErr> {
     let mut acc = Integer(0);
-    for n in args {
-        if let Object::Number(n) = n.as_ref() {
-            acc = match (acc, n) {
-                (Integer(a), Integer(b)) => Integer(a + *b),
-                (a, b) => Float(get_float(&a) + get_float(b)),
-            }
-        } else {
-            return Err(EvalErr::NumericArgsRequiredFor("+".to_string()));
+    for n in numbers(&args, "+")? {
+        acc = match (acc, n) {
+            (Integer(a), Integer(b)) => Integer(a + b),
+            (a, b) => Float(get_float(&a) + get_float(&b)),
         }
     }
-    Ok(Rc::new(Object::Number(acc)))
+    Ok(acc.into_scheme())
 }
 
 pub fn num_mul(args: List) -> Result<Rc<Object>, EvalErr> {
     let mut acc = Integer(1);
-    for n in args {
-        if let Object::Number(n) = n.as_ref() {
-            acc = match (acc, n) {
-                (Integer(a), Integer(b)) => Integer(a * *b),
-                (a, b) => Float(get_float(&a) * get_float(b)),
-            }
-        } else {
-            return Err(EvalErr::NumericArgsRequiredFor("*".to_string()));
+    for n in numbers(&args, "*")? {
+        acc = match (acc, n) {
+            (Integer(a), Integer(b)) => Integer(a * b),
+            (a, b) => Float(get_float(&a) * get_float(&b)),
         }
     }
-    Ok(Rc::new(Object::Number(acc)))
+    Ok(acc.into_scheme())
 }
 
 pub fn num_minus(vec: List) -> Result<Rc<Object>, EvalErr> {
+    let nums = numbers(&vec, "-")?;
     let mut result = Integer(0);
-    for n in 0..vec.len() {
-        if let Object::Number(x) = vec.get(n).unwrap().as_ref() {
-            if n == 0 && vec.len() > 1 {
-                result = *x;
-            } else {
-                result = match (result, x) {
-                    (Integer(a), Integer(b)) => Integer(a - *b),
-                    (a, b) => Float(get_float(&a) - get_float(b)),
-                };
-            }
+    for (n, x) in nums.iter().enumerate() {
+        if n == 0 && nums.len() > 1 {
+            result = *x;
         } else {
-            return Err(EvalErr::NumericArgsRequiredFor("-".to_string()));
+            result = match (result, x) {
+                (Integer(a), Integer(b)) => Integer(a - *b),
+                (a, b) => Float(get_float(&a) - get_float(b)),
+            };
         }
     }
-    Ok(Rc::new(Object::Number(result)))
+    Ok(result.into_scheme())
 }
 
 pub fn num_div(vec: List) -> Result<Rc<Object>, EvalErr> {
+    let nums = numbers(&vec, "/")?;
     let mut result = 1.0;
-    for n in 0..vec.len() {
-        if let Object::Number(x) = vec[n].as_ref() {
-            if n == 0 && vec.len() > 1 {
-                result = get_float(x);
-            } else {
-                let x = get_float(x);
-                if x == 0.0 {
-                    return Err(EvalErr::DivisionByZero());
-                }
-                result /= x;
-            }
+    for (n, x) in nums.iter().enumerate() {
+        if n == 0 && nums.len() > 1 {
+            result = get_float(x);
         } else {
-            return Err(EvalErr::NumericArgsRequiredFor("/".to_string()));
+            let x = get_float(x);
+            if x == 0.0 {
+                return Err(EvalErr::DivisionByZero());
+            }
+            result /= x;
         }
     }
-    Ok(Rc::new(Object::Number(Float(result))))
+    Ok(result.into_scheme())
 }
 
 fn check_int_div(vec: List, name: &str) -> Result<(i64, i64), EvalErr> {
-    let (n, d) = expect_2_args(vec, name)?;
-    if let (Object::Number(Integer(n)), Object::Number(Integer(d))) = (n.as_ref(), d.as_ref()) {
-        if *d == 0 {
-            return Err(EvalErr::DivisionByZero());
-        }
-        Ok((*n, *d))
-    } else {
-        Err(EvalErr::IntegerArgsRequiredFor(name.to_string()))
+    let (n, d) = from_args(&vec, name).map_err(|err| match err {
+        EvalErr::TypeMismatch(..) => EvalErr::IntegerArgsRequiredFor(name.to_string()),
+        err => err,
+    })?;
+    if d == 0 {
+        return Err(EvalErr::DivisionByZero());
     }
+    Ok((n, d))
 }
 
 pub fn quotient(vec: List) -> Result<Rc<Object>, EvalErr> {
     let (n, d) = check_int_div(vec, "quotient")?;
-    Ok(Rc::new(Object::Number(Integer(n / d))))
+    Ok((n / d).into_scheme())
 }
 
 pub fn remainder(vec: List) -> Result<Rc<Object>, EvalErr> {
     let (n, d) = check_int_div(vec, "remainder")?;
-    Ok(Rc::new(Object::Number(Integer(n - n / d * d))))
+    Ok((n - n / d * d).into_scheme())
 }
 
 pub fn modulo(vec: List) -> Result<Rc<Object>, EvalErr> {
     let (n, d) = check_int_div(vec, "modulo")?;
     let q = ((n as f64) / (d as f64)).floor() as i64;
     let rem = n - d * q;
-    Ok(Rc::new(Object::Number(Integer(rem))))
+    Ok(rem.into_scheme())
 }
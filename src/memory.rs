@@ -0,0 +1,239 @@
+//! Allocation statistics and heap introspection.
+//!
+//! Objects are counted by type when they are made and dropped, `Nil` is not counted.
+//! Sizes are estimated from the size of the objects and the strings they own.
+//! The counters are per thread, shared by all interpreters of the thread.
+
+use crate::convert::IntoScheme;
+use crate::errors::EvalErr;
+use crate::gc::{self, GcStats, Node};
+use crate::object::{List, Object};
+use crate::scope::Scope;
+use crate::service::expect_args;
+use crate::symbol::Symbol;
+
+use ahash::RandomState;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// Names of the counted types of objects
+pub const OBJECT_TYPES: [&str; 7] = [
+    "boolean", "symbol", "string", "number", "pair", "function", "foreign",
+];
+
+/// The size of an object with the reference counters
+const OBJECT_SIZE: usize = std::mem::size_of::<Object>() + 2 * std::mem::size_of::<usize>();
+
+#[derive(Default)]
+struct Counters {
+    allocated: [Cell<u64>; OBJECT_TYPES.len()],
+    live: [Cell<u64>; OBJECT_TYPES.len()],
+    bytes: Cell<u64>,
+    peak_bytes: Cell<u64>,
+    scopes: Cell<u64>,
+    frames: Cell<u64>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = Counters::default();
+}
+
+/// Allocation statistics of the current thread
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryStats {
+    /// Counters for every type of objects in the order of `OBJECT_TYPES`
+    pub objects: Vec<ObjectStats>,
+    /// The number of live scopes of the tree-walker
+    pub scopes: u64,
+    /// The number of live frames of the analyzer and the VM
+    pub frames: u64,
+    /// The estimated size of the live objects in bytes
+    pub bytes: u64,
+    /// The maximal size of the live objects
+    pub peak_bytes: u64,
+    pub gc: GcStats,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectStats {
+    pub type_name: &'static str,
+    /// The number of objects made since the start of the thread
+    pub allocated: u64,
+    /// The number of objects that are not dropped yet
+    pub live: u64,
+}
+
+/// Objects reachable from a scope grouped by type
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapDump {
+    /// Types sorted by the size, the largest first
+    pub entries: Vec<HeapEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapEntry {
+    pub type_name: &'static str,
+    pub count: u64,
+    /// The estimated size in bytes
+    pub bytes: u64,
+}
+
+fn type_index(obj: &Object) -> Option<usize> {
+    match obj {
+        Object::Nil => None,
+        Object::Boolean(_) => Some(0),
+        Object::Symbol(_) => Some(1),
+        Object::String(_) => Some(2),
+        Object::Number(_) => Some(3),
+        Object::Pair(..) => Some(4),
+        Object::Function(_) => Some(5),
+        Object::Foreign(_) => Some(6),
+    }
+}
+
+fn object_bytes(obj: &Object) -> usize {
+    match obj {
+        Object::String(s) => OBJECT_SIZE + s.capacity(),
+        _ => OBJECT_SIZE,
+    }
+}
+
+pub(crate) fn allocated(obj: &Object) {
+    if let Some(i) = type_index(obj) {
+        COUNTERS.with(|c| {
+            c.allocated[i].set(c.allocated[i].get() + 1);
+            c.live[i].set(c.live[i].get() + 1);
+            let bytes = c.bytes.get() + object_bytes(obj) as u64;
+            c.bytes.set(bytes);
+            c.peak_bytes.set(c.peak_bytes.get().max(bytes));
+        });
+    }
+}
+
+/// Objects may be dropped when thread-local variables are already destroyed
+pub(crate) fn freed(obj: &Object) {
+    if let Some(i) = type_index(obj) {
+        let _ = COUNTERS.try_with(|c| {
+            c.live[i].set(c.live[i].get() - 1);
+            c.bytes.set(c.bytes.get() - object_bytes(obj) as u64);
+        });
+    }
+}
+
+/// The number of objects of all types that are alive, counted for all the interpreters of the thread
+pub(crate) fn live_objects() -> u64 {
+    COUNTERS.with(|c| c.live.iter().map(Cell::get).sum())
+}
+
+pub(crate) fn scope_created() {
+    COUNTERS.with(|c| c.scopes.set(c.scopes.get() + 1));
+}
+
+pub(crate) fn scope_dropped() {
+    let _ = COUNTERS.try_with(|c| c.scopes.set(c.scopes.get() - 1));
+}
+
+pub(crate) fn frame_created() {
+    COUNTERS.with(|c| c.frames.set(c.frames.get() + 1));
+}
+
+pub(crate) fn frame_dropped() {
+    let _ = COUNTERS.try_with(|c| c.frames.set(c.frames.get() - 1));
+}
+
+/// Allocation statistics of the current thread
+pub fn stats() -> MemoryStats {
+    COUNTERS.with(|c| MemoryStats {
+        objects: (OBJECT_TYPES.iter().enumerate())
+            .map(|(i, type_name)| ObjectStats {
+                type_name,
+                allocated: c.allocated[i].get(),
+                live: c.live[i].get(),
+            })
+            .collect(),
+        scopes: c.scopes.get(),
+        frames: c.frames.get(),
+        bytes: c.bytes.get(),
+        peak_bytes: c.peak_bytes.get(),
+        gc: gc::stats(),
+    })
+}
+
+/// Counts the objects, scopes and frames reachable from the scope.
+/// Objects referenced only by the code of functions are not counted.
+pub fn heap_dump(root: &Rc<Scope>) -> HeapDump {
+    let mut counts: Vec<(&'static str, u64, u64)> = vec![];
+    let mut add = |type_name: &'static str, bytes: usize| {
+        let i = (counts.iter().position(|entry| entry.0 == type_name)).unwrap_or_else(|| {
+            counts.push((type_name, 0, 0));
+            counts.len() - 1
+        });
+        counts[i].1 += 1;
+        counts[i].2 += bytes as u64;
+    };
+    let mut visited: HashSet<*const (), RandomState> = HashSet::default();
+    let mut work = vec![Node::Scope(root.clone())];
+    while let Some(node) = work.pop() {
+        if !visited.insert(node.id()) {
+            continue;
+        }
+        match &node {
+            Node::Object(obj) => match type_index(obj) {
+                Some(i) => add(OBJECT_TYPES[i], object_bytes(obj)),
+                None => continue,
+            },
+            Node::Scope(scope) => add("scope", scope.size()),
+            Node::Frame(frame) => add("frame", frame.size()),
+        }
+        node.children(&mut work);
+    }
+    let mut entries = (counts.into_iter())
+        .map(|(type_name, count, bytes)| HeapEntry { type_name, count, bytes })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.type_name.cmp(b.type_name)));
+    HeapDump { entries }
+}
+
+impl Display for HeapDump {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "{:<10} {:>10} {:>12}", "type", "count", "bytes")?;
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:<10} {:>10} {:>12}",
+                entry.type_name, entry.count, entry.bytes
+            )?;
+        }
+        let count: u64 = self.entries.iter().map(|e| e.count).sum();
+        let bytes: u64 = self.entries.iter().map(|e| e.bytes).sum();
+        write!(f, "{:<10} {:>10} {:>12}", "total", count, bytes)
+    }
+}
+
+/// Returns the statistics as an association list:
+/// `((allocated (boolean . n) ...) (live (boolean . n) ...) (scopes . n) (frames . n) ...)`
+pub fn memory_stats(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "memory-stats", 0)?;
+    let stats = stats();
+    let entry = |name: &str, value: Rc<Object>| {
+        Object::cons(Symbol::intern(name).into_scheme(), value).into_scheme()
+    };
+    let by_type = |count: fn(&ObjectStats) -> u64| {
+        (stats.objects.iter())
+            .map(|s| entry(s.type_name, (count(s) as i64).into_scheme()))
+            .collect::<Vec<_>>()
+            .into_scheme()
+    };
+    let counters = vec![
+        entry("allocated", by_type(|s| s.allocated)),
+        entry("live", by_type(|s| s.live)),
+        entry("scopes", (stats.scopes as i64).into_scheme()),
+        entry("frames", (stats.frames as i64).into_scheme()),
+        entry("bytes", (stats.bytes as i64).into_scheme()),
+        entry("peak-bytes", (stats.peak_bytes as i64).into_scheme()),
+        entry("collections", (stats.gc.collections as i64).into_scheme()),
+    ];
+    Ok(counters.into_scheme())
+}
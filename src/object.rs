@@ -1,4 +1,7 @@
+use crate::foreign::Foreign;
 use crate::functions::Function;
+use crate::memory;
+use crate::symbol::Symbol;
 use std::fmt::{Debug, Display, Formatter};
 use std::rc::Rc;
 
@@ -8,31 +11,104 @@ pub enum Number {
     Integer(i64),
 }
 
+/// Variants other than `Nil` can't be built outside of the crate, see the constructors below
 #[derive(PartialEq)]
 pub enum Object {
     Nil,
+    #[non_exhaustive]
     Boolean(bool),
-    Symbol(String),
+    #[non_exhaustive]
+    Symbol(Symbol),
+    #[non_exhaustive]
     String(String),
+    #[non_exhaustive]
     Number(Number),
+    #[non_exhaustive]
     Pair(Rc<Object>, Rc<Object>),
+    #[non_exhaustive]
     Function(Function),
+    #[non_exhaustive]
+    Foreign(Foreign),
 }
 
 pub type List = Vec<Rc<Object>>;
 
+/// Objects other than `Nil` are made with the constructors and conversions below,
+/// so they are accounted by the heap limit and the allocation statistics.
 impl Object {
     pub fn make_pair(a: Object, b: Object) -> Object {
-        Object::Pair(Rc::new(a), Rc::new(b))
+        Object::cons(Rc::new(a), Rc::new(b))
+    }
+    pub fn cons(car: Rc<Object>, cdr: Rc<Object>) -> Object {
+        Object::Pair(car, cdr).counted()
     }
     pub fn make_int(value: i64) -> Object {
-        Object::Number(Number::Integer(value))
+        Object::from(Number::Integer(value))
+    }
+    /// Accounts the object which is made directly, every object but `Nil` must be passed here
+    pub(crate) fn counted(self) -> Object {
+        memory::allocated(&self);
+        self
     }
     pub fn is_nil(&self) -> bool {
         *self == Object::Nil
     }
     pub fn is_true(&self) -> bool {
-        *self != Object::Boolean(false)
+        !matches!(self, Object::Boolean(false))
+    }
+    pub fn is_symbol(&self, name: &str) -> bool {
+        matches!(self, Object::Symbol(s) if s.as_str() == name)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(value: bool) -> Self {
+        Object::Boolean(value).counted()
+    }
+}
+
+impl From<Symbol> for Object {
+    fn from(value: Symbol) -> Self {
+        Object::Symbol(value).counted()
+    }
+}
+
+impl From<String> for Object {
+    fn from(value: String) -> Self {
+        Object::String(value).counted()
+    }
+}
+
+impl From<Number> for Object {
+    fn from(value: Number) -> Self {
+        Object::Number(value).counted()
+    }
+}
+
+impl From<Function> for Object {
+    fn from(value: Function) -> Self {
+        Object::Function(value).counted()
+    }
+}
+
+thread_local! {
+    static NIL: Rc<Object> = Rc::new(Object::Nil);
+}
+
+/// Long lists are dropped in a loop instead of recursion that could overflow the stack
+impl Drop for Object {
+    fn drop(&mut self) {
+        memory::freed(self);
+        if let Object::Pair(_, cdr) = self {
+            let nil = || NIL.try_with(Rc::clone).unwrap_or_else(|_| Rc::new(Object::Nil));
+            let mut tail = std::mem::replace(cdr, nil());
+            while let Ok(mut obj) = Rc::try_unwrap(tail) {
+                match &mut obj {
+                    Object::Pair(_, cdr) => tail = std::mem::replace(cdr, nil()),
+                    _ => break,
+                }
+            }
+        }
     }
 }
 
@@ -48,6 +124,7 @@ impl Debug for Object {
             Object::Number(Number::Integer(v)) => write!(f, "{}", v),
             Object::Pair(a, b) => write!(f, "({:?} . {:?})", a, b),
             Object::Function(_) => write!(f, "<function>"),
+            Object::Foreign(x) => write!(f, "#<{}>", x.type_name()),
         }
     }
 }
@@ -81,7 +158,7 @@ mod tests {
     fn test_format() {
         assert_eq!(format!("{}", Object::Nil), "()");
 
-        let obj = Object::make_pair(Object::Boolean(true), Object::Boolean(false));
+        let obj = Object::make_pair(Object::from(true), Object::from(false));
         assert_eq!(format!("{}", obj), "(#t . #f)");
 
         let obj = Object::make_pair(Object::make_int(1), Object::Nil);
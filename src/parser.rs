@@ -1,9 +1,12 @@
 use super::object::*;
 use crate::errors::ParseErr;
-use std::fmt::Debug;
+use crate::source::{self, Location};
+use crate::symbol::Symbol;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
 
 #[derive(PartialEq, Debug)]
-enum Token {
+pub(crate) enum Token {
     Lpar,
     Rpar,
     Dot,
@@ -12,25 +15,33 @@ enum Token {
     Float(f64),
     Symbol(String),
     String(String),
+    /// The text of a comment from `;` to the end of the line, it is skipped by the parser
+    Comment(String),
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
-            Token::Lpar => "(".to_string(),
-            Token::Rpar => ")".to_string(),
-            Token::Dot => ".".to_string(),
-            Token::Quote => "'".to_string(),
-            Token::Integer(x) => x.to_string(),
-            Token::Float(x) => x.to_string(),
-            Token::Symbol(x) => x.into(),
-            Token::String(x) => x.into(),
+            Token::Lpar => write!(f, "("),
+            Token::Rpar => write!(f, ")"),
+            Token::Dot => write!(f, "."),
+            Token::Quote => write!(f, "'"),
+            Token::Integer(x) => write!(f, "{}", x),
+            Token::Float(x) => write!(f, "{}", x),
+            Token::Symbol(x) => write!(f, "{}", x),
+            Token::String(x) => write!(f, "{}", x),
+            Token::Comment(x) => write!(f, "{}", x),
         }
     }
 }
 
 const SYMBOLS_ALLOWED: &str = "+-.*/<=>!?:$%_&~^#";
 
+/// Checks if the character can be a part of a symbol or a number
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || SYMBOLS_ALLOWED.contains(c)
+}
+
 fn try_parse_number(s: String) -> Token {
     s.parse::<i64>().map_or_else(
         |_| {
@@ -68,33 +79,41 @@ fn parse_symbol(source: &[char]) -> (usize, String) {
     (ptr - 1, source[..ptr].iter().collect())
 }
 
-fn tokenize(source: &str) -> Result<Vec<Token>, ParseErr> {
+/// Splits the source into tokens with the lines they start on, comments are kept
+pub(crate) fn tokenize(source: &str) -> Result<Vec<(Token, u32)>, (ParseErr, u32)> {
     let chars = &source.chars().collect::<Vec<char>>()[..];
-    let mut result = Vec::<Token>::new();
+    let mut result = Vec::<(Token, u32)>::new();
     let mut ptr = 0;
+    let mut line = 1;
+    let mut counted = 0;
     loop {
         if ptr < chars.len() {
+            line += chars[counted..ptr].iter().filter(|&&c| c == '\n').count() as u32;
+            counted = ptr;
             let c = chars[ptr];
             if c == ';' {
-                while ptr < chars.len() && chars[ptr] != '\n' {
+                let start = ptr;
+                while ptr + 1 < chars.len() && chars[ptr + 1] != '\n' {
                     ptr += 1;
                 }
+                let text = chars[start..=ptr].iter().collect::<String>();
+                result.push((Token::Comment(text.trim_end().to_string()), line));
             } else if c == '(' {
-                result.push(Token::Lpar);
+                result.push((Token::Lpar, line));
             } else if c == ')' {
-                result.push(Token::Rpar);
+                result.push((Token::Rpar, line));
             } else if c == '\'' {
-                result.push(Token::Quote);
+                result.push((Token::Quote, line));
             } else if c == '.' {
-                result.push(Token::Dot);
+                result.push((Token::Dot, line));
             } else if c == '"' {
-                let (p, token) = parse_string(&chars[ptr + 1..])?;
+                let (p, token) = parse_string(&chars[ptr + 1..]).map_err(|err| (err, line))?;
                 ptr += p;
-                result.push(token);
-            } else if c.is_alphanumeric() || SYMBOLS_ALLOWED.contains(c) {
+                result.push((token, line));
+            } else if is_symbol_char(c) {
                 let r = parse_symbol(&chars[ptr..]);
                 ptr += r.0;
-                result.push(try_parse_number(r.1));
+                result.push((try_parse_number(r.1), line));
             }
             ptr += 1;
         } else {
@@ -117,40 +136,81 @@ fn tokenize(source: &str) -> Result<Vec<Token>, ParseErr> {
  * The main parsing function.
  */
 pub fn parse_expression(source: &str) -> Result<Vec<Object>, ParseErr> {
-    let tokens = &mut tokenize(source)?.into_iter();
+    parse_source(source, None)
+}
+
+/// Parses the source read from the file, the file name is kept in the locations of the lists.
+pub fn parse_source(source: &str, file: Option<&str>) -> Result<Vec<Object>, ParseErr> {
+    parse_lines(source, file).map_err(|(err, _)| err)
+}
+
+/// Parses the source like `parse_source`, the error comes with the line where it is found.
+/// Errors caused by the end of input are reported on the line of the last token.
+pub fn parse_lines(source: &str, file: Option<&str>) -> Result<Vec<Object>, (ParseErr, u32)> {
+    let tokens = &mut Tokens {
+        iter: tokenize(source)?.into_iter(),
+        file: file.map(Rc::from),
+        line: 1,
+    };
     let mut program = vec![];
-    while let Some(t) = tokens.next() {
-        program.push(parse_object(t, tokens)?);
+    while let Some((t, line)) = tokens.next() {
+        program.push(parse_object(t, line, tokens).map_err(|err| (err, tokens.line))?);
     }
     Ok(program)
 }
 
+/// Tokens with their lines and the file they are read from, comments are skipped
+struct Tokens {
+    iter: std::vec::IntoIter<(Token, u32)>,
+    file: Option<Rc<str>>,
+    /// The line of the last read token
+    line: u32,
+}
+
+impl Iterator for Tokens {
+    type Item = (Token, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = (self.iter.by_ref()).find(|(token, _)| !matches!(token, Token::Comment(_)));
+        if let Some((_, line)) = &next {
+            self.line = *line;
+        }
+        next
+    }
+}
+
 /*
  * object  ::=  (list
  * object  ::=  'object
  * object  ::=  number | symbol | string
  */
-fn parse_object(first: Token, rest: &mut dyn Iterator<Item = Token>) -> Result<Object, ParseErr> {
+fn parse_object(first: Token, line: u32, rest: &mut Tokens) -> Result<Object, ParseErr> {
     match first {
         Token::Symbol(s) => Ok(match s.as_str() {
-            "#t" => Object::Boolean(true),
-            "#f" => Object::Boolean(false),
-            _ => Object::Symbol(s),
+            "#t" => Object::from(true),
+            "#f" => Object::from(false),
+            _ => Object::from(Symbol::intern(&s)),
         }),
-        Token::String(s) => Ok(Object::String(s)),
-        Token::Float(value) => Ok(Object::Number(Number::Float(value))),
-        Token::Integer(value) => Ok(Object::Number(Number::Integer(value))),
+        Token::String(s) => Ok(Object::from(s)),
+        Token::Float(value) => Ok(Object::from(Number::Float(value))),
+        Token::Integer(value) => Ok(Object::from(Number::Integer(value))),
         Token::Quote => (rest.next())
-            .map(|token| {
-                let current = parse_object(token, rest)?;
+            .map(|(token, line)| {
+                let current = parse_object(token, line, rest)?;
                 Ok(Object::make_pair(
-                    Object::Symbol("quote".to_string()),
+                    Object::from(Symbol::intern("quote")),
                     Object::make_pair(current, Object::Nil),
                 ))
             })
             .unwrap_or_else(|| Err(ParseErr::Unexpected_EOF)),
         Token::Lpar => (rest.next())
-            .map(|token| parse_list(token, rest))
+            .map(|(token, next_line)| {
+                let list = parse_list(token, next_line, rest)?;
+                if let Object::Pair(_, tail) = &list {
+                    source::record(tail, Location { file: rest.file.clone(), line });
+                }
+                Ok(list)
+            })
             .unwrap_or_else(|| Err(ParseErr::Unexpected_EOF_AfterPars)),
         _ => Err(ParseErr::UnexpectedToken(format!("{:?}", first))),
     }
@@ -161,23 +221,23 @@ fn parse_object(first: Token, rest: &mut dyn Iterator<Item = Token>) -> Result<O
  * list  ::=  . object)
  * list  ::=  object list
  */
-fn parse_list(first: Token, rest: &mut dyn Iterator<Item = Token>) -> Result<Object, ParseErr> {
+fn parse_list(first: Token, line: u32, rest: &mut Tokens) -> Result<Object, ParseErr> {
     match first {
         Token::Rpar => Ok(Object::Nil),
         Token::Dot => (rest.next())
-            .map(|token| {
-                let current = parse_object(token, rest)?;
+            .map(|(token, line)| {
+                let current = parse_object(token, line, rest)?;
                 match rest.next() {
-                    Some(Token::Rpar) => Ok(current),
-                    Some(t) => Err(ParseErr::ClosingParExpected(t.to_string())),
+                    Some((Token::Rpar, _)) => Ok(current),
+                    Some((t, _)) => Err(ParseErr::ClosingParExpected(t.to_string())),
                     None => Err(ParseErr::ClosingParExpected_EOF),
                 }
             })
             .unwrap_or_else(|| Err(ParseErr::Unexpected_EOF_AfterDot)),
         _ => {
-            let head = parse_object(first, rest)?;
+            let head = parse_object(first, line, rest)?;
             rest.next()
-                .map(|token| Ok(Object::make_pair(head, parse_list(token, rest)?)))
+                .map(|(token, line)| Ok(Object::make_pair(head, parse_list(token, line, rest)?)))
                 .unwrap_or_else(|| Err(ParseErr::Unexpected_EOF))
         }
     }
@@ -187,6 +247,7 @@ fn parse_list(first: Token, rest: &mut dyn Iterator<Item = Token>) -> Result<Obj
 #[rustfmt::skip]
 mod tests {
     use super::*;
+    use crate::service::list_to_vec;
 
     fn expect_err(source: &str, expected: ParseErr) {
         let result = parse_expression(source);
@@ -202,31 +263,41 @@ mod tests {
         }
     }
 
+    fn tokens(source: &str) -> Result<Vec<Token>, ParseErr> {
+        Ok(tokenize(source).map_err(|(err, _)| err)?.into_iter().map(|(token, _)| token).collect())
+    }
+
     #[test]
     fn lexer_test() {
-        assert!(tokenize(" \t \n ; qqq ").unwrap().is_empty());
+        assert!(tokens(" \t \n ").unwrap().is_empty());
+        assert_eq!(tokens("; qqq \n;;\n(a ;b").unwrap(),
+                   vec![Token::Comment("; qqq".to_string()), Token::Comment(";;".to_string()),
+                        Token::Lpar, Token::Symbol("a".to_string()), Token::Comment(";b".to_string())]);
 
-        assert_eq!(tokenize("(.')").unwrap(),
+        assert_eq!(tokens("(.')").unwrap(),
                    vec![Token::Lpar, Token::Dot, Token::Quote, Token::Rpar]);
 
-        assert_eq!(tokenize("\"str\" symbol").unwrap(),
+        assert_eq!(tokens("\"str\" symbol").unwrap(),
                    vec![Token::String("str".to_string()),
                         Token::Symbol("symbol".to_string())]);
 
-        assert_eq!(tokenize("42\"hi\"-3.14e-15").unwrap(),
+        assert_eq!(tokens("42\"hi\"-3.14e-15").unwrap(),
                     vec![Token::Integer(42),
                          Token::String("hi".to_string()),
                          Token::Float(-3.14e-15)]);
 
-        assert_eq!(tokenize("4.5.2 --3.14 2-3").unwrap(),
+        assert_eq!(tokens("4.5.2 --3.14 2-3").unwrap(),
                     vec![Token::Symbol("4.5.2".to_string()),
                          Token::Symbol("--3.14".to_string()),
                          Token::Symbol("2-3".to_string())]);
 
-        assert_eq!(tokenize("\"\"").unwrap(), vec![Token::String("".to_string())]);
-        assert_eq!(tokenize("\"❤\"").unwrap(), vec![Token::String("❤".to_string())]);
+        assert_eq!(tokens("\"\"").unwrap(), vec![Token::String("".to_string())]);
+        assert_eq!(tokens("\"❤\"").unwrap(), vec![Token::String("❤".to_string())]);
 
         expect_err("\"   ", ParseErr::UnclosedString);
+
+        let lines = tokenize("(a ; b\n \"c\nd\"\n\n e)").unwrap();
+        assert_eq!(lines.iter().map(|(_, line)| *line).collect::<Vec<_>>(), vec![1, 1, 1, 2, 5, 5]);
     }
 
     #[test]
@@ -239,11 +310,11 @@ mod tests {
                    vec![Object::make_pair(Object::make_int(1), Object::Nil)]);
 
         assert_eq!(parse_expression("#t").unwrap(),
-                    vec![Object::Boolean(true)]);
+                    vec![Object::from(true)]);
 
         assert_eq!(parse_expression("(1 . a)").unwrap(),
                    vec![Object::make_pair(Object::make_int(1),
-                                          Object::Symbol("a".to_string()))]);
+                                          Object::from(Symbol::intern("a")))]);
 
         assert_eq!(parse_expression("1 (2 3) ()").unwrap(),
                    vec![Object::make_int(1),
@@ -255,4 +326,27 @@ mod tests {
         expect_err("(1 .", ParseErr::Unexpected_EOF_AfterDot);
         expect_err("(1 . 2 .", ParseErr::ClosingParExpected(".".to_string()));
     }
+
+    #[test]
+    fn locations_test() {
+        let program = parse_source("(f)\n\n(g\n (h 1))", Some("test.scm")).unwrap();
+        let location = |obj: &Object| match obj {
+            Object::Pair(_, tail) => source::location(tail).map(|l| l.to_string()),
+            _ => None,
+        };
+        assert_eq!(location(&program[0]), Some("test.scm:1".to_string()));
+        assert_eq!(location(&program[1]), Some("test.scm:3".to_string()));
+        let inner = list_to_vec(&program[1]).unwrap();
+        assert_eq!(location(&inner[1]), Some("test.scm:4".to_string()));
+    }
+
+    #[test]
+    fn error_lines_test() {
+        let line = |source| parse_lines(source, None).map(|_| ()).map_err(|(_, line)| line);
+        assert_eq!(line("(a)\n(b\n . c d)"), Err(3));
+        assert_eq!(line("(a\n \"b\n c)"), Err(2));
+        assert_eq!(line("(a\n (b)\n\n"), Err(2));
+        assert_eq!(line("(a\n)"), Ok(()));
+        assert_eq!(line("(a ; b)\n"), Err(1));
+    }
 }
@@ -0,0 +1,30 @@
+//! Output functions. All of them write to the output of the current interpreter.
+
+use crate::context::write_output;
+use crate::errors::EvalErr;
+use crate::object::{List, Object};
+use crate::service::{expect_1_arg, expect_args, undef};
+
+use std::rc::Rc;
+
+/// Prints an object in a human-readable form, strings are printed without quotes
+pub fn display(args: List) -> Result<Rc<Object>, EvalErr> {
+    let arg = expect_1_arg(args, "display")?;
+    match arg.as_ref() {
+        Object::String(s) => write_output(s)?,
+        obj => write_output(&obj.to_string())?,
+    }
+    Ok(undef())
+}
+
+/// Prints an object in the same form as the REPL does
+pub fn write(args: List) -> Result<Rc<Object>, EvalErr> {
+    write_output(&expect_1_arg(args, "write")?.to_string())?;
+    Ok(undef())
+}
+
+pub fn newline(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "newline", 0)?;
+    write_output("\n")?;
+    Ok(undef())
+}
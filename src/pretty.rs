@@ -0,0 +1,286 @@
+//! Pretty-printing of data and formatting of source code.
+//!
+//! A list that doesn't fit in the width is broken into lines. Forms with a body such as
+//! `define`, `lambda` and `let` keep their name, parameters or bindings on the first line
+//! and indent the body by two spaces. Clauses of `cond`, bindings of `let` and arguments
+//! of calls are put on separate lines aligned with the first one, lists of atoms are filled
+//! up to the width.
+
+use crate::context;
+use crate::convert::from_args;
+use crate::errors::{EvalErr, ParseErr};
+use crate::object::{List, Object};
+use crate::parser::{parse_lines, tokenize, Token};
+use crate::service::undef;
+
+use std::rc::Rc;
+
+/// The width used by `pretty-print` without the width argument and by the formatter
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Forms with a body and the number of their arguments kept on the first line
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("define", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("letrec", 1),
+    ("begin", 0),
+];
+
+/// A form being printed
+enum Node {
+    Atom(String),
+    /// Items of a list and the tail of a dotted one
+    List(Vec<Node>, Option<Box<Node>>),
+    Quote(Box<Node>),
+    /// A comment, it is trailing if it follows a form on the same line of the source
+    Comment(String, bool),
+}
+
+impl Node {
+    fn from_object(obj: &Object) -> Node {
+        match obj {
+            Object::Pair(head, tail) => {
+                let mut items = vec![Node::from_object(head)];
+                let mut tail = tail.as_ref();
+                while let Object::Pair(head, next) = tail {
+                    items.push(Node::from_object(head));
+                    tail = next;
+                }
+                let tail = (!tail.is_nil()).then(|| Box::new(Node::from_object(tail)));
+                Node::List(items, tail)
+            }
+            x => Node::Atom(x.to_string()),
+        }
+    }
+
+    /// The node printed on one line, `None` if it contains a comment
+    fn flat(&self) -> Option<String> {
+        match self {
+            Node::Atom(text) => Some(text.clone()),
+            Node::Quote(node) => node.flat().map(|text| format!("'{}", text)),
+            Node::Comment(..) => None,
+            Node::List(items, tail) => {
+                let mut items = items.iter().map(Node::flat).collect::<Option<Vec<_>>>()?;
+                if let Some(tail) = tail {
+                    items.push(format!(". {}", tail.flat()?));
+                }
+                Some(format!("({})", items.join(" ")))
+            }
+        }
+    }
+}
+
+struct Printer {
+    width: usize,
+    out: String,
+}
+
+impl Printer {
+    fn column(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.chars().count()
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    fn print(&mut self, node: &Node) {
+        match node {
+            Node::Atom(text) | Node::Comment(text, _) => self.out.push_str(text),
+            Node::Quote(node) => {
+                self.out.push('\'');
+                self.print(node);
+            }
+            Node::List(items, tail) => match node.flat() {
+                Some(flat) if self.column() + flat.chars().count() <= self.width => {
+                    self.out.push_str(&flat)
+                }
+                _ => self.list(items, tail.as_deref()),
+            },
+        }
+    }
+
+    /// Prints the list broken into lines
+    fn list(&mut self, items: &[Node], tail: Option<&Node>) {
+        let open = self.column();
+        let head = match items.first() {
+            Some(Node::Atom(head)) => Some(head.as_str()),
+            _ => None,
+        };
+        let body = BODY_FORMS.iter().find(|(form, _)| Some(*form) == head);
+        let atoms = items.iter().all(|item| matches!(item, Node::Atom(_)));
+        // the number of items on the first line, the indentation of the next ones
+        // and whether the items are filled up to the width
+        let (inline, indent, fill) = match (head, body) {
+            (_, Some((_, args))) => (1 + args, open + 2, false),
+            _ if atoms => (1, open + 1, true),
+            (Some(head), None) if open + head.chars().count() + 2 < self.width / 2 => {
+                (2, open + head.chars().count() + 2, false)
+            }
+            (Some(_), None) => (1, open + 2, false),
+            (None, None) => (1, open + 1, false),
+        };
+        self.out.push('(');
+        let mut broken = false;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                let fits = |printer: &Printer| match item.flat() {
+                    Some(flat) => printer.column() + 1 + flat.chars().count() <= printer.width,
+                    None => false,
+                };
+                match item {
+                    Node::Comment(_, true) if !broken => self.out.push(' '),
+                    Node::Comment(..) => self.newline(indent),
+                    _ if !broken && (i < inline || (fill && fits(self))) => self.out.push(' '),
+                    _ => self.newline(indent),
+                }
+            }
+            self.print(item);
+            broken = matches!(item, Node::Comment(..));
+        }
+        if let Some(tail) = tail {
+            let fits =
+                matches!(tail.flat(), Some(flat) if self.column() + flat.len() + 3 <= self.width);
+            if broken || !fits {
+                self.newline(indent);
+            } else {
+                self.out.push(' ');
+            }
+            self.out.push_str(". ");
+            self.print(tail);
+            broken = matches!(tail, Node::Comment(..));
+        }
+        if broken {
+            self.newline(indent);
+        }
+        self.out.push(')');
+    }
+}
+
+/// Prints the object breaking the lists that don't fit in the width into lines
+pub fn pretty(obj: &Object, width: usize) -> String {
+    let mut printer = Printer { width, out: String::new() };
+    printer.print(&Node::from_object(obj));
+    printer.out
+}
+
+/// `(pretty-print obj)` or `(pretty-print obj width)` writes the object broken into lines
+/// followed by a newline
+pub fn fn_pretty_print(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (obj, width) = match args.len() {
+        1 => (args[0].clone(), DEFAULT_WIDTH),
+        _ => {
+            let (obj, width): (Rc<Object>, i64) = from_args(&args, "pretty-print")?;
+            (obj, width.max(0) as usize)
+        }
+    };
+    context::write_output(&pretty(&obj, width))?;
+    context::write_output("\n")?;
+    Ok(undef())
+}
+
+/// Tokens of the source being formatted
+struct Reader {
+    tokens: std::vec::IntoIter<(Token, u32)>,
+    /// The line of the last read token
+    line: u32,
+    /// Comments read before the quoted form or the dotted tail which are put before them
+    comments: Vec<Node>,
+}
+
+impl Reader {
+    fn next(&mut self) -> Option<Token> {
+        let (token, line) = self.tokens.next()?;
+        self.line = line;
+        Some(token)
+    }
+
+    /// Reads the node, the source is known to be parsed without errors
+    fn node(&mut self, token: Token) -> Node {
+        match token {
+            Token::Lpar => {
+                let mut items = vec![];
+                let mut tail = None;
+                loop {
+                    let line = self.line;
+                    match self.next() {
+                        Some(Token::Rpar) | None => break,
+                        Some(Token::Comment(text)) => {
+                            items.push(Node::Comment(text, self.line == line))
+                        }
+                        Some(Token::Dot) => {
+                            let token = self.skip_comments();
+                            items.append(&mut self.comments);
+                            tail = token.map(|token| Box::new(self.node(token)));
+                            items.append(&mut self.comments);
+                        }
+                        Some(token) => {
+                            let node = self.node(token);
+                            items.append(&mut self.comments);
+                            items.push(node);
+                        }
+                    }
+                }
+                Node::List(items, tail)
+            }
+            Token::Quote => match self.skip_comments() {
+                Some(token) => Node::Quote(Box::new(self.node(token))),
+                None => Node::Atom("'".to_string()),
+            },
+            Token::Comment(text) => Node::Comment(text, false),
+            Token::String(s) => Node::Atom(format!("\"{}\"", s)),
+            Token::Float(x) => Node::Atom(format!("{:?}", x)),
+            token => Node::Atom(token.to_string()),
+        }
+    }
+
+    /// Returns the next token that is not a comment, the comments are kept to be put before the form
+    fn skip_comments(&mut self) -> Option<Token> {
+        loop {
+            let line = self.line;
+            match self.next()? {
+                Token::Comment(text) => self.comments.push(Node::Comment(text, self.line == line)),
+                token => return Some(token),
+            }
+        }
+    }
+}
+
+/// Formats the source code keeping its comments. Top-level forms are separated by a blank line
+/// if there is at least one between them in the source. Returns the parse error with its line
+/// for a source that can't be parsed.
+pub fn format_source(source: &str, width: usize) -> Result<String, (ParseErr, u32)> {
+    parse_lines(source, None)?;
+    let mut reader = Reader {
+        tokens: tokenize(source)?.into_iter(),
+        line: 0,
+        comments: vec![],
+    };
+    let mut printer = Printer { width, out: String::new() };
+    let mut last_line = None;
+    while let Some(token) = reader.next() {
+        let line = reader.line;
+        let trailing = last_line == Some(line) && matches!(token, Token::Comment(_));
+        match last_line {
+            _ if trailing => printer.out.push(' '),
+            Some(last) if line > last + 1 => printer.out.push_str("\n\n"),
+            Some(_) => printer.out.push('\n'),
+            None => {}
+        }
+        let node = reader.node(token);
+        for comment in reader.comments.drain(..) {
+            printer.print(&comment);
+            printer.out.push('\n');
+        }
+        printer.print(&node);
+        last_line = Some(reader.line);
+    }
+    if !printer.out.is_empty() {
+        printer.out.push('\n');
+    }
+    Ok(printer.out)
+}
@@ -0,0 +1,335 @@
+//! The profiler of procedures defined in Scheme.
+//!
+//! Every engine keeps an `Activation` for the procedure whose body it executes:
+//! it is entered when the procedure is called, a tail call replaces it with the called procedure
+//! and it is exited when the body returns. While the profiler runs, activations count the calls
+//! and measure the time spent in the procedures. Procedures are identified by the name
+//! and the location of their definition. Built-in functions are not profiled,
+//! their time is added to the procedure that called them.
+//!
+//! The profile is per thread, it covers all interpreters of the thread.
+
+use crate::context;
+use crate::convert::FromScheme;
+use crate::errors::EvalErr;
+use crate::functions::Function;
+use crate::object::{List, Object};
+use crate::source::Location;
+use crate::symbol::Symbol;
+use crate::trace;
+
+use ahash::RandomState;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::default());
+}
+
+/// Measurements of a procedure
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcedureProfile {
+    pub name: String,
+    /// Where the procedure is defined
+    pub location: Option<Location>,
+    pub calls: u64,
+    /// The time spent in the procedure and in the procedures it called,
+    /// recursive calls are counted once
+    pub inclusive: Duration,
+    /// The time spent in the procedure itself
+    pub exclusive: Duration,
+}
+
+/// The result of profiling
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    /// Procedures sorted by the exclusive time, the slowest first
+    pub procedures: Vec<ProcedureProfile>,
+    /// Stacks of called procedures separated by `;` with the exclusive time
+    /// of the innermost one in microseconds
+    pub stacks: Vec<(String, u64)>,
+}
+
+#[derive(Default)]
+struct Profiler {
+    procedures: Vec<Procedure>,
+    index: HashMap<(Symbol, Option<Location>), usize, RandomState>,
+    /// The tree of calls, the first node is the root
+    nodes: Vec<CallNode>,
+    /// Procedures that are executed now, the innermost is the last
+    stack: Vec<Running>,
+    /// The number of entered activations, it identifies them in the stack
+    serial: u64,
+}
+
+struct Procedure {
+    name: Symbol,
+    location: Option<Location>,
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+    /// The number of calls in progress, the inclusive time is added when the outermost returns
+    active: u32,
+}
+
+#[derive(Default)]
+struct CallNode {
+    procedure: usize,
+    parent: usize,
+    /// Nodes of the called procedures by their indexes
+    children: HashMap<usize, usize, RandomState>,
+    exclusive: Duration,
+}
+
+struct Running {
+    procedure: usize,
+    node: usize,
+    serial: u64,
+    start: Instant,
+    /// The time spent in the called procedures
+    children: Duration,
+}
+
+/// The procedure executed by a frame of an evaluator, see the module documentation.
+/// It also keeps the traced call whose value is returned by the frame.
+#[derive(Default)]
+pub(crate) struct Activation {
+    profile: Option<(usize, u64)>,
+    trace: Option<trace::Call>,
+}
+
+impl Activation {
+    /// Enters the called procedure replacing the current one
+    pub(crate) fn enter(&mut self, name: &str, location: Option<&Location>) {
+        self.exit();
+        if RUNNING.with(Cell::get) {
+            self.profile = PROFILER.with(|p| p.borrow_mut().enter(name, location));
+        }
+    }
+
+    /// Prints the call if the function is traced
+    pub(crate) fn trace(&mut self, fun: &Function, args: &[Rc<Object>]) -> Result<(), EvalErr> {
+        if let Some(call) = trace::call(fun, args, self.trace.as_ref())? {
+            self.trace = Some(call);
+        }
+        Ok(())
+    }
+
+    /// Prints the value returned by the frame if it made a traced call
+    pub(crate) fn returned(&self, value: &Object) -> Result<(), EvalErr> {
+        match &self.trace {
+            Some(call) => call.returned(value),
+            None => Ok(()),
+        }
+    }
+
+    fn exit(&mut self) {
+        if let Some((depth, serial)) = self.profile.take() {
+            let _ = PROFILER.try_with(|p| p.borrow_mut().exit(depth, serial));
+        }
+    }
+}
+
+impl Drop for Activation {
+    fn drop(&mut self) {
+        self.exit();
+    }
+}
+
+impl Profiler {
+    fn enter(&mut self, name: &str, location: Option<&Location>) -> Option<(usize, u64)> {
+        let key = (Symbol::intern(name), location.cloned());
+        let procedures = &mut self.procedures;
+        let procedure = *self
+            .index
+            .entry(key)
+            .or_insert_with_key(|(name, location)| {
+                procedures.push(Procedure {
+                    name: *name,
+                    location: location.clone(),
+                    calls: 0,
+                    inclusive: Duration::ZERO,
+                    exclusive: Duration::ZERO,
+                    active: 0,
+                });
+                procedures.len() - 1
+            });
+        procedures[procedure].calls += 1;
+        procedures[procedure].active += 1;
+
+        if self.nodes.is_empty() {
+            self.nodes.push(CallNode::default());
+        }
+        let parent = self.stack.last().map_or(0, |running| running.node);
+        let nodes = &mut self.nodes;
+        let count = nodes.len();
+        let node = *nodes[parent].children.entry(procedure).or_insert(count);
+        if node == count {
+            nodes.push(CallNode { procedure, parent, ..CallNode::default() });
+        }
+
+        self.serial += 1;
+        self.stack.push(Running {
+            procedure,
+            node,
+            serial: self.serial,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+        Some((self.stack.len() - 1, self.serial))
+    }
+
+    /// Exits the activation and the ones entered after it that are not exited yet
+    fn exit(&mut self, depth: usize, serial: u64) {
+        if self.stack.get(depth).map(|running| running.serial) != Some(serial) {
+            return;
+        }
+        let now = Instant::now();
+        while self.stack.len() > depth {
+            let running = self.stack.pop().unwrap();
+            let elapsed = now - running.start;
+            let exclusive = elapsed.saturating_sub(running.children);
+            let procedure = &mut self.procedures[running.procedure];
+            procedure.exclusive += exclusive;
+            procedure.active -= 1;
+            if procedure.active == 0 {
+                procedure.inclusive += elapsed;
+            }
+            self.nodes[running.node].exclusive += exclusive;
+            if let Some(caller) = self.stack.last_mut() {
+                caller.children += elapsed;
+            }
+        }
+    }
+
+    fn frame_name(&self, procedure: usize) -> String {
+        let procedure = &self.procedures[procedure];
+        match &procedure.location {
+            Some(location) => format!("{} ({})", procedure.name, location),
+            None => procedure.name.to_string(),
+        }
+    }
+
+    fn profile(&self) -> Profile {
+        let mut procedures = (self.procedures.iter())
+            .map(|p| ProcedureProfile {
+                name: p.name.to_string(),
+                location: p.location.clone(),
+                calls: p.calls,
+                inclusive: p.inclusive,
+                exclusive: p.exclusive,
+            })
+            .collect::<Vec<_>>();
+        procedures.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        let mut stacks = vec![];
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let micros = node.exclusive.as_micros() as u64;
+            if micros == 0 {
+                continue;
+            }
+            let mut frames = vec![];
+            let mut current = i;
+            while current != 0 {
+                frames.push(self.frame_name(self.nodes[current].procedure));
+                current = self.nodes[current].parent;
+            }
+            frames.reverse();
+            stacks.push((frames.join(";"), micros));
+        }
+        stacks.sort();
+        Profile { procedures, stacks }
+    }
+}
+
+/// Starts profiling, the measurements of the previous run are discarded.
+pub fn start() {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let serial = p.serial;
+        *p = Profiler { serial, ..Profiler::default() };
+    });
+    RUNNING.with(|r| r.set(true));
+}
+
+/// Stops profiling and returns the measurements.
+/// Procedures that are still executed are measured up to this moment.
+pub fn stop() -> Profile {
+    RUNNING.with(|r| r.set(false));
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        if let Some(serial) = p.stack.first().map(|running| running.serial) {
+            p.exit(0, serial);
+        }
+        p.profile()
+    })
+}
+
+pub fn is_running() -> bool {
+    RUNNING.with(Cell::get)
+}
+
+impl Profile {
+    /// Stacks in the folded format accepted by flame graph tools: a line per stack
+    /// with the procedures from the outermost separated by `;` and the time in microseconds.
+    pub fn folded(&self) -> String {
+        (self.stacks.iter())
+            .map(|(stack, micros)| format!("{} {}\n", stack, micros))
+            .collect()
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:>10} {:>14} {:>14}  procedure",
+            "calls", "inclusive ms", "exclusive ms"
+        )?;
+        for p in &self.procedures {
+            let location = (p.location.as_ref()).map_or(String::new(), |l| format!(" ({})", l));
+            write!(
+                f,
+                "\n{:>10} {:>14.3} {:>14.3}  {}{}",
+                p.calls,
+                millis(p.inclusive),
+                millis(p.exclusive),
+                p.name,
+                location
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `(profile thunk)` calls the procedure without arguments and prints the profile of the call.
+/// The folded stacks are written to the file if its name is given as the second argument.
+/// Returns the value of the procedure. If the profiler already runs the procedure is just called.
+pub fn fn_profile(args: List) -> Result<Rc<Object>, EvalErr> {
+    let thunk = match args[0].as_ref() {
+        Object::Function(f) => f,
+        x => return Err(EvalErr::IllegalObjectAsAFunction(x.to_string())),
+    };
+    let file = args.get(1).map(String::from_scheme).transpose()?;
+    if file.is_some() && context::is_sandboxed() {
+        return Err(EvalErr::IoError(
+            "files can't be written in the sandbox".to_string(),
+        ));
+    }
+    if is_running() {
+        return thunk.invoke(vec![]);
+    }
+    start();
+    let result = thunk.invoke(vec![]);
+    let profile = stop();
+    let value = result?;
+    context::write_output(&format!("{}\n", profile))?;
+    if let Some(file) = file {
+        std::fs::write(&file, profile.folded()).map_err(|e| EvalErr::IoError(e.to_string()))?;
+    }
+    Ok(value)
+}
@@ -0,0 +1,50 @@
+//! The base protocol shared by the debug adapter and the language server:
+//! JSON messages preceded by the `Content-Length` header.
+
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+pub(crate) struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Connection {
+    pub(crate) fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Connection { input, output }
+    }
+
+    /// Reads the next message, returns `None` at the end of the input.
+    pub(crate) fn read(&mut self) -> std::io::Result<Option<Value>> {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let mut content = vec![0; length.ok_or_else(|| invalid("no Content-Length header"))?];
+        self.input.read_exact(&mut content)?;
+        let message = serde_json::from_slice(&content).map_err(|e| invalid(&e.to_string()))?;
+        Ok(Some(message))
+    }
+
+    pub(crate) fn send(&mut self, message: &Value) -> std::io::Result<()> {
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.output.flush()
+    }
+}
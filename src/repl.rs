@@ -0,0 +1,501 @@
+//! Read-Eval-Print Loop.
+//!
+//! `Repl` accumulates lines of input until they form complete expressions
+//! so an expression may span several lines and a line may contain several expressions.
+//! Lines starting with a comma are meta-commands handled by the REPL itself, see `,help`.
+//!
+//! Breakpoints and `,step` attach a console to the debugger. When the evaluation pauses
+//! the console reads its own commands and expressions evaluated in the paused frame.
+
+use crate::debugger::{self, Breakpoint, DebugHook, Pause, PauseReason, Resume, StackFrame};
+use crate::errors::EvalErr;
+use crate::expand::expand;
+use crate::functions::Function;
+use crate::interpreter::{Engine, Interpreter};
+use crate::object::Object;
+use crate::parser::parse_expression;
+use crate::symbol::Symbol;
+
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const DEBUG_PROMPT: &str = "debug> ";
+
+const HELP: &[&str] = &[
+    ",help            show this message",
+    ",load [file]     evaluate the file, reload the last loaded file without an argument",
+    ",time <expr>     evaluate the expression and show the elapsed time",
+    ",env             list top-level bindings",
+    ",describe <name> show what the name is bound to",
+    ",expand <expr>   expand a derived form one step",
+    ",reset           discard all definitions and load the prelude again",
+    ",heap            count the objects retained by the top-level bindings by type",
+    ",break <target>  pause at a procedure, a line or file:line",
+    ",breakpoints     list breakpoints",
+    ",delete [id]     delete the breakpoint or all of them",
+    ",step <expr>     evaluate the expression pausing at its first step",
+];
+
+const DEBUG_HELP: &[&str] = &[
+    ",step            pause at the next step",
+    ",next            pause at the next step of the current procedure",
+    ",finish          pause when the current procedure returns",
+    ",continue        run until a breakpoint",
+    ",abort           stop the evaluation",
+    ",backtrace       show the frames of the evaluated procedures",
+    ",frame <n>       select the frame for ,locals and expressions",
+    ",locals          show the local bindings of the selected frame",
+    ",break, ,breakpoints and ,delete work as in the REPL,",
+    "other lines are evaluated in the selected frame",
+];
+
+pub struct Repl {
+    interpreter: Interpreter,
+    buffer: String,
+    last_file: Option<PathBuf>,
+    exit_code: Option<i32>,
+    /// The console that is not attached to the debugger yet
+    console: Option<Console>,
+}
+
+/// Talks to the user while the evaluation is paused
+struct Console {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    interactive: bool,
+    /// The index of the selected frame
+    frame: usize,
+}
+
+/// Reads the standard input a line at a time without keeping it locked,
+/// so the REPL and the debugger paused in it read the same input.
+#[derive(Default)]
+pub(crate) struct StdinLines {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+fn error<T: std::fmt::Display>(err: T) -> Vec<String> {
+    vec![format!("Error: {}", err)]
+}
+
+/// Describes a binding, for procedures shows their formal arguments.
+pub(crate) fn describe(name: &str, value: &Object) -> String {
+    match value {
+        Object::Function(Function::Object { name: fn_name, args, .. }) => {
+            let signature = Object::cons(Rc::new(Object::from(Symbol::intern(fn_name))), args.clone());
+            format!("{}: procedure {}", name, signature)
+        }
+        Object::Function(Function::Compiled(closure)) => {
+            let fn_name = Rc::new(Object::from(Symbol::intern(closure.name())));
+            let signature = Object::cons(fn_name, closure.args().clone());
+            format!("{}: procedure {}", name, signature)
+        }
+        Object::Function(_) => format!("{}: built-in procedure", name),
+        x => format!("{}: {}", name, x),
+    }
+}
+
+impl Repl {
+    pub fn new(interpreter: Interpreter) -> Self {
+        Repl {
+            interpreter,
+            buffer: String::new(),
+            last_file: None,
+            exit_code: None,
+            console: Some(Console {
+                input: Box::new(StdinLines::default()),
+                output: Box::new(std::io::stdout()),
+                interactive: std::io::stdin().is_terminal(),
+                frame: 0,
+            }),
+        }
+    }
+
+    /// Replaces the standard input and output used by the debugger while the evaluation is paused.
+    pub fn set_debugger_io(&mut self, input: Box<dyn BufRead>, output: Box<dyn Write>) {
+        let console = Console { input, output, interactive: false, frame: 0 };
+        if self.console.is_none() {
+            debugger::attach(console);
+        } else {
+            self.console = Some(console);
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// The status passed to `exit` if it was called. The REPL should stop then.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Checks if the previous lines contain an unfinished expression.
+    pub fn is_pending(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.is_pending() {
+            CONTINUATION_PROMPT
+        } else {
+            PROMPT
+        }
+    }
+
+    /// Discards the unfinished expression.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Adds a line of input and evaluates all the expressions completed by it.
+    /// Returns printed representation of the results, one line per expression.
+    /// Evaluation stops at the first error.
+    pub fn feed(&mut self, line: &str) -> Vec<String> {
+        if self.is_pending() {
+            self.buffer.push('\n');
+        } else if line.trim().is_empty() {
+            return vec![];
+        } else if let Some(command) = line.trim().strip_prefix(',') {
+            return self.command(command);
+        }
+        self.buffer.push_str(line);
+        let exprs = match parse_expression(&self.buffer) {
+            Ok(exprs) => exprs,
+            Err(err) if err.is_incomplete() => return vec![],
+            Err(err) => {
+                self.buffer.clear();
+                return error(err);
+            }
+        };
+        self.buffer.clear();
+        self.eval_all(exprs)
+    }
+
+    fn eval_all(&mut self, exprs: Vec<Object>) -> Vec<String> {
+        let mut output = vec![];
+        for expr in exprs {
+            match self.interpreter.eval(&Rc::new(expr)) {
+                Ok(obj) => output.push(obj.to_string()),
+                Err(EvalErr::Exit(code)) => {
+                    self.exit_code = Some(code);
+                    break;
+                }
+                Err(err) => {
+                    output.push(format!("Error: {}", err));
+                    break;
+                }
+            }
+        }
+        // a step that is not finished by the input doesn't pause the next one
+        debugger::cancel_step();
+        output
+    }
+
+    /// Executes a meta-command, the leading comma is already removed.
+    fn command(&mut self, command: &str) -> Vec<String> {
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let needs_arg = matches!(name, "time" | "describe" | "expand" | "break" | "step");
+        if needs_arg && arg.is_empty() {
+            return error(format_args!(",{} expects an argument", name));
+        }
+        match name {
+            "help" => HELP.iter().map(|s| s.to_string()).collect(),
+            "load" => self.load(arg),
+            "time" => {
+                let exprs = match parse_expression(arg) {
+                    Ok(exprs) => exprs,
+                    Err(err) => return error(err),
+                };
+                let start = Instant::now();
+                let mut output = self.eval_all(exprs);
+                output.push(format!("; elapsed {:?}", start.elapsed()));
+                output
+            }
+            "env" => (self.interpreter.scope().bindings().iter())
+                .map(|(name, value)| describe(name, value))
+                .collect(),
+            "describe" => {
+                let name = Rc::new(Object::from(Symbol::intern(arg)));
+                match self.interpreter.eval(&name) {
+                    Ok(value) => vec![describe(arg, &value)],
+                    Err(err) => error(err),
+                }
+            }
+            "expand" => {
+                let expr = match parse_expression(arg) {
+                    Ok(mut exprs) if exprs.len() == 1 => Rc::new(exprs.remove(0)),
+                    Ok(_) => return error(",expand expects a single expression"),
+                    Err(err) => return error(err),
+                };
+                match expand(&expr) {
+                    Ok(result) => vec![result.unwrap_or(expr).to_string()],
+                    Err(err) => error(err),
+                }
+            }
+            "heap" => (self.interpreter.heap_dump().to_string().lines())
+                .map(|line| line.to_string())
+                .collect(),
+            "break" | "step" if self.interpreter.engine() != Engine::TreeWalker => {
+                error("the debugger works only with the tree-walker")
+            }
+            "break" | "breakpoints" | "delete" => {
+                self.attach_console();
+                breakpoint_command(name, arg)
+            }
+            "step" => {
+                let exprs = match parse_expression(arg) {
+                    Ok(exprs) => exprs,
+                    Err(err) => return error(err),
+                };
+                self.attach_console();
+                debugger::step_in();
+                self.eval_all(exprs)
+            }
+            "reset" => {
+                self.buffer.clear();
+                match self.interpreter.reset() {
+                    Ok(()) => vec!["Environment is reset".to_string()],
+                    Err(err) => error(err),
+                }
+            }
+            _ => error(format_args!(
+                "unknown command ,{}, type ,help for the list",
+                name
+            )),
+        }
+    }
+
+    fn attach_console(&mut self) {
+        if let Some(console) = self.console.take() {
+            debugger::attach(console);
+        }
+    }
+
+    fn load(&mut self, arg: &str) -> Vec<String> {
+        let path = if arg.is_empty() {
+            match &self.last_file {
+                Some(path) => path.clone(),
+                None => return error(",load expects a file name"),
+            }
+        } else {
+            PathBuf::from(arg)
+        };
+        self.last_file = Some(path.clone());
+        match self.interpreter.eval_file(&path) {
+            Ok(()) => vec![format!("Loaded {}", path.display())],
+            Err(err) => match err.downcast_ref::<EvalErr>() {
+                Some(EvalErr::Exit(code)) => {
+                    self.exit_code = Some(*code);
+                    vec![]
+                }
+                _ => error(err),
+            },
+        }
+    }
+
+    /// Reports an unfinished expression when the input is over.
+    pub fn finish(&mut self) -> Option<String> {
+        let result = parse_expression(&self.buffer)
+            .err()
+            .map(|err| format!("Error: {}", err));
+        self.buffer.clear();
+        result
+    }
+
+    /// Reads the input line by line until it ends.
+    /// Prompts are printed only in the interactive mode.
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    pub fn run<R: BufRead, W: Write>(
+        &mut self, mut input: R, mut output: W, interactive: bool,
+    ) -> std::io::Result<()> {
+        let mut line = Vec::new();
+        loop {
+            if interactive {
+                write!(output, "{}", self.prompt())?;
+                output.flush()?;
+            }
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            for result in self.feed(text.trim_end_matches(&['\n', '\r'][..])) {
+                writeln!(output, "{}", result)?;
+            }
+            if self.exit_code.is_some() {
+                return Ok(());
+            }
+        }
+        if let Some(err) = self.finish() {
+            writeln!(output, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl Console {
+    fn print(&mut self, lines: &[String]) {
+        for line in lines {
+            let _ = writeln!(self.output, "{}", line);
+        }
+        let _ = self.output.flush();
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        if self.interactive {
+            let _ = write!(self.output, "{}", DEBUG_PROMPT);
+            let _ = self.output.flush();
+        }
+        let mut line = vec![];
+        match self.input.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(String::from_utf8_lossy(&line).trim().to_string()),
+        }
+    }
+
+    /// Executes a line read while the evaluation is paused,
+    /// returns how to resume the evaluation if the line says so.
+    fn execute(&mut self, pause: &Pause, line: &str) -> Option<Resume> {
+        let frames = &pause.frames;
+        let command = match line.strip_prefix(',') {
+            Some(command) => command,
+            None if line.is_empty() => return None,
+            None => {
+                let result = match frames[self.frame].eval(line) {
+                    Ok(value) => vec![value.to_string()],
+                    Err(err) => error(err),
+                };
+                self.print(&result);
+                return None;
+            }
+        };
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let output = match name {
+            "step" => return Some(Resume::StepIn),
+            "next" => return Some(Resume::StepOver),
+            "finish" => return Some(Resume::StepOut),
+            "continue" => return Some(Resume::Continue),
+            "abort" => return Some(Resume::Abort),
+            "help" => DEBUG_HELP.iter().map(|s| s.to_string()).collect(),
+            "backtrace" => (frames.iter().enumerate())
+                .map(|(i, frame)| format!("#{} {}", i, frame))
+                .collect(),
+            "frame" => match arg.parse::<usize>() {
+                Ok(i) if i < frames.len() => {
+                    self.frame = i;
+                    vec![format!("#{} {}", i, frames[i])]
+                }
+                _ => error(format_args!("no frame {}", arg)),
+            },
+            "locals" => locals(&frames[self.frame]),
+            "break" if arg.is_empty() => error(",break expects an argument"),
+            "break" | "breakpoints" | "delete" => breakpoint_command(name, arg),
+            _ => error(format_args!(
+                "unknown command ,{}, type ,help for the list",
+                name
+            )),
+        };
+        self.print(&output);
+        None
+    }
+}
+
+impl DebugHook for Console {
+    /// Reads commands until one of them resumes the evaluation, the end of the input aborts it.
+    fn paused(&mut self, pause: &Pause) -> Resume {
+        self.frame = 0;
+        let header = match pause.reason {
+            PauseReason::Breakpoint(id) => format!("; breakpoint {}, {}", id, pause.frames[0]),
+            PauseReason::Step => format!("; {}", pause.frames[0]),
+        };
+        self.print(&[header]);
+        while let Some(line) = self.read_line() {
+            if let Some(resume) = self.execute(pause, &line) {
+                return resume;
+            }
+        }
+        Resume::Abort
+    }
+}
+
+/// Shows bindings of the scopes of the frame except the top-level and the built-in ones.
+fn locals(frame: &StackFrame) -> Vec<String> {
+    let scopes = frame.scopes();
+    let locals = &scopes[..scopes.len().saturating_sub(2)];
+    if locals.is_empty() {
+        return vec!["; no local bindings".to_string()];
+    }
+    let mut lines = vec![];
+    for (i, scope) in locals.iter().enumerate() {
+        lines.push(format!("; scope {}", i));
+        for (name, value) in scope.bindings() {
+            lines.push(format!("{} = {}", name, value));
+        }
+    }
+    lines
+}
+
+impl Read for StdinLines {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.fill_buf()?.read(buf)?;
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            std::io::stdin().lock().read_until(b'\n', &mut self.line)?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+/// Handles the commands managing breakpoints, they are the same in the REPL and in the debugger.
+fn breakpoint_command(name: &str, arg: &str) -> Vec<String> {
+    match name {
+        "break" => {
+            let breakpoint = Breakpoint::parse(arg);
+            let id = debugger::add_breakpoint(breakpoint.clone());
+            vec![format!("Breakpoint {}: {}", id, breakpoint)]
+        }
+        "breakpoints" => {
+            let breakpoints = debugger::breakpoints();
+            if breakpoints.is_empty() {
+                return vec!["No breakpoints".to_string()];
+            }
+            (breakpoints.iter())
+                .map(|(id, breakpoint)| format!("{}: {}", id, breakpoint))
+                .collect()
+        }
+        _ if arg.is_empty() => {
+            debugger::clear_breakpoints();
+            vec!["All breakpoints are deleted".to_string()]
+        }
+        _ => match arg.parse() {
+            Ok(id) if debugger::remove_breakpoint(id) => {
+                vec![format!("Breakpoint {} is deleted", id)]
+            }
+            _ => error(format_args!("no breakpoint {}", arg)),
+        },
+    }
+}
@@ -1,8 +1,16 @@
 use crate::functions::*;
+use crate::gc::*;
 use crate::lists::*;
 use crate::logic::*;
 use crate::math::*;
+use crate::memory::{self, memory_stats};
 use crate::object::*;
+use crate::ports::*;
+use crate::pretty::fn_pretty_print;
+use crate::profiler::fn_profile;
+use crate::symbol::*;
+use crate::system::*;
+use crate::trace::{fn_trace, fn_untrace};
 
 use ahash::RandomState;
 use std::cell::RefCell;
@@ -11,77 +19,192 @@ use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Scope {
-    map: RefCell<HashMap<String, Rc<Object>, RandomState>>,
+    map: RefCell<HashMap<Symbol, Rc<Object>, RandomState>>,
     parent: Option<Rc<Scope>>,
 }
 
 impl Scope {
-    pub fn get(&self, key: &str) -> Option<Rc<Object>> {
-        (self.map.borrow().get(key).map(Rc::clone))
-            .or_else(|| self.parent.as_ref().and_then(|p| p.get(key)))
+    pub fn get<K: Into<Symbol>>(&self, key: K) -> Option<Rc<Object>> {
+        self.get_symbol(key.into())
     }
-    pub fn bind(&self, key: &str, value: Rc<Object>) {
-        self.map.borrow_mut().insert(key.to_string(), value);
+    fn get_symbol(&self, key: Symbol) -> Option<Rc<Object>> {
+        (self.map.borrow().get(&key).map(Rc::clone))
+            .or_else(|| self.parent.as_ref().and_then(|p| p.get_symbol(key)))
+    }
+    pub fn bind<K: Into<Symbol>>(&self, key: K, value: Rc<Object>) {
+        self.map.borrow_mut().insert(key.into(), value);
+    }
+    /// Names of all bindings visible from the scope including the ones from outer scopes.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = (self.map.borrow().keys())
+            .map(|key| key.to_string())
+            .collect::<Vec<_>>();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.names());
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+    /// Bindings of the scope itself without the outer ones, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Rc<Object>)> {
+        let mut bindings = (self.map.borrow().iter())
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect::<Vec<_>>();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+    /// Values bound in the scope itself without the outer ones.
+    pub fn values(&self) -> List {
+        self.map.borrow().values().cloned().collect()
+    }
+    pub fn parent(&self) -> Option<&Rc<Scope>> {
+        self.parent.as_ref()
+    }
+    /// The estimated size of the scope itself in bytes, the values are not included
+    pub fn size(&self) -> usize {
+        let entry = std::mem::size_of::<(Symbol, Rc<Object>)>();
+        std::mem::size_of::<Scope>() + self.map.borrow().capacity() * entry
+    }
+    /// Removes all bindings of the scope, outer scopes are left intact.
+    pub fn clear(&self) {
+        self.map.borrow_mut().clear();
     }
     pub fn new(items: &[(String, Rc<Object>)], parent: &Rc<Scope>) -> Self {
         let mut scope = HashMap::with_capacity_and_hasher(items.len(), RandomState::new());
         for item in items {
-            scope.insert(item.0.clone(), Rc::clone(&item.1));
+            scope.insert(Symbol::from(&item.0), Rc::clone(&item.1));
         }
-        Scope { map: RefCell::new(scope), parent: Some(parent.clone()) }
+        Scope::with_map(scope, Some(parent.clone()))
     }
-    pub fn new_owned(items: Vec<(String, Rc<Object>)>, parent: &Rc<Scope>) -> Self {
+    pub fn new_owned(items: Vec<(Symbol, Rc<Object>)>, parent: &Rc<Scope>) -> Self {
         let mut scope = HashMap::with_capacity_and_hasher(items.len(), RandomState::new());
         for (key, value) in items {
             scope.insert(key, value);
         }
-        Scope { map: RefCell::new(scope), parent: Some(parent.clone()) }
+        Scope::with_map(scope, Some(parent.clone()))
+    }
+    fn with_map(map: HashMap<Symbol, Rc<Object>, RandomState>, parent: Option<Rc<Scope>>) -> Self {
+        memory::scope_created();
+        Scope { map: RefCell::new(map), parent }
     }
     pub fn from_global() -> Self {
         Self::new(&[], &Rc::new(get_global_scope()))
     }
+    /// The global scope without functions that access the process and its environment.
+    pub fn from_safe_global() -> Self {
+        let global = get_global_scope();
+        for name in UNSAFE_FUNCTIONS {
+            global.map.borrow_mut().remove(&Symbol::intern(name));
+        }
+        Self::new(&[], &Rc::new(global))
+    }
     pub fn from_scope(scope: &Rc<Scope>) -> Self {
         Self::new(&[], scope)
     }
 }
 
+impl Drop for Scope {
+    fn drop(&mut self) {
+        memory::scope_dropped();
+    }
+}
+
+/// Functions that are not available for untrusted code
+const UNSAFE_FUNCTIONS: &[&str] = &[
+    "command-line",
+    "get-environment-variable",
+    "get-environment-variables",
+    "exit",
+    "emergency-exit",
+];
+
+/// Built-in functions of the global scope with the minimal and the maximal numbers
+/// of their arguments, `None` if the number is not limited
+#[rustfmt::skip]
+static BUILTINS: &[Builtin] = &[
+    Builtin::new("cons", cons, 2, Some(2)),
+    Builtin::new("list", list, 0, None),
+    Builtin::new("length", length, 1, Some(1)),
+    Builtin::new("map", fn_map, 2, None),
+    Builtin::new("append", append, 0, None),
+    Builtin::new("list-copy", list_copy, 1, Some(1)),
+    Builtin::new("iota", iota, 1, Some(3)),
+    Builtin::new("filter", filter, 2, Some(2)),
+    Builtin::new("remove", remove, 2, Some(2)),
+    Builtin::new("partition", partition, 2, Some(2)),
+    Builtin::new("delete", delete, 2, Some(3)),
+    Builtin::new("delete-duplicates", delete_duplicates, 1, Some(2)),
+    Builtin::new("any", any, 2, None),
+    Builtin::new("every", every, 2, None),
+    Builtin::new("find", find, 2, Some(2)),
+    Builtin::new("find-tail", find_tail, 2, Some(2)),
+    Builtin::new("count", count, 2, None),
+    Builtin::new("list-index", list_index, 2, None),
+    Builtin::new("take", take, 2, Some(2)),
+    Builtin::new("drop", drop, 2, Some(2)),
+    Builtin::new("split-at", split_at, 2, Some(2)),
+    Builtin::new("append-map", append_map, 2, None),
+    Builtin::new("fold", fold, 3, None),
+    Builtin::new("fold-right", fold_right, 3, None),
+    Builtin::new("reduce", reduce, 3, Some(3)),
+    Builtin::new("boolean?", is_boolean, 1, Some(1)),
+    Builtin::new("list?", is_list, 1, Some(1)),
+    Builtin::new("pair?", is_pair, 1, Some(1)),
+    Builtin::new("null?", is_null, 1, Some(1)),
+    Builtin::new("not", logic_not, 1, Some(1)),
+    Builtin::new("eq?", fn_eq, 2, Some(2)),
+    Builtin::new("symbol?", is_symbol, 1, Some(1)),
+    Builtin::new("symbol=?", symbols_equal, 2, None),
+    Builtin::new("symbol->string", symbol_to_string, 1, Some(1)),
+    Builtin::new("string->symbol", string_to_symbol, 1, Some(1)),
+    Builtin::new("string->uninterned-symbol", string_to_uninterned_symbol, 1, Some(1)),
+    Builtin::new("gensym", gensym, 0, Some(1)),
+    Builtin::new("generate-uninterned-symbol", gensym, 0, Some(1)),
+    Builtin::new("eqv?", fn_eqv, 2, Some(2)),
+    Builtin::new("equal?", fn_equal, 2, Some(2)),
+    Builtin::new("number?", is_number, 1, Some(1)),
+    Builtin::new("integer?", is_integer, 1, Some(1)),
+    Builtin::new("real?", is_real, 1, Some(1)),
+    Builtin::new("=", num_eqv, 2, None),
+    Builtin::new("<", num_less, 2, None),
+    Builtin::new(">", num_greater, 2, None),
+    Builtin::new("+", num_plus, 0, None),
+    Builtin::new("-", num_minus, 0, None),
+    Builtin::new("*", num_mul, 0, None),
+    Builtin::new("/", num_div, 0, None),
+    Builtin::new("quotient", quotient, 2, Some(2)),
+    Builtin::new("remainder", remainder, 2, Some(2)),
+    Builtin::new("modulo", modulo, 2, Some(2)),
+    Builtin::new("display", display, 1, Some(1)),
+    Builtin::new("write", write, 1, Some(1)),
+    Builtin::new("pretty-print", fn_pretty_print, 1, Some(2)),
+    Builtin::new("newline", newline, 0, Some(0)),
+    Builtin::new("command-line", command_line, 0, Some(0)),
+    Builtin::new("get-environment-variable", get_environment_variable, 1, Some(1)),
+    Builtin::new("get-environment-variables", get_environment_variables, 0, Some(0)),
+    Builtin::new("exit", exit, 0, Some(1)),
+    Builtin::new("emergency-exit", emergency_exit, 0, Some(1)),
+    Builtin::new("current-second", current_second, 0, Some(0)),
+    Builtin::new("current-jiffy", current_jiffy, 0, Some(0)),
+    Builtin::new("jiffies-per-second", jiffies_per_second, 0, Some(0)),
+    Builtin::new("gc", fn_gc, 0, Some(0)),
+    Builtin::new("gc-stats", gc_stats, 0, Some(0)),
+    Builtin::new("memory-stats", memory_stats, 0, Some(0)),
+    Builtin::new("profile", fn_profile, 1, Some(2)),
+    Builtin::new("trace", fn_trace, 0, None),
+    Builtin::new("untrace", fn_untrace, 0, None),
+];
+
 /// Global bindings storage accessible from everywhere.
 /// Contains core functions and constants like `#t` and `#f`
-#[rustfmt::skip]
 fn get_global_scope() -> Scope {
-    let bindings = [
-        ("#t", Object::Boolean(true)),
-        ("#f", Object::Boolean(false)),
-        ("cons", Function::from_pointer(cons)),
-        ("list", Function::from_pointer(list)),
-        ("length", Function::from_pointer(length)),
-        ("map", Function::from_pointer(fn_map)),
-        ("boolean?", Function::from_pointer(is_boolean)),
-        ("list?", Function::from_pointer(is_list)),
-        ("pair?", Function::from_pointer(is_pair)),
-        ("null?", Function::from_pointer(is_null)),
-        ("not", Function::from_pointer(logic_not)),
-        ("eq?", Function::from_pointer(fn_eq)),
-        ("eqv?", Function::from_pointer(fn_eqv)),
-        ("equal?", Function::from_pointer(fn_equal)),
-        ("number?", Function::from_pointer(is_number)),
-        ("integer?", Function::from_pointer(is_integer)),
-        ("real?", Function::from_pointer(is_real)),
-        ("=", Function::from_pointer(num_eqv)),
-        ("<", Function::from_pointer(num_less)),
-        (">", Function::from_pointer(num_greater)),
-        ("+", Function::from_pointer(num_plus)),
-        ("-", Function::from_pointer(num_minus)),
-        ("*", Function::from_pointer(num_mul)),
-        ("/", Function::from_pointer(num_div)),
-        ("quotient", Function::from_pointer(quotient)),
-        ("remainder", Function::from_pointer(remainder)),
-        ("modulo", Function::from_pointer(modulo)),
-
-    ];
-    let mut map = HashMap::with_capacity_and_hasher(bindings.len(), RandomState::new());
-    for (s, obj) in bindings {
-        map.insert(s.to_string(), Rc::new(obj));
+    let mut map = HashMap::with_capacity_and_hasher(BUILTINS.len() + 2, RandomState::new());
+    map.insert(Symbol::intern("#t"), Rc::new(Object::from(true)));
+    map.insert(Symbol::intern("#f"), Rc::new(Object::from(false)));
+    for builtin in BUILTINS {
+        let function = Object::from(Function::Builtin(builtin));
+        map.insert(Symbol::intern(builtin.name), Rc::new(function));
     }
-    Scope { map: RefCell::new(map), parent: None }
+    Scope::with_map(map, None)
 }
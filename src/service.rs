@@ -3,6 +3,7 @@
 
 use crate::errors::EvalErr;
 use crate::object::{List, Object};
+use crate::symbol::Symbol;
 use std::rc::Rc;
 
 /// Converts lists to Vec of references to its elements.
@@ -24,7 +25,7 @@ pub fn list_to_vec(obj: &Object) -> Result<List, EvalErr> {
 /// This function always succeeds.
 pub fn vec_to_list(vec: &[Rc<Object>]) -> Object {
     vec.iter().rfold(Object::Nil, |tail, elem| {
-        Object::Pair(elem.clone(), Rc::new(tail))
+        Object::cons(elem.clone(), Rc::new(tail))
     })
 }
 
@@ -61,5 +62,5 @@ pub fn check_pair(obj: &Object) -> Result<(&Rc<Object>, &Rc<Object>), EvalErr> {
 /// Used in situations when an expression have not an exact result.
 /// E.g. for `cond` where all clauses are false.
 pub fn undef() -> Rc<Object> {
-    Rc::new(Object::Symbol("#<undef>".to_string()))
+    Rc::new(Object::from(Symbol::intern("#<undef>")))
 }
@@ -0,0 +1,70 @@
+//! Locations of parsed expressions in the source code.
+//!
+//! The parser records the line of every list it reads. A list is identified by its tail:
+//! `(f x)` is a pair of `f` and the tail `(x)`, and the tail is what evaluators get
+//! as the arguments of a form, so they can find where the form was written.
+//! The table keeps weak references to the tails, so their memory is not reused for other objects
+//! until the entries of dropped tails are removed.
+
+use crate::object::Object;
+
+use ahash::RandomState;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::{Rc, Weak};
+
+/// A line of a source file, lines are counted from one
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// The file name, `None` for code that doesn't come from a file
+    pub file: Option<Rc<str>>,
+    pub line: u32,
+}
+
+/// The size of the table when dropped expressions are removed from it for the first time
+const MIN_CAPACITY: usize = 4096;
+
+struct SourceMap {
+    locations: HashMap<*const Object, (Weak<Object>, Location), RandomState>,
+    /// The table is cleaned when it grows to this size
+    capacity: usize,
+}
+
+thread_local! {
+    static SOURCE_MAP: RefCell<SourceMap> = RefCell::new(SourceMap {
+        locations: HashMap::default(),
+        capacity: MIN_CAPACITY,
+    });
+}
+
+/// Remembers the location of the list with the given tail.
+pub(crate) fn record(tail: &Rc<Object>, location: Location) {
+    SOURCE_MAP.with(|map| {
+        let mut map = map.borrow_mut();
+        if map.locations.len() >= map.capacity {
+            map.locations.retain(|_, (tail, _)| tail.strong_count() > 0);
+            map.capacity = (2 * map.locations.len()).max(MIN_CAPACITY);
+        }
+        (map.locations).insert(Rc::as_ptr(tail), (Rc::downgrade(tail), location));
+    });
+}
+
+/// Returns the location of the list with the given tail if it was read by the parser.
+pub fn location(tail: &Rc<Object>) -> Option<Location> {
+    SOURCE_MAP.with(|map| {
+        let map = map.borrow();
+        map.locations
+            .get(&Rc::as_ptr(tail))
+            .map(|(_, location)| location.clone())
+    })
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
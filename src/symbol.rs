@@ -0,0 +1,183 @@
+//! Interned symbols.
+//!
+//! Every name is stored once in the symbol table of the thread and a symbol is the index of its name,
+//! so symbols are compared and hashed as integers. Uninterned symbols share the stored name and get
+//! a new number every time they are made, they are not equal to any other symbol even if their names
+//! are the same. Names are never freed, like in most Scheme implementations, but making uninterned
+//! symbols with a name seen before takes no memory. Symbols are indexes into the table of their
+//! thread, so they can't be sent to other threads.
+
+use crate::convert::{from_args, IntoScheme};
+use crate::errors::EvalErr;
+use crate::object::{List, Object};
+use crate::service::expect_1_arg;
+
+use ahash::RandomState;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    name: u32,
+    /// Zero for interned symbols, a number unique in the thread for uninterned ones
+    id: u64,
+    /// Whether the number is a part of the name, as for `gensym`
+    numbered: bool,
+    thread: PhantomData<*const ()>,
+}
+
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<&'static str>,
+    indexes: HashMap<&'static str, u32, RandomState>,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::default());
+    static UNINTERNED_COUNTER: Cell<u64> = const { Cell::new(0) };
+}
+
+impl SymbolTable {
+    fn index(&mut self, name: &str) -> u32 {
+        if let Some(index) = self.indexes.get(name) {
+            return *index;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let index = self.names.len() as u32;
+        self.names.push(name);
+        self.indexes.insert(name, index);
+        index
+    }
+}
+
+impl Symbol {
+    fn new(name: &str, id: u64, numbered: bool) -> Symbol {
+        let name = SYMBOLS.with(|table| table.borrow_mut().index(name));
+        Symbol { name, id, numbered, thread: PhantomData }
+    }
+
+    /// The symbol with the name, all calls with the same name return the same symbol
+    pub fn intern(name: &str) -> Symbol {
+        Symbol::new(name, 0, false)
+    }
+
+    /// A new symbol which differs from all others
+    pub fn uninterned(name: &str) -> Symbol {
+        Symbol::new(name, next_id(), false)
+    }
+
+    /// A new symbol which differs from all others, named by the prefix and its number
+    fn numbered(prefix: &str) -> Symbol {
+        Symbol::new(prefix, next_id(), true)
+    }
+
+    /// The name of the symbol, without the number of a symbol made by `gensym`
+    pub fn as_str(&self) -> &'static str {
+        SYMBOLS.with(|table| table.borrow().names[self.name as usize])
+    }
+}
+
+fn next_id() -> u64 {
+    UNINTERNED_COUNTER.with(|counter| {
+        counter.set(counter.get() + 1);
+        counter.get()
+    })
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(symbol: &Symbol) -> Self {
+        *symbol
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if self.numbered {
+            write!(f, "{}{}", self.as_str(), self.id)
+        } else {
+            write!(f, "{}", self.as_str())
+        }
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+pub fn is_symbol(args: List) -> Result<Rc<Object>, EvalErr> {
+    let arg = expect_1_arg(args, "symbol?")?;
+    Ok(matches!(arg.as_ref(), Object::Symbol(_)).into_scheme())
+}
+
+/// Returns `#t` if all arguments are the same symbol
+pub fn symbols_equal(args: List) -> Result<Rc<Object>, EvalErr> {
+    let symbols: Vec<Symbol> = from_args(&args, "symbol=?")?;
+    Ok(symbols
+        .windows(2)
+        .all(|pair| pair[0] == pair[1])
+        .into_scheme())
+}
+
+pub fn symbol_to_string(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (symbol,): (Symbol,) = from_args(&args, "symbol->string")?;
+    Ok(symbol.to_string().into_scheme())
+}
+
+pub fn string_to_symbol(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (name,): (String,) = from_args(&args, "string->symbol")?;
+    Ok(Symbol::intern(&name).into_scheme())
+}
+
+pub fn string_to_uninterned_symbol(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (name,): (String,) = from_args(&args, "string->uninterned-symbol")?;
+    Ok(Symbol::uninterned(&name).into_scheme())
+}
+
+/// Makes a new uninterned symbol named by the optional prefix and a counter
+pub fn gensym(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (prefix,): (String,) = match args.len() {
+        0 => ("g".to_string(),),
+        _ => from_args(&args, "gensym")?,
+    };
+    Ok(Symbol::numbered(&prefix).into_scheme())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        assert_eq!(Symbol::intern("foo"), Symbol::intern("foo"));
+        assert_ne!(Symbol::intern("foo"), Symbol::intern("bar"));
+        assert_eq!(Symbol::intern("foo").as_str(), "foo");
+
+        let uninterned = Symbol::uninterned("foo");
+        assert_ne!(uninterned, Symbol::intern("foo"));
+        assert_ne!(uninterned, Symbol::uninterned("foo"));
+        assert_eq!(uninterned.as_str(), "foo");
+
+        let names = SYMBOLS.with(|table| table.borrow().names.len());
+        let numbered = Symbol::numbered("foo");
+        assert_ne!(numbered, Symbol::numbered("foo"));
+        assert_eq!(numbered.to_string(), format!("foo{}", numbered.id));
+        assert_eq!(SYMBOLS.with(|table| table.borrow().names.len()), names);
+    }
+}
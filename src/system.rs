@@ -0,0 +1,88 @@
+//! Access to the environment of the running program and the system clock.
+
+use crate::context;
+use crate::convert::{from_args, IntoScheme};
+use crate::errors::EvalErr;
+use crate::object::{List, Object};
+use crate::service::expect_args;
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Jiffies are microseconds
+const JIFFIES_PER_SECOND: i64 = 1_000_000;
+
+/// The moment the jiffies are counted from, it's the first call of `current-jiffy`
+static JIFFY_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Returns the program name followed by its arguments as a list of strings
+pub fn command_line(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "command-line", 0)?;
+    Ok(context::command_line().into_scheme())
+}
+
+/// Returns the value of the variable or `#f` if it is not set
+pub fn get_environment_variable(args: List) -> Result<Rc<Object>, EvalErr> {
+    let (name,) = from_args::<(String,)>(&args, "get-environment-variable")?;
+    Ok(std::env::var(name).ok().into_scheme())
+}
+
+/// Returns all the variables as an association list sorted by names
+pub fn get_environment_variables(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "get-environment-variables", 0)?;
+    let mut vars = std::env::vars_os()
+        .map(|(k, v)| {
+            (
+                k.to_string_lossy().to_string(),
+                v.to_string_lossy().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    vars.sort();
+    let pairs = vars
+        .into_iter()
+        .map(|(k, v)| Object::cons(k.into_scheme(), v.into_scheme()));
+    Ok(pairs.collect::<Vec<_>>().into_scheme())
+}
+
+/// Converts the optional argument of `exit` to the status code.
+/// No argument and `#t` mean success, `#f` means failure.
+fn exit_code(args: List, name: &str) -> Result<i32, EvalErr> {
+    match args.first().map(|x| x.as_ref()) {
+        None | Some(Object::Boolean(true)) => Ok(0),
+        Some(Object::Boolean(false)) => Ok(1),
+        Some(obj) => i32::try_from(from_args::<(i64,)>(&args, name)?.0)
+            .map_err(|_| EvalErr::TypeMismatch("exit code".to_string(), obj.to_string())),
+    }
+}
+
+/// Stops the evaluation and makes the interpreter's caller terminate the program.
+/// The output is flushed when the evaluation is left, there is no `dynamic-wind` to unwind.
+pub fn exit(args: List) -> Result<Rc<Object>, EvalErr> {
+    Err(EvalErr::Exit(exit_code(args, "exit")?))
+}
+
+/// Terminates the process immediately
+pub fn emergency_exit(args: List) -> Result<Rc<Object>, EvalErr> {
+    std::process::exit(exit_code(args, "emergency-exit")?)
+}
+
+/// Seconds since the Unix epoch
+pub fn current_second(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "current-second", 0)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH);
+    Ok(time.map_or(0.0, |t| t.as_secs_f64()).into_scheme())
+}
+
+pub fn current_jiffy(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "current-jiffy", 0)?;
+    let elapsed = JIFFY_EPOCH.get_or_init(Instant::now).elapsed();
+    Ok((elapsed.as_micros() as i64).into_scheme())
+}
+
+pub fn jiffies_per_second(args: List) -> Result<Rc<Object>, EvalErr> {
+    expect_args(args, "jiffies-per-second", 0)?;
+    Ok(JIFFIES_PER_SECOND.into_scheme())
+}
@@ -0,0 +1,129 @@
+//! Tracing of procedures: `(trace f ...)` prints the calls of the procedures with their arguments
+//! and the values they return, indented by the depth of the traced calls.
+//!
+//! A traced call is kept by the activation of the frame that made it and its value is printed
+//! when the frame returns. So a traced procedure called in the tail position of another one
+//! is printed at the same depth, only the last value is printed and no frame is kept for the call.
+//! Every engine prints the calls when it enters the procedures, so they are traced
+//! with all of them.
+
+use crate::context;
+use crate::errors::EvalErr;
+use crate::functions::Function;
+use crate::object::{List, Object};
+use crate::service::{undef, vec_to_list};
+use crate::symbol::Symbol;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+thread_local! {
+    /// Traced procedures with the names they are printed with
+    static TRACED: RefCell<Vec<(Rc<Object>, String)>> = const { RefCell::new(Vec::new()) };
+    /// The depth of the next traced call
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A traced call in progress, the depth is restored when it is dropped
+pub(crate) struct Call {
+    depth: usize,
+}
+
+fn is_same(obj: &Object, fun: &Function) -> bool {
+    matches!(obj, Object::Function(f) if f == fun)
+}
+
+/// Writes the line with the indentation of the depth
+fn write(depth: usize, text: &str) -> Result<(), EvalErr> {
+    let indent = (0..=depth)
+        .map(|i| if i % 2 == 0 { '|' } else { ' ' })
+        .collect::<String>();
+    context::write_output(&format!("{}{}\n", indent, text))
+}
+
+/// Prints the call if the function is traced. A call made in the tail position of the current one
+/// is printed at its depth, otherwise a new call is returned.
+pub(crate) fn call(
+    fun: &Function, args: &[Rc<Object>], current: Option<&Call>,
+) -> Result<Option<Call>, EvalErr> {
+    let name = TRACED.with(|traced| {
+        (traced.borrow().iter())
+            .find(|(f, _)| is_same(f, fun))
+            .map(|(_, name)| name.clone())
+    });
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let mut text = format!("({}", name);
+    for arg in args {
+        text += &format!(" {}", arg);
+    }
+    text.push(')');
+    match current {
+        Some(call) => write(call.depth, &text).map(|()| None),
+        None => {
+            let depth = DEPTH.with(|d| d.replace(d.get() + 1));
+            let call = Call { depth };
+            write(depth, &text).map(|()| Some(call))
+        }
+    }
+}
+
+impl Call {
+    pub(crate) fn returned(&self, value: &Object) -> Result<(), EvalErr> {
+        write(self.depth, &value.to_string())
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        let _ = DEPTH.try_with(|d| d.set(self.depth));
+    }
+}
+
+/// `(trace f ...)` traces the procedures and returns the names of all traced ones
+pub fn fn_trace(args: List) -> Result<Rc<Object>, EvalErr> {
+    for arg in args {
+        let name = match arg.as_ref() {
+            Object::Function(Function::Object { name, .. }) => name.clone(),
+            Object::Function(Function::Compiled(closure)) => closure.name().to_string(),
+            Object::Function(Function::Dynamic(name)) => name.clone(),
+            Object::Function(Function::Builtin(builtin)) => builtin.name.to_string(),
+            Object::Function(Function::Pointer(_)) => "<function>".to_string(),
+            x => {
+                let expected = "Procedure".to_string();
+                return Err(EvalErr::TypeMismatch(expected, x.to_string()));
+            }
+        };
+        TRACED.with(|traced| {
+            let mut traced = traced.borrow_mut();
+            if let Object::Function(f) = arg.as_ref() {
+                traced.retain(|(obj, _)| !is_same(obj, f));
+            }
+            traced.push((arg, name));
+        });
+    }
+    let names = TRACED.with(|traced| {
+        (traced.borrow().iter())
+            .map(|(_, name)| Rc::new(Object::from(Symbol::intern(name))))
+            .collect::<Vec<_>>()
+    });
+    Ok(Rc::new(vec_to_list(&names)))
+}
+
+/// `(untrace f ...)` stops tracing the procedures, all of them if no one is given
+pub fn fn_untrace(args: List) -> Result<Rc<Object>, EvalErr> {
+    TRACED.with(|traced| {
+        let mut traced = traced.borrow_mut();
+        if args.is_empty() {
+            traced.clear();
+        }
+        for arg in &args {
+            if let Object::Function(f) = arg.as_ref() {
+                traced.retain(|(obj, _)| !is_same(obj, f));
+            }
+        }
+    });
+    Ok(undef())
+}
@@ -0,0 +1,270 @@
+//! The virtual machine executing the bytecode made by the compiler.
+//!
+//! It is an alternative to the tree-walking `eval` with the same semantics.
+//! Calls of compiled functions don't use the Rust stack, tail calls replace the current call frame.
+//! Built-in functions and functions made by `eval` are called as usual.
+
+use crate::compiler::{self, Lambda, Op};
+use crate::context::{self, DepthGuard};
+use crate::errors::EvalErr;
+use crate::eval::read_loaded_file;
+use crate::functions::{apply_args, Function};
+use crate::gc;
+use crate::lexical::Frame;
+use crate::object::{List, Object};
+use crate::profiler::Activation;
+use crate::scope::Scope;
+use crate::service::undef;
+use crate::source::Location;
+
+use std::rc::Rc;
+
+/// A compiled lambda with the frames and the global scope it was created in.
+pub struct Closure {
+    lambda: Rc<Lambda>,
+    env: Option<Rc<Frame>>,
+    globals: Rc<Scope>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        &self.lambda.name
+    }
+
+    /// Formal arguments as they are written in the source
+    pub fn args(&self) -> &Rc<Object> {
+        &self.lambda.args
+    }
+
+    /// Where the lambda is defined if it is known
+    pub fn location(&self) -> Option<&Location> {
+        self.lambda.location.as_ref()
+    }
+
+    /// The frame of the local variables the closure was created in
+    pub(crate) fn env(&self) -> Option<&Rc<Frame>> {
+        self.env.as_ref()
+    }
+
+    pub(crate) fn globals(&self) -> &Rc<Scope> {
+        &self.globals
+    }
+}
+
+struct CallFrame {
+    lambda: Rc<Lambda>,
+    pc: usize,
+    env: Option<Rc<Frame>>,
+    globals: Rc<Scope>,
+    /// The height of the value stack when the call started
+    base: usize,
+    /// The called procedure in the profile and its traced call
+    activation: Activation,
+    _depth: DepthGuard,
+}
+
+/// Compiles the expression and executes it. Top-level definitions are bound in the given scope.
+pub fn eval(obj: &Rc<Object>, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr> {
+    let lambda = compiler::compile(obj)?;
+    context::tick()?;
+    let frame = CallFrame {
+        lambda,
+        pc: 0,
+        env: None,
+        globals: scope.clone(),
+        base: 0,
+        activation: Activation::default(),
+        _depth: context::enter_eval()?,
+    };
+    run(frame)
+}
+
+/// Calls a compiled function
+pub fn call(closure: &Closure, args: List) -> Result<Rc<Object>, EvalErr> {
+    context::tick()?;
+    let mut activation = Activation::default();
+    activation.enter(closure.name(), closure.location());
+    let frame = CallFrame {
+        lambda: closure.lambda.clone(),
+        pc: 0,
+        env: bind_args(closure, args)?,
+        globals: closure.globals.clone(),
+        base: 0,
+        activation,
+        _depth: context::enter_eval()?,
+    };
+    run(frame)
+}
+
+/// Makes the frame of a call with the arguments in the first slots
+fn bind_args(closure: &Closure, args: List) -> Result<Option<Rc<Frame>>, EvalErr> {
+    let lambda = &closure.lambda;
+    let slots = lambda.params.bind(&lambda.name, args, lambda.frame_size)?;
+    Ok(Some(Frame::new(slots, closure.env.clone())))
+}
+
+fn run(frame: CallFrame) -> Result<Rc<Object>, EvalErr> {
+    let mut stack = Vec::new();
+    let mut frames = vec![frame];
+    loop {
+        let frame = frames.last_mut().unwrap();
+        let op = frame.lambda.code[frame.pc];
+        frame.pc += 1;
+        let (func, args, tail) = match op {
+            Op::Const(i) => {
+                stack.push(frame.lambda.constants[i as usize].clone());
+                continue;
+            }
+            Op::Local(depth, index, name) => {
+                let slot = frame.env.as_ref().unwrap().get(depth, index);
+                let unbound =
+                    || EvalErr::UnboundVariable(frame.lambda.names[name as usize].to_string());
+                stack.push(slot.ok_or_else(unbound)?);
+                continue;
+            }
+            Op::SetLocal(depth, index) => {
+                let value = stack.pop().unwrap();
+                frame.env.as_ref().unwrap().set(depth, index, value);
+                continue;
+            }
+            Op::Global(i) => {
+                let name = &frame.lambda.names[i as usize];
+                let value = frame.globals.get(name);
+                stack.push(value.ok_or_else(|| EvalErr::UnboundVariable(name.to_string()))?);
+                continue;
+            }
+            Op::DefineGlobal(i) => {
+                frame
+                    .globals
+                    .bind(frame.lambda.names[i as usize], stack.pop().unwrap());
+                continue;
+            }
+            Op::Closure(i) => {
+                frame.env.iter().for_each(gc::track_frame);
+                gc::track_scope(&frame.globals);
+                let closure = Closure {
+                    lambda: frame.lambda.lambdas[i as usize].clone(),
+                    env: frame.env.clone(),
+                    globals: frame.globals.clone(),
+                };
+                stack.push(Rc::new(Object::from(Function::Compiled(closure))));
+                continue;
+            }
+            Op::Pop => {
+                stack.pop();
+                continue;
+            }
+            Op::Jump(target) => {
+                frame.pc = target as usize;
+                continue;
+            }
+            Op::JumpIfFalse(target) => {
+                if !stack.pop().unwrap().is_true() {
+                    frame.pc = target as usize;
+                }
+                continue;
+            }
+            Op::JumpIfTrue(target) => {
+                if stack.last().unwrap().is_true() {
+                    frame.pc = target as usize;
+                } else {
+                    stack.pop();
+                }
+                continue;
+            }
+            Op::EnterFrame(size, n) => {
+                let values = stack.split_off(stack.len() - n as usize);
+                let mut slots = values.into_iter().map(Some).collect::<Vec<_>>();
+                slots.resize(size as usize, None);
+                let parent = frame.env.take();
+                frame.env = Some(Frame::new(slots, parent));
+                continue;
+            }
+            Op::LeaveFrame => {
+                frame.env = frame.env.as_ref().unwrap().parent();
+                continue;
+            }
+            Op::Load => {
+                let name = stack.pop().unwrap();
+                for expr in read_loaded_file(&name)? {
+                    eval(&Rc::new(expr), &frame.globals)?;
+                }
+                stack.push(undef());
+                continue;
+            }
+            Op::Return => {
+                let value = stack.pop().unwrap();
+                let frame = frames.pop().unwrap();
+                frame.activation.returned(&value)?;
+                if frames.is_empty() {
+                    return Ok(value);
+                }
+                stack.truncate(frame.base);
+                stack.push(value);
+                continue;
+            }
+            Op::Call(argc, head) | Op::TailCall(argc, head) => {
+                let args = stack.split_off(stack.len() - argc as usize);
+                let func = stack.pop().unwrap();
+                if !matches!(func.as_ref(), Object::Function(_)) {
+                    let head = &frame.lambda.constants[head as usize];
+                    return Err(EvalErr::IllegalObjectAsAFunction(head.to_string()));
+                }
+                (func, args, matches!(op, Op::TailCall(..)))
+            }
+            Op::Apply(argc) | Op::TailApply(argc) => {
+                let args = stack.split_off(stack.len() - argc as usize);
+                let (func, args) = apply_args(args)?;
+                (func, args, matches!(op, Op::TailApply(_)))
+            }
+        };
+        context::tick()?;
+        match func.as_ref() {
+            Object::Function(f @ Function::Compiled(closure)) => {
+                let mut activation = Activation::default();
+                match frames.last_mut() {
+                    Some(frame) if tail => frame.activation.trace(f, &args)?,
+                    _ => activation.trace(f, &args)?,
+                }
+                let env = bind_args(closure, args)?;
+                let lambda = closure.lambda.clone();
+                let globals = closure.globals.clone();
+                if tail {
+                    let frame = frames.last_mut().unwrap();
+                    stack.truncate(frame.base);
+                    frame.lambda = lambda;
+                    frame.pc = 0;
+                    frame.env = env;
+                    frame.globals = globals;
+                    frame.activation.enter(closure.name(), closure.location());
+                } else {
+                    let base = stack.len();
+                    let _depth = context::enter_eval()?;
+                    activation.enter(closure.name(), closure.location());
+                    frames.push(CallFrame {
+                        lambda,
+                        pc: 0,
+                        env,
+                        globals,
+                        base,
+                        activation,
+                        _depth,
+                    });
+                }
+            }
+            Object::Function(f) => {
+                let value = f.invoke(args)?;
+                if tail {
+                    let frame = frames.pop().unwrap();
+                    frame.activation.returned(&value)?;
+                    if frames.is_empty() {
+                        return Ok(value);
+                    }
+                    stack.truncate(frame.base);
+                }
+                stack.push(value);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,23 +1,45 @@
+use scheme::convert::FromScheme;
 use scheme::errors::EvalErr;
 use scheme::eval_expr;
 use scheme::functions::Function;
+use scheme::interpreter::Engine;
 use scheme::object::{Number, Object};
+use scheme::parser::parse_expression;
 use scheme::scope::Scope;
 
 use std::rc::Rc;
 use std::time::Instant;
 
+const ENGINES: [Engine; 3] = [Engine::TreeWalker, Engine::Analyzer, Engine::Bytecode];
+
+/// Evaluates the code with the engine, the result of the last expression is returned
+fn eval_with(engine: Engine, code: &str, scope: &Rc<Scope>) -> Rc<Object> {
+    let mut result = Rc::new(Object::Nil);
+    for obj in parse_expression(code).unwrap() {
+        result = engine.eval(&Rc::new(obj), scope).unwrap();
+    }
+    result
+}
+
+/// Names the measurement made with the engine
+fn label(name: &str, engine: Engine) -> String {
+    match engine {
+        Engine::TreeWalker => name.to_string(),
+        Engine::Analyzer => format!("{} (analyzed)", name),
+        Engine::Bytecode => format!("{} (VM)", name),
+    }
+}
+
 fn seq_sum(args: Vec<Rc<Object>>) -> Result<Rc<Object>, EvalErr> {
     if args.len() > 1 {
         return Err(EvalErr::TooManyArguments("seq_sum".to_string()));
     }
-    let n = &args[0];
-    if let &Object::Number(Number::Integer(n)) = n.as_ref() {
+    if let Ok(n) = i64::from_scheme(&args[0]) {
         let mut sum = 0;
         for i in 0..=n {
             sum += i;
         }
-        Ok(Rc::new(Object::Number(Number::Integer(sum))))
+        Ok(Rc::new(Object::from(Number::Integer(sum))))
     } else {
         Err(EvalErr::NumericArgsRequiredFor("seq_sum".to_string()))
     }
@@ -46,7 +68,7 @@ fn benchmark_loops() {
         assert_eq!(test_fn(), reference_sum);
     }
     let reference_time = start.elapsed().div_f64(scale as f64);
-    println!(" {:<20} {:12?}", "Rust loop", reference_time);
+    println!(" {:<26} {:12?}", "Rust loop", reference_time);
     
     let scope = Rc::new(Scope::from_global());
     scope.bind("seq-sum", Rc::new(Function::from_pointer(seq_sum)));
@@ -58,40 +80,44 @@ fn benchmark_loops() {
         assert_eq!(sum.to_string(), reference_sum.to_string());
     }
     let elapsed = start.elapsed().div_f64(scale as f64);
-    println!(" {:<20} {:12?} ({:.2 }x)",
+    println!(" {:<26} {:12?} ({:.2 }x)",
              "Rust implementation", elapsed, elapsed.as_secs_f64() / reference_time.as_secs_f64());
     
-    let scope = Rc::new(Scope::from_global());
-    let seq_sum = "
-        (define (seq-sum n)
-            (if (= 0 n)  0 (+ n (seq-sum (- n 1)))))";
-    eval_expr(seq_sum, &scope).unwrap();
-    let code = format!("(seq-sum {})", N);
-    let start = Instant::now();
-    for _ in 0..LOOP {
-        let sum = eval_expr(&code, &scope).unwrap();
-        assert_eq!(sum.to_string(), reference_sum.to_string());
+    for engine in ENGINES {
+        let scope = Rc::new(Scope::from_global());
+        let seq_sum = "
+            (define (seq-sum n)
+                (if (= 0 n)  0 (+ n (seq-sum (- n 1)))))";
+        eval_with(engine, seq_sum, &scope);
+        let code = format!("(seq-sum {})", N);
+        let start = Instant::now();
+        for _ in 0..LOOP {
+            let sum = eval_with(engine, &code, &scope);
+            assert_eq!(sum.to_string(), reference_sum.to_string());
+        }
+        let elapsed = start.elapsed();
+        println!(" {:<26} {:12?} ({:.0}x)",
+                label("Recursion", engine), elapsed, elapsed.as_secs_f64() / reference_time.as_secs_f64());
     }
-    let elapsed = start.elapsed();
-    println!(" {:<20} {:12?} ({:.0}x)",
-            "Recursion", elapsed, elapsed.as_secs_f64() / reference_time.as_secs_f64());
 
-    let scope = Rc::new(Scope::from_global());
-    let seq_sum = "
-        (define (seq-sum n)
-            (define (seq-sum n acc)
-                (if (= 0 n)  acc (seq-sum (- n 1) (+ acc n))))
-            (seq-sum n 0))";
-    eval_expr(seq_sum, &scope).unwrap();
-    let code = format!("(seq-sum {})", N);
-    let start = Instant::now();
-    for _ in 0..LOOP {
-        let sum = eval_expr(&code, &scope).unwrap();
-        assert_eq!(sum.to_string(), reference_sum.to_string());
+    for engine in ENGINES {
+        let scope = Rc::new(Scope::from_global());
+        let seq_sum = "
+            (define (seq-sum n)
+                (define (seq-sum n acc)
+                    (if (= 0 n)  acc (seq-sum (- n 1) (+ acc n))))
+                (seq-sum n 0))";
+        eval_with(engine, seq_sum, &scope);
+        let code = format!("(seq-sum {})", N);
+        let start = Instant::now();
+        for _ in 0..LOOP {
+            let sum = eval_with(engine, &code, &scope);
+            assert_eq!(sum.to_string(), reference_sum.to_string());
+        }
+        let elapsed = start.elapsed();
+        println!(" {:<26} {:12?} ({:.0}x)",
+                label("Tail recursion", engine), elapsed, elapsed.as_secs_f64() / reference_time.as_secs_f64());
     }
-    let elapsed = start.elapsed();
-    println!(" {:<20} {:12?} ({:.0}x)",
-            "Tail recursion", elapsed, elapsed.as_secs_f64() / reference_time.as_secs_f64());
 }
 
 /// ```
@@ -111,14 +137,14 @@ fn arr_to_string(arr: &[i32]) -> String {
 fn benchmark_map() {
     const LOOP: i32 = 1000;
     let arr1 = [1, 2, 3, 4, 5, 6, 7, 8, 9];
-    let mut arr2 = arr1.clone();
+    let mut arr2 = arr1;
     arr2.reverse();
     let result = [9, 16, 21, 24, 25, 24, 21, 16, 9];
     
     let test_fn = |arr1: &[i32], arr2: &[i32]| {
-        return (arr1.iter().zip(arr2))
+        (arr1.iter().zip(arr2))
         .map(|(x, y)| x * y)
-        .collect::<Vec<_>>();
+        .collect::<Vec<_>>()
     };
 
     let start = Instant::now();
@@ -128,7 +154,7 @@ fn benchmark_map() {
         assert_eq!(res, result);
     }
     let reference_time = start.elapsed().div_f64(scale as f64);
-    println!(" {:<20} {:12?}", "Rust zip + map", reference_time);
+    println!(" {:<26} {:12?}", "Rust zip + map", reference_time);
 
     let map1 = format!("(map * '{} '{})", arr_to_string(&arr1), arr_to_string(&arr2));
     let start = Instant::now();
@@ -140,7 +166,7 @@ fn benchmark_map() {
     }
     let elapsed1 = start.elapsed().div_f64(scale as f64);
     let x1 = elapsed1.as_secs_f64() / reference_time.as_secs_f64();
-    println!(" {:<20} {:12?} ({:.0}x)", "Built-in map", elapsed1, x1);
+    println!(" {:<26} {:12?} ({:.0}x)", "Built-in map", elapsed1, x1);
 
     let my_map = "
         (define (map1 func list)
@@ -154,17 +180,19 @@ fn benchmark_map() {
                 (cons (apply func (map1 car lists))
                     (apply my-map func (map1 cdr lists)))))";
 
-    let start = Instant::now();
-    let scope = Rc::new(Scope::from_global());
-    eval_expr(my_map, &scope).unwrap();
-    let map2 = format!("(my-map * '{} '{})", arr_to_string(&arr1), arr_to_string(&arr2));
-    for _ in 0..LOOP {
-        let res = eval_expr(&map2, &scope).unwrap();
-        assert_eq!(res.to_string(), arr_to_string(&result));
+    for engine in ENGINES {
+        let start = Instant::now();
+        let scope = Rc::new(Scope::from_global());
+        eval_with(engine, my_map, &scope);
+        let map2 = format!("(my-map * '{} '{})", arr_to_string(&arr1), arr_to_string(&arr2));
+        for _ in 0..LOOP {
+            let res = eval_with(engine, &map2, &scope);
+            assert_eq!(res.to_string(), arr_to_string(&result));
+        }
+        let elapsed2 = start.elapsed();
+        let x2 = elapsed2.as_secs_f64() / elapsed1.as_secs_f64();
+        println!(" {:<26} {:12?} ({:.0}x * {:.0}x)", label("Scheme map", engine), elapsed2, x1, x2);
     }
-    let elapsed2 = start.elapsed();
-    let x2 = elapsed2.as_secs_f64() / elapsed1.as_secs_f64();
-    println!(" {:<20} {:12?} ({:.0}x * {:.0}x)", "Scheme map", elapsed2, x1, x2);
 }
 
 /// Counting number of primes in range 2..N in the simplest way.
@@ -197,7 +225,7 @@ fn behchmark_primes() {
         assert_eq!(res, result);
     }
     let reference_time = start.elapsed().div_f64(scale as f64);
-    println!(" {:<20} {:12?}", "Rust impl", reference_time);
+    println!(" {:<26} {:12?}", "Rust impl", reference_time);
 
     let count_primes = "
         (define (prime? x y)
@@ -214,30 +242,32 @@ fn behchmark_primes() {
         (define (count-primes n)
             (count-primes-acc n 0))";
 
-    let start = Instant::now();
-    let scope = Rc::new(Scope::from_global());
-    eval_expr(count_primes, &scope).unwrap();
-    let scm_code: String = format!("(count-primes {})", N);
-    {
-        let res = eval_expr(&scm_code, &scope).unwrap();
-        assert_eq!(res.to_string(), result.to_string());
+    for engine in ENGINES {
+        let start = Instant::now();
+        let scope = Rc::new(Scope::from_global());
+        eval_with(engine, count_primes, &scope);
+        let scm_code: String = format!("(count-primes {})", N);
+        {
+            let res = eval_with(engine, &scm_code, &scope);
+            assert_eq!(res.to_string(), result.to_string());
+        }
+        let elapsed1 = start.elapsed();
+        let x1 = elapsed1.as_secs_f64() / reference_time.as_secs_f64();
+        println!(" {:<26} {:12?} ({:.0}x)", label("Scheme impl", engine), elapsed1, x1);
     }
-    let elapsed1 = start.elapsed();
-    let x1 = elapsed1.as_secs_f64() / reference_time.as_secs_f64();
-    println!(" {:<20} {:12?} ({:.0}x)", "Scheme impl", elapsed1, x1);
 }
 
 #[test]
 fn run_bench() {
-    println!("{:=^50}", "[ Loop benchmark ]");
+    println!("{:=^56}", "[ Loop benchmark ]");
     benchmark_loops();
     println!();
 
-    println!("{:=^50}", "[ Map benchmark ]");
+    println!("{:=^56}", "[ Map benchmark ]");
     benchmark_map();
     println!();
 
-    println!("{:=^50}", "[ Count primes benchmark ]");
+    println!("{:=^56}", "[ Count primes benchmark ]");
     behchmark_primes();
     println!();
 }
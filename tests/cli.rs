@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn scheme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_scheme")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// Creates a directory for test files that is unique for the test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scheme-cli-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn expressions() {
+    let output = scheme(&["-e", "(define x 2)", "-e", "(display (* x 21))"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "42");
+
+    let output = scheme(&["-e", "(display 1) (car '())"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "1");
+    assert!(stderr(&output).starts_with("Error: "));
+
+    let output = scheme(&["--no-prelude", "-e", "(reverse '(1 2))"]);
+    assert_eq!(stderr(&output), "Error: Unbound variable reverse\n");
+
+    let output = scheme(&["--vm", "-e", "(display (fold + 0 (iota 5)))"]);
+    assert_eq!(stdout(&output), "10");
+}
+
+#[test]
+fn command_line() {
+    let output = scheme(&["-e", "(write (cdr (command-line)))", "--", "a", "-e"]);
+    assert_eq!(stdout(&output), "(\"a\" \"-e\")");
+
+    let dir = temp_dir("args");
+    let script = dir.join("args.scm");
+    std::fs::write(&script, "(write (command-line))").unwrap();
+    let script = script.to_str().unwrap();
+    let output = scheme(&[script, "1", "--no-prelude"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), format!("(\"{}\" \"1\" \"--no-prelude\")", script));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn scripts() {
+    let dir = temp_dir("scripts");
+    let lib = dir.join("lib");
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(lib.join("square.scm"), "(define (square x) (* x x))").unwrap();
+    let script = dir.join("main.scm");
+    std::fs::write(&script, "(load \"square.scm\") (display (square 7))").unwrap();
+
+    let output = scheme(&["-I", lib.to_str().unwrap(), script.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "49");
+
+    let output = scheme(&[script.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("Error: Cannot load 'square.scm': "));
+
+    let output = scheme(&[dir.join("missing.scm").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn profile() {
+    let dir = temp_dir("profile");
+    let script = dir.join("fib.scm");
+    let source = "(define (fib n)\n  (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))\n(display (fib 10))";
+    std::fs::write(&script, source).unwrap();
+    let folded = dir.join("fib.folded");
+    let output = scheme(&["--profile", folded.to_str().unwrap(), script.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "55");
+    let fib = format!("fib ({}:1)", script.display());
+    let report = stderr(&output);
+    assert!(report.lines().any(|line| line.trim_start().starts_with("177 ") && line.ends_with(&fib)), "{}", report);
+    let stacks = std::fs::read_to_string(&folded).unwrap();
+    assert!(stacks.lines().any(|line| line.contains(&format!("{};{}", fib, fib))), "{}", stacks);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn lint() {
+    let dir = temp_dir("lint");
+    let script = dir.join("lint.scm");
+    std::fs::write(&script, "(define (f x y)\n  (* x 2))\n(display (f 1))").unwrap();
+    let script = script.to_str().unwrap();
+    let output = scheme(&["--lint", script]);
+    assert_eq!(output.status.code(), Some(1));
+    let expected = format!("{0}:1: Unused parameter y\n{0}:3: Wrong number of arguments for f: 1, expected 2\n", script);
+    assert_eq!(stdout(&output), expected);
+
+    std::fs::write(dir.join("lint.scm"), "(define (f x)\n  (* x 2))\n(display (f 1))").unwrap();
+    let output = scheme(&["--lint", script]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn fmt() {
+    let dir = temp_dir("fmt");
+    let script = dir.join("fmt.scm");
+    std::fs::write(&script, "; double\n(define (double x)   (* x 2)) ; trailing\n\n\n(double 2)").unwrap();
+    let output = scheme(&["--fmt", script.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "; double\n(define (double x) (* x 2)) ; trailing\n\n(double 2)\n");
+
+    std::fs::write(&script, "(define (f x)\n  (* x 2)").unwrap();
+    let output = scheme(&["--fmt", script.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), format!("Error: {}:2: Unexpected end of input\n", script.display()));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn debugger() {
+    let dir = temp_dir("debugger");
+    let script = dir.join("fact.scm");
+    std::fs::write(&script, "(define (fact n)\n  (if (= n 0)\n      1\n      (* n (fact (- n 1)))))").unwrap();
+    let script = script.to_str().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_scheme"))
+        .args(["-i", script])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = ",break fact.scm:4\n(fact 2)\nn\n,continue\nn\n,continue\n(fact 0)\n";
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let pause = format!("; breakpoint 1, fact at {}:4: (* n (fact (- n 1)))", script);
+    let expected = format!("Breakpoint 1: fact.scm:4\n{0}\n2\n{0}\n1\n2\n1\n", pause);
+    assert_eq!(stdout(&output), expected);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn usage() {
+    let output = scheme(&["--frobnicate"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("scheme: unknown option --frobnicate"));
+
+    let output = scheme(&["-e"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = scheme(&["--help"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Usage: "));
+}
+
+#[test]
+fn exit() {
+    let output = scheme(&["-e", "(display \"a\") (exit 7) (display \"b\")"]);
+    assert_eq!(output.status.code(), Some(7));
+    assert_eq!(stdout(&output), "a");
+
+    let output = scheme(&["-e", "(display \"a\") (emergency-exit #f)"]);
+    assert_eq!(output.status.code(), Some(1));
+}
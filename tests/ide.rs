@@ -0,0 +1,91 @@
+#![cfg(feature = "ide")]
+
+//! Sessions of the debug adapter and the language server recorded in `tests/dap/*.json`
+//! and `tests/lsp/*.json`. A transcript is a list of messages from the client and from the server
+//! in their order, `${dir}` stands for the directory of the transcripts.
+
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn replace_dir(message: &Value, from: &str, to: &str) -> Value {
+    serde_json::from_str(&message.to_string().replace(from, to)).unwrap()
+}
+
+/// Splits the output of the adapter into messages
+fn messages(mut output: &str) -> Vec<Value> {
+    let mut messages = Vec::new();
+    while !output.is_empty() {
+        let (header, rest) = output.split_at(output.find("\r\n\r\n").unwrap());
+        let length: usize = header
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        messages.push(serde_json::from_str(&rest[4..4 + length]).unwrap());
+        output = &rest[4 + length..];
+    }
+    messages
+}
+
+/// Replays the transcript `tests/<server>/<name>` with `scheme --<server>`
+fn replay(server: &str, name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(server);
+    let transcript = std::fs::read_to_string(dir.join(name)).unwrap();
+    let transcript: Vec<Value> = serde_json::from_str(&transcript).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let mut input = String::new();
+    for message in transcript.iter().filter_map(|entry| entry.get("client")) {
+        let message = replace_dir(message, "${dir}", dir).to_string();
+        input += &format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_scheme"))
+        .arg(format!("--{}", server))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let expected: Vec<&Value> = transcript
+        .iter()
+        .filter_map(|entry| entry.get("server"))
+        .collect();
+    let actual = messages(&String::from_utf8(output.stdout).unwrap());
+    assert_eq!(actual.len(), expected.len(), "{:#?}", actual);
+    for (actual, expected) in actual.iter().zip(expected) {
+        assert_eq!(&replace_dir(actual, dir, "${dir}"), expected);
+    }
+}
+
+#[test]
+fn dap_breakpoints() {
+    replay("dap", "breakpoints.json");
+}
+
+#[test]
+fn dap_stepping() {
+    replay("dap", "stepping.json");
+}
+
+#[test]
+fn dap_errors() {
+    replay("dap", "errors.json");
+}
+
+#[test]
+fn lsp_session() {
+    replay("lsp", "session.json");
+}
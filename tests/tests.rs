@@ -1,38 +1,90 @@
+use std::cell::RefCell;
+use std::io::Write;
 use std::rc::Rc;
+use std::time::Duration;
 
+use scheme::convert::IntoScheme;
+use scheme::debugger::{self, Breakpoint, Pause, PauseReason, Resume};
 use scheme::errors::EvalErr;
-use scheme::eval::eval;
+use scheme::foreign;
+use scheme::functions::Function;
+use scheme::interpreter::{Engine, Interpreter, Limits};
+use scheme::lint;
+use scheme::memory::MemoryStats;
+use scheme::object::{List, Object};
 use scheme::parser::parse_expression;
+use scheme::pretty;
+use scheme::profiler;
+use scheme::repl::Repl;
 use scheme::scope::Scope;
+use scheme::symbol::Symbol;
 use scheme::{eval_expr, eval_file};
 
+/// Output buffer that stays accessible after it was given to an interpreter
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl SharedOutput {
+    fn take(&self) -> String {
+        String::from_utf8(self.0.take()).unwrap()
+    }
+}
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Every expression is checked with all engines, the tree-walker is the reference
+const ENGINES: [Engine; 3] = [Engine::TreeWalker, Engine::Analyzer, Engine::Bytecode];
+
+/// Evaluates all expressions from the string like `eval_expr` but with the given engine
+fn eval_with(engine: Engine, expr: &str, scope: &Rc<Scope>) -> Result<Rc<Object>, EvalErr> {
+    let mut result = Rc::new(Object::Nil);
+    for obj in parse_expression(expr).unwrap() {
+        result = engine.eval(&Rc::new(obj), scope)?;
+    }
+    Ok(result)
+}
+
 fn assert_eval(expr: &str, expected: &str) {
-    assert_eval_with_scope(&Rc::new(Scope::from_global()), expr, expected);
+    for engine in ENGINES {
+        assert_eval_with_engine(engine, &Rc::new(Scope::from_global()), expr, expected);
+    }
 }
 
 fn assert_eval_with_scope(scope: &Rc<Scope>, expr: &str, expected: &str) {
-    let obj = parse_expression(expr).unwrap().pop().unwrap();
-    match eval(&Rc::new(obj), &scope) {
-        Ok(obj) => assert_eq!(format!("{}", obj), expected),
-        Err(err) => panic!("{}", err),
+    for engine in ENGINES {
+        assert_eval_with_engine(engine, scope, expr, expected);
+    }
+}
+
+fn assert_eval_with_engine(engine: Engine, scope: &Rc<Scope>, expr: &str, expected: &str) {
+    match eval_with(engine, expr, scope) {
+        Ok(obj) => assert_eq!(format!("{}", obj), expected, "{:?}: {}", engine, expr),
+        Err(err) => panic!("{:?}: {}", engine, err),
     }
 }
 
 fn expect_err(expr: &str, expected: EvalErr) {
-    let scope = Scope::from_global();
-    let obj = parse_expression(expr).unwrap().pop().unwrap();
-    let result = eval(&Rc::new(obj), &Rc::new(scope));
-    match result {
-        Ok(_) => panic!(
-            "expression {} expected to evaluate with the error\n\"{}\"",
-            expr, expected
-        ),
-        Err(err) => {
-            if err != expected {
-                panic!(
-                    "expression \"{}\" expected to evaluate with the error\n\"{}\" but the actual error is \n\"{}\"",
-                    expr, expected, err
-                );
+    for engine in ENGINES {
+        let scope = Rc::new(Scope::from_global());
+        match eval_with(engine, expr, &scope) {
+            Ok(_) => panic!(
+                "{:?}: expression {} expected to evaluate with the error\n\"{}\"",
+                engine, expr, expected
+            ),
+            Err(err) => {
+                if err != expected {
+                    panic!(
+                        "{:?}: expression \"{}\" expected to evaluate with the error\n\"{}\" but the actual error is \n\"{}\"",
+                        engine, expr, expected, err
+                    );
+                }
             }
         }
     }
@@ -186,6 +238,23 @@ fn equalities() {
     assert_eval("(equal? '(2) '(2.0))", "#t");
 }
 
+#[test]
+#[rustfmt::skip]
+fn symbols() {
+    assert_eval("(list (symbol? 'a) (symbol? \"a\") (symbol? (car '(a))))", "(#t #f #t)");
+    assert_eval("(list (symbol=? 'a 'a 'a) (symbol=? 'a 'a 'b))", "(#t #f)");
+    assert_eval("(list (symbol->string 'abc) (string->symbol \"abc\"))", "(\"abc\" abc)");
+    assert_eval("(eq? (string->symbol \"abc\") 'abc)", "#t");
+    expect_err("(symbol=? 'a \"a\")", EvalErr::TypeMismatch("symbol".to_string(), "\"a\"".to_string()));
+    expect_err("(symbol=? 'a)", EvalErr::NeedAtLeastArgs("symbol=?".to_string(), 2, 1));
+
+    // uninterned symbols are equal only to themselves
+    assert_eval("(define s (string->uninterned-symbol \"abc\")) (list s (eq? s 'abc) (eq? s s))", "(abc #f #t)");
+    assert_eval("(let ((g (gensym))) (list (symbol? g) (eq? g (gensym)) (eq? g (string->symbol (symbol->string g)))))", "(#t #f #f)");
+    assert_eval("(equal? (list (generate-uninterned-symbol)) (list (generate-uninterned-symbol)))", "#f");
+    assert_eval("(equal? (symbol->string (gensym \"t\")) (symbol->string (gensym \"t\")))", "#f");
+}
+
 #[test]
 #[rustfmt::skip]
 fn prelude() {
@@ -195,6 +264,84 @@ fn prelude() {
     assert_eval_with_scope(scope, "(foldl cons '() '(1 2 3))", "(((() . 1) . 2) . 3)");
     assert_eval_with_scope(scope, "(append '(1 2) '(3 4))", "(1 2 3 4)");
     assert_eval_with_scope(scope, "(reverse '(1 2 3 4))", "(4 3 2 1)");
+    assert_eval_with_scope(scope, "(list (iota 0) (iota 3 0.5) (iota 2 1 0.5))", "(() (0.5 1.5 2.5) (1 1.5))");
+    expect_err("(iota -1)", EvalErr::TypeMismatch("non-negative integer".to_string(), "-1".to_string()));
+    expect_err("(iota 1.5)", EvalErr::TypeMismatch("integer".to_string(), "1.5".to_string()));
+    expect_err("(iota 1 'a)", EvalErr::TypeMismatch("number".to_string(), "a".to_string()));
+
+    for engine in [Engine::Analyzer, Engine::Bytecode] {
+        let scope = &Rc::new(Scope::from_global());
+        eval_with(engine, &std::fs::read_to_string("prelude.scm").unwrap(), scope).unwrap();
+        assert_eval_with_scope(scope, "(foldl cons '() '(1 2 3))", "(((() . 1) . 2) . 3)");
+        assert_eval_with_scope(scope, "(reverse '(1 2 3 4))", "(4 3 2 1)");
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn list_library() {
+    for engine in ENGINES {
+        let scope = &Rc::new(Scope::from_global());
+        eval_with(engine, scheme::PRELUDE, scope).unwrap();
+        list_library_with_scope(scope);
+    }
+
+    let output = SharedOutput::default();
+    let interpreter = Interpreter::builder().stdout(output.clone()).build().unwrap();
+    interpreter.eval_str("(for-each (lambda (x y) (display (+ x y))) '(1 2) '(10 20 30))").unwrap();
+    assert_eq!(output.take(), "1122");
+}
+
+#[rustfmt::skip]
+fn list_library_with_scope(scope: &Rc<Scope>) {
+    assert_eval_with_scope(scope, "(length (reverse (iota 10000)))", "10000");
+    assert_eval_with_scope(scope, "(length (append (iota 100000) (iota 10)))", "100010");
+    assert_eval_with_scope(scope, "(list-tail '(1 2 3 4) 2)", "(3 4)");
+    assert_eval_with_scope(scope, "(list-ref '(a b c) 1)", "b");
+    assert_eval_with_scope(scope, "(last-pair '(1 2 . 3))", "(2 . 3)");
+    assert_eval_with_scope(scope, "(let ((x '(1 2))) (list (equal? x (list-copy x)) (eqv? x (list-copy x))))", "(#t #f)");
+    assert_eval_with_scope(scope, "(list-copy '(1 2 . 3))", "(1 2 . 3)");
+    assert_eval_with_scope(scope, "(let ((l (iota 1000))) (length (list-copy (apply append (map (lambda (i) l) (iota 200))))))", "200000");
+    assert_eval_with_scope(scope, "(list (memq 'c '(a b c d)) (memq 'e '(a b c d)))", "((c d) #f)");
+    assert_eval_with_scope(scope, "(list (member '(a) '(b (a) c)) (memv '(a) '(b (a) c)))", "(((a) c) #f)");
+    assert_eval_with_scope(scope, "(member 2.0 '(1 2 3) =)", "(2 3)");
+    assert_eval_with_scope(scope, "(assq 'b '((a 1) (b 2)))", "(b 2)");
+    assert_eval_with_scope(scope, "(list (assoc '(a) '(((a)) ((b)))) (assv 5 '((2 3) (5 7))))", "(((a)) (5 7))");
+    assert_eval_with_scope(scope, "(assoc 2.0 '((1 1) (2 4) (3 9)) =)", "(2 4)");
+    assert_eval_with_scope(scope, "(filter integer? '(1 2.5 3 a))", "(1 3)");
+    assert_eval_with_scope(scope, "(list (reduce + 0 '(1 2 3)) (reduce + 0 '()) (reduce list 0 '(1 2 3)))", "(6 0 (3 (2 1)))");
+    assert_eval_with_scope(scope, "(list (iota 5) (iota 3 1) (iota 3 0 2))", "((0 1 2 3 4) (1 2 3) (0 2 4))");
+}
+
+#[test]
+#[rustfmt::skip]
+fn srfi_1() {
+    assert_eval("(list (append) (append '(1)) (append '(1) 2) (append '(1 2) '() '(3) '(4 . 5)))", "(() (1) (1 . 2) (1 2 3 4 . 5))");
+    expect_err("(append '(1 . 2) '(3))", EvalErr::ListRequired("(1 . 2)".to_string()));
+    assert_eval("(list (filter integer? '(1 2.5 3 a)) (remove integer? '(1 2.5 3 a)))", "((1 3) (2.5 a))");
+    assert_eval("(partition integer? '(1 2.5 3 a))", "((1 3) (2.5 a))");
+    assert_eval("(list (delete '(a) '(1 (a) 2 (a))) (delete 2 '(1 2 3) <))", "((1 2) (1 2))");
+    assert_eval("(list (delete-duplicates '(a b a c b)) (delete-duplicates '(1 2 1.0 3) eq?))", "((a b c) (1 2 1 3))");
+    assert_eval("(list (any integer? '(a 2.5 3)) (any < '(1 5) '(0 6 7)) (any integer? '()))", "(#t #t #f)");
+    assert_eval("(list (every integer? '(1 2.5)) (every + '(1 2) '(3 4 5)) (every integer? '()))", "(#f 6 #t)");
+    assert_eval("(list (find integer? '(a 2.5 3 4)) (find-tail integer? '(a 2.5 3 4)) (find null? '(1 2)))", "(3 (3 4) #f)");
+    assert_eval("(list (count integer? '(1 a 2)) (count < '(1 2 4 8) '(2 4 6)))", "(2 3)");
+    assert_eval("(list (list-index integer? '(a 2.5 3)) (list-index = '(3 1 2) '(2 1)) (list-index null? '(1)))", "(2 1 #f)");
+    assert_eval("(list (take '(a b c d) 2) (drop '(a b c d) 2) (drop '(a b . c) 2))", "((a b) (c d) c)");
+    assert_eval("(split-at '(a b c d e) 2)", "((a b) (c d e))");
+    expect_err("(take '(a b) 3)", EvalErr::PairRequired("()".to_string()));
+    expect_err("(drop '(a b) -1)", EvalErr::TypeMismatch("non-negative integer".to_string(), "-1".to_string()));
+    assert_eval("(append-map (lambda (x y) (list x y)) '(1 2 3) '(a b))", "(1 a 2 b)");
+    assert_eval("(list (fold cons '() '(1 2 3)) (fold (lambda (x y acc) (cons (list x y) acc)) '() '(a b) '(1 2 3)))", "((3 2 1) ((b 2) (a 1)))");
+    assert_eval("(list (fold-right cons '() '(1 2 3)) (fold-right list 0 '(1 2) '(3 4)))", "((1 2 3) (1 3 (2 4 0)))");
+    assert_eval("(list (reduce + 0 '(1 2 3)) (reduce + 0 '()) (reduce list 0 '(1 2 3)))", "(6 0 (3 (2 1)))");
+    expect_err("(fold + 0)", EvalErr::NeedAtLeastArgs("fold".to_string(), 3, 2));
+    expect_err("(delete 1)", EvalErr::NeedAtLeastArgs("delete".to_string(), 2, 1));
+    expect_err("(delete 1 '(1) = 2)", EvalErr::TooManyArguments("delete".to_string()));
+    // the number of arguments of built-ins is checked by their declared arity
+    expect_err("(gensym \"a\" \"b\")", EvalErr::TooManyArguments("gensym".to_string()));
+    expect_err("(symbol=? 'a)", EvalErr::NeedAtLeastArgs("symbol=?".to_string(), 2, 1));
+    expect_err("(length)", EvalErr::WrongAgrsNum("length".to_string(), 1, 0));
 }
 
 #[test]
@@ -231,6 +378,13 @@ fn test_let() {
 /// Verifies that tail calls are working properly.
 /// That is, tail recursion does not lead to stack overflow.
 fn test_tail_call() {
+    for engine in ENGINES {
+        tail_call(engine);
+    }
+}
+
+#[rustfmt::skip]
+fn tail_call(engine: Engine) {
     let scope = &Rc::new(Scope::from_global());
     
     // sum of 10000 consecutive integers
@@ -239,8 +393,8 @@ fn test_tail_call() {
           (define (seq-sum n acc)
             (if (= 0 n)  acc (seq-sum (- n 1) (+ acc n))))
           (seq-sum n 0))";
-    eval_expr(seq_sum, scope).unwrap();
-    assert_eval_with_scope(scope, "(seq-sum 10000)", "50005000");
+    eval_with(engine, seq_sum, scope).unwrap();
+    assert_eval_with_engine(engine, scope, "(seq-sum 10000)", "50005000");
 
     // mutual recursion
     let is_odd = "
@@ -249,9 +403,9 @@ fn test_tail_call() {
     let is_even = "
         (define (even? n)
             (if (= n 0) #t (odd? (- n 1))))";
-    eval_expr(is_odd, scope).unwrap();
-    eval_expr(is_even, scope).unwrap();
-    assert_eval_with_scope(scope, "(map even? '(10500 9999))", "(#t #f)");
+    eval_with(engine, is_odd, scope).unwrap();
+    eval_with(engine, is_even, scope).unwrap();
+    assert_eval_with_engine(engine, scope, "(map even? '(10500 9999))", "(#t #f)");
 
     // tail recursion with 'apply'
     let seq_sum = "
@@ -259,6 +413,673 @@ fn test_tail_call() {
           (define (seq-sum n acc)
             (if (= 0 n) acc (apply seq-sum (list (- n 1) (+ acc n)))))
           (seq-sum n 0))";
-    eval_expr(seq_sum, scope).unwrap();
-    assert_eval_with_scope(scope, "(seq-sum 10000)", "50005000");
+    eval_with(engine, seq_sum, scope).unwrap();
+    assert_eval_with_engine(engine, scope, "(seq-sum 10000)", "50005000");
+}
+
+#[test]
+#[rustfmt::skip]
+fn bytecode() {
+    // internal definitions may refer to the ones that follow
+    assert_eval("(define (h) (define (f) (g)) (define (g) 42) (f)) (h)", "42");
+    expect_err("(define (h) (define y x) (define x 1) y) (h)", EvalErr::UnboundVariable("x".to_string()));
+    assert_eval("(let ((x 1) (x 2)) x)", "2");
+    assert_eval("(let* ((x 1) (x (+ x 1))) (define y x) (list x y))", "(2 2)");
+    assert_eval("(let ((f (lambda (x) (lambda () x)))) (map (lambda (g) (g)) (list (f 1) (f 2))))", "(1 2)");
+
+    // functions made by one engine can be called by the other
+    let scope = &Rc::new(Scope::from_global());
+    eval_with(Engine::Bytecode, "(define (adder x) (lambda (y) (+ x y)))", scope).unwrap();
+    eval_with(Engine::TreeWalker, "(define add2 (adder 2))", scope).unwrap();
+    assert_eval_with_scope(scope, "(map add2 '(1 2))", "(3 4)");
+
+    // calls of compiled functions don't use the Rust stack
+    eval_with(Engine::Bytecode, "(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))", scope).unwrap();
+    assert_eval_with_engine(Engine::Bytecode, scope, "(sum 100000)", "5000050000");
+
+    // the analyzer and the compiler report syntax errors before anything runs,
+    // the tree-walker only when the malformed form is evaluated
+    let scope = &Rc::new(Scope::from_global());
+    assert_eval_with_engine(Engine::TreeWalker, scope, "(if #t 1 (lambda))", "1");
+    assert_eval_with_engine(Engine::TreeWalker, scope, "(define (f) (display 1) (if)) 2", "2");
+    for engine in [Engine::Analyzer, Engine::Bytecode] {
+        let scope = &Rc::new(Scope::from_global());
+        let err = eval_with(engine, "(if #t 1 (lambda))", scope).unwrap_err();
+        assert_eq!(err, EvalErr::TooFewArguments("lambda".to_string()), "{:?}", engine);
+        let err = eval_with(engine, "(define (f) (display 1) (if)) 2", scope).unwrap_err();
+        assert_eq!(err, EvalErr::WrongAgrsNum("if".to_string(), 3, 0), "{:?}", engine);
+    }
+
+    let interpreter = Interpreter::builder().engine(Engine::Bytecode).build().unwrap();
+    let mut repl = Repl::new(interpreter);
+    assert_eq!(repl.feed(",describe member"), vec!["member: procedure (member x lst . compare)"]);
+    assert_eq!(repl.feed("(iota 3 1)"), vec!["(1 2 3)"]);
+}
+
+#[test]
+#[rustfmt::skip]
+fn lexical_addressing() {
+    assert_eval("(define (adder a) (lambda (b) (lambda (c) (list a b c)))) (((adder 1) 2) 3)", "(1 2 3)");
+    assert_eval("(define (f list) (list 1)) (f (lambda (x) (+ x 1)))", "2");
+    assert_eval("(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                          (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                   (even? 10))", "#t");
+    expect_err("(let ((x 1)) (define y 2) (+ x y)) y", EvalErr::UnboundVariable("y".to_string()));
+
+    // globals are looked up by name when a function is called
+    let scope = &Rc::new(Scope::from_global());
+    for engine in ENGINES {
+        eval_with(engine, "(define (f) (g)) (define (g) 1)", scope).unwrap();
+        assert_eval_with_engine(engine, scope, "(f)", "1");
+        eval_with(engine, "(define (g) 2)", scope).unwrap();
+        assert_eval_with_engine(engine, scope, "(f)", "2");
+    }
+}
+
+#[test]
+fn interpreter() {
+    let output = SharedOutput::default();
+    let first = Interpreter::builder()
+        .prelude_source("(define (square x) (* x x))")
+        .stdout(output.clone())
+        .build()
+        .unwrap();
+    let second = Interpreter::builder().no_prelude().build().unwrap();
+
+    assert_eq!(first.eval_str("(square 5)").unwrap().to_string(), "25");
+    assert!(second.eval_str("(square 5)").is_err());
+    assert_eq!(first.call("square", vec![3i64.into_scheme()]), Ok(9i64.into_scheme()));
+    assert_eq!(
+        second.call("square", vec![]),
+        Err(EvalErr::UnboundVariable("square".to_string()))
+    );
+
+    second.set_global("x", 42i64);
+    assert_eq!(second.get_global("x"), Some(42i64.into_scheme()));
+    assert_eq!(first.get_global("x"), None);
+
+    first.eval_str("(display \"x = \") (write \"y\") (newline)").unwrap();
+    assert_eq!(output.take(), "x = \"y\"\n");
+
+    let limited = Interpreter::builder()
+        .prelude_file("prelude.scm")
+        .limits(Limits { max_steps: Some(1000), ..Limits::default() })
+        .build()
+        .unwrap();
+    assert!(limited.eval_str("(reverse '(1 2 3))").is_ok());
+    let err = limited.call("reverse", vec![(0..100).collect::<Vec<i64>>().into_scheme()]);
+    assert_eq!(err, Err(EvalErr::LimitExceeded("steps", 1000)));
+}
+
+fn limited(engine: Engine, limits: Limits) -> (Interpreter, SharedOutput) {
+    let output = SharedOutput::default();
+    let builder = Interpreter::builder().engine(engine).stdout(output.clone());
+    (builder.limits(limits).build().unwrap(), output)
+}
+
+#[test]
+fn resource_limits() {
+    for engine in ENGINES {
+        limits_with_engine(engine);
+    }
+}
+
+#[rustfmt::skip]
+fn limits_with_engine(engine: Engine) {
+    let (interpreter, _) = limited(engine, Limits { max_time: Some(Duration::from_millis(50)), ..Limits::default() });
+    let err = interpreter.eval_str("(define (loop) (loop)) (loop)").unwrap_err();
+    assert_eq!(err.to_string(), "Evaluation exceeded the limit of 50 milliseconds");
+
+    let (interpreter, _) = limited(engine, Limits { max_depth: Some(100), ..Limits::default() });
+    interpreter.eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))").unwrap();
+    assert_eq!(interpreter.eval_str("(sum 10)").unwrap().to_string(), "55");
+    let err = interpreter.eval_str("(sum 1000000)").unwrap_err();
+    assert_eq!(err.to_string(), "Evaluation exceeded the limit of 100 nested evaluations");
+    // tail calls don't make the evaluation deeper
+    assert!(interpreter.eval_str("(length (iota 10000))").is_ok());
+
+    let (interpreter, _) = limited(engine, Limits { max_heap: Some(1000), ..Limits::default() });
+    interpreter.eval_str("(define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))").unwrap();
+    assert!(interpreter.eval_str("(length (build 400 '()))").is_ok());
+    // the garbage doesn't count
+    assert!(interpreter.eval_str("(begin (build 400 '()) (build 400 '()) 1)").is_ok());
+    // every pair holds a number, so only a half of the objects are pairs
+    assert!(interpreter.eval_str("(length (build 900 '()))").is_err());
+    let err = interpreter.eval_str("(length (build 100000 '()))").unwrap_err();
+    assert_eq!(err.to_string(), "Evaluation exceeded the limit of 1000 objects");
+    // objects of all types count and the aborted evaluation frees all of its objects
+    let live = || interpreter.stats().objects.iter().map(|s| s.live).sum::<u64>();
+    let before = live();
+    for _ in 0..20 {
+        assert!(interpreter.eval_str("(length (build 400 '()))").is_ok());
+    }
+    let err = interpreter.eval_str("(length (map (lambda (x) (symbol->string 'abc)) (build 400 '())))").unwrap_err();
+    assert_eq!(err.to_string(), "Evaluation exceeded the limit of 1000 objects");
+    assert_eq!(live(), before);
+
+    let (interpreter, output) = limited(engine, Limits { max_output: Some(10), ..Limits::default() });
+    interpreter.eval_str("(define (spam n) (display \"abc\") (spam (+ n 1)))").unwrap();
+    let err = interpreter.eval_str("(spam 0)").unwrap_err();
+    assert_eq!(err.to_string(), "Evaluation exceeded the limit of 10 bytes of output");
+    assert_eq!(output.take(), "abcabcabc");
+    // the limit is applied to every expression separately
+    assert!(interpreter.eval_str("(display \"0123456789\")").is_ok());
+}
+
+#[test]
+fn cycle_collection() {
+    for engine in ENGINES {
+        collect_with_engine(engine);
+    }
+    assert_eval("(list (integer? (gc)) (map car (gc-stats)))", "(#t (collections freed tracked))");
+}
+
+#[rustfmt::skip]
+fn collect_with_engine(engine: Engine) {
+    let interpreter = Interpreter::builder().engine(engine).build().unwrap();
+    interpreter.eval_str("(define (make n) (define (count k) (if (= k n) k (count (+ k 1)))) count)").unwrap();
+    interpreter.eval_str("(define counter (make 3))").unwrap();
+    let counter = Rc::downgrade(&interpreter.get_global("counter").unwrap());
+    interpreter.gc();
+    assert_eq!(interpreter.eval_str("(counter 0)").unwrap().to_string(), "3");
+    // the closure and the environment of the call it was defined in reference each other
+    interpreter.eval_str("(define counter #f)").unwrap();
+    assert!(counter.upgrade().is_some());
+    assert!(interpreter.gc() >= 1);
+    assert!(counter.upgrade().is_none());
+
+    // closures used by Rust code, by data and by the running evaluation are not freed
+    let held = interpreter.eval_str("(make 5)").unwrap();
+    interpreter.gc();
+    match held.as_ref() {
+        Object::Function { 0: f, .. } => assert_eq!(f.invoke(vec![0.into_scheme()]).unwrap().to_string(), "5"),
+        x => panic!("{:?}: function expected, got {}", engine, x),
+    }
+    assert_eq!(interpreter.eval_str("(define l (list (make 4))) (gc) ((car l) 0)").unwrap().to_string(), "4");
+    assert_eq!(interpreter.eval_str("(let ((c (make 2))) (gc) (c 0))").unwrap().to_string(), "2");
+
+    // top-level functions reference the scope of the interpreter
+    let scope = Rc::downgrade(interpreter.scope());
+    drop(held);
+    drop(interpreter);
+    assert!(scope.upgrade().is_some());
+    scheme::gc::collect();
+    assert!(scope.upgrade().is_none(), "{:?}", engine);
+}
+
+#[test]
+fn memory_statistics() {
+    for engine in ENGINES {
+        memory_with_engine(engine);
+    }
+    assert_eval("(map car (memory-stats))", "(allocated live scopes frames bytes peak-bytes collections)");
+    assert_eval("(map car (cdr (car (memory-stats))))", "(boolean symbol string number pair function foreign)");
+}
+
+#[rustfmt::skip]
+fn memory_with_engine(engine: Engine) {
+    let live = |stats: &MemoryStats, type_name| stats.objects.iter().find(|s| s.type_name == type_name).unwrap().live;
+    let interpreter = Interpreter::builder().engine(engine).no_prelude().build().unwrap();
+    let before = interpreter.stats();
+    interpreter.eval_str("(define l (list 1 2 \"abc\"))").unwrap();
+    let after = interpreter.stats();
+    assert_eq!(live(&after, "pair"), live(&before, "pair") + 3, "{:?}", engine);
+    let allocated = |stats: &MemoryStats| stats.objects.iter().map(|s| s.allocated).collect::<Vec<_>>();
+    let (a, b) = (allocated(&after), allocated(&before));
+    assert!((1..=4).all(|i| a[i] > b[i]), "symbols, strings, numbers and pairs are made by {:?}", engine);
+    assert!(after.scopes >= 2 && after.peak_bytes >= after.bytes && after.bytes > before.bytes);
+
+    let dump = interpreter.heap_dump();
+    let count = |type_name| dump.entries.iter().find(|e| e.type_name == type_name).map_or(0, |e| e.count);
+    assert_eq!((count("pair"), count("number"), count("string"), count("scope")), (3, 2, 1, 2));
+    assert!(dump.to_string().ends_with(&format!("{}", dump.entries.iter().map(|e| e.bytes).sum::<u64>())));
+
+    interpreter.eval_str("(define l #f)").unwrap();
+    assert_eq!(live(&interpreter.stats(), "pair"), live(&before, "pair"));
+}
+
+#[test]
+#[rustfmt::skip]
+fn memory_counters_balance() {
+    let live = || scheme::memory::stats().objects.iter().map(|s| s.live).collect::<Vec<_>>();
+    let baseline = live();
+    let objects = vec![
+        Object::from(true),
+        Object::from(Symbol::intern("a")),
+        Object::from("abc".to_string()),
+        Object::make_pair(Object::make_int(1), Object::Nil),
+        Function::from_pointer(|args| Ok(args[0].clone())),
+        Object::make_foreign(1),
+    ];
+    assert_eq!(live().iter().zip(&baseline).map(|(a, b)| a - b).collect::<Vec<_>>(), [1, 1, 1, 1, 1, 1, 1]);
+    drop(objects);
+    assert_eq!(live(), baseline);
+
+    for engine in ENGINES {
+        let interpreter = Interpreter::builder().engine(engine).no_prelude().build().unwrap();
+        let before = live();
+        interpreter.eval_str("(length (map (lambda (x) (list x \"s\" 'a 1.5 #t)) '(1 2 3)))").unwrap();
+        assert_eq!(live(), before, "{:?}", engine);
+    }
+}
+
+#[test]
+fn profiling() {
+    for engine in ENGINES {
+        profile_with_engine(engine);
+    }
+}
+
+#[rustfmt::skip]
+fn profile_with_engine(engine: Engine) {
+    let interpreter = Interpreter::builder().engine(engine).no_prelude().build().unwrap();
+    interpreter.eval_str("(define (count n) (if (= n 0) 0 (count (- n 1))))\n(define (twice f) (f) (f))").unwrap();
+    profiler::start();
+    interpreter.eval_str("(twice (lambda () (count 100)))").unwrap();
+    let profile = profiler::stop();
+    let procedure = |name| profile.procedures.iter().find(|p| p.name == name).unwrap();
+    let calls = |name| (procedure(name).calls, procedure(name).location.as_ref().unwrap().to_string());
+    assert_eq!(calls("count"), (202, "line 1".to_string()), "{:?}", engine);
+    assert_eq!(calls("twice"), (1, "line 2".to_string()), "{:?}", engine);
+    assert_eq!(calls("#<lambda>"), (2, "line 1".to_string()), "{:?}", engine);
+    assert!(profile.procedures.iter().all(|p| p.inclusive >= p.exclusive));
+    // tail calls replace the calling procedure
+    let stacks = profile.stacks.iter().map(|(stack, _)| stack.as_str()).collect::<Vec<_>>();
+    assert!(stacks.contains(&"count (line 1)") && stacks.contains(&"twice (line 2);count (line 1)"), "{:?}", stacks);
+    assert!(profile.folded().lines().all(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().is_ok()));
+
+    let output = SharedOutput::default();
+    let interpreter = Interpreter::builder().engine(engine).stdout(output.clone()).build().unwrap();
+    let result = interpreter.eval_str("(define (sq x) (* x x)) (profile (lambda () (map sq (iota 3))))").unwrap();
+    assert_eq!(result.to_string(), "(0 1 4)");
+    let report = output.take();
+    assert!(report.starts_with("     calls   inclusive ms   exclusive ms  procedure\n"), "{}", report);
+    assert!(report.lines().any(|line| line.trim_start().starts_with("3 ") && line.ends_with(" sq (line 1)")), "{}", report);
+    assert!(!profiler::is_running());
+}
+
+#[test]
+#[rustfmt::skip]
+fn tracing() {
+    for engine in ENGINES {
+        let (interpreter, output) = limited(engine, Limits { max_depth: Some(100), ..Limits::default() });
+        interpreter.eval_str("(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))
+                              (define (count n) (if (= n 0) 'done (count (- n 1))))
+                              (define (start n) (count n))").unwrap();
+        assert_eq!(interpreter.eval_str("(trace fact count start car)").unwrap().to_string(), "(fact count start car)");
+        interpreter.eval_str("(fact 2) (start 2) (map car '((1) (\"a\")))").unwrap();
+        assert_eq!(output.take(), "\
+|(fact 2)\n| (fact 1)\n| |(fact 0)\n| |1\n| 1\n|2
+|(start 2)\n|(count 2)\n|(count 1)\n|(count 0)\n|done
+|(car (1))\n|1\n|(car (\"a\"))\n|\"a\"\n", "{:?}", engine);
+        // traced tail calls don't keep frames
+        interpreter.eval_str("(untrace car start) (count 1000)").unwrap();
+        assert_eq!(output.take().lines().count(), 1002);
+        interpreter.eval_str("(untrace) (fact 3)").unwrap();
+        assert_eq!(output.take(), "");
+        // built-ins sharing an implementation are traced by their own names
+        let traced = interpreter.eval_str("(trace generate-uninterned-symbol gensym)").unwrap();
+        assert_eq!(traced.to_string(), "(generate-uninterned-symbol gensym)");
+        interpreter.eval_str("(untrace)").unwrap();
+        let err = interpreter.eval_str("(trace 1)").unwrap_err();
+        assert_eq!(err.to_string(), "Procedure expected, got 1");
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn linting() {
+    let interpreter = Interpreter::new().unwrap();
+    let lint = |source: &str| -> Vec<String> {
+        lint::lint(source, None, interpreter.scope()).iter().map(|w| w.to_string()).collect()
+    };
+    assert_eq!(lint("(define (f a b) (+ a 1))\n(define (f a) a)\n(f 1 2)"), [
+        "line 1: Unused parameter b",
+        "line 2: f is already defined at line 1",
+        "line 3: Wrong number of arguments for f: 2, expected 1",
+    ]);
+    assert_eq!(lint("(define (g x)\n  (let* ((y x) (z 2) (_w 3))\n    (if y 1 2 3)))"), [
+        "line 2: Unused variable z",
+        "line 3: Too many branches for if: 3",
+    ]);
+    assert_eq!(lint("(car 1 2)\n(cons 1)\n(iota)\n(caddr '(1 2 3))\n(display y)\n(define (list . xs) xs)"), [
+        "line 1: Wrong number of arguments for car: 2, expected 1",
+        "line 2: Wrong number of arguments for cons: 1, expected 2",
+        "line 3: Wrong number of arguments for iota: 0, expected 1 to 3",
+        "line 5: Unbound variable y",
+        "line 6: list shadows a built-in",
+    ]);
+    assert_eq!(lint("(symbol=? 'a)\n(gensym \"a\" \"b\")\n(= 1 2 3)\n(trace)"), [
+        "line 1: Wrong number of arguments for symbol=?: 1, expected at least 2",
+        "line 2: Wrong number of arguments for gensym: 2, expected 0 to 1",
+    ]);
+    // the same checks as at runtime
+    assert_eq!(lint("(lambda (x x) 1)\n(let ((1 2)) 3)\n(let x)\n(cond)\n(if 1 2)"), [
+        "line 1: Duplication of argument id: x",
+        "line 2: Need symbol for 'let' binding, got 1",
+        "line 3: \"let\" need at least 2 arguments, found 1",
+        "line 4: 'cond' needs at least 1 clause",
+        "line 5: Wrong number of arguments for if: 2",
+    ]);
+    // definitions of bodies are known before they are evaluated, quoted data is not checked
+    assert_eq!(lint("(define (even? n) (if (= n 0) #t (odd? (- n 1))))
+                  (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+                  (letrec ((f (lambda (n) (if (= n 0) 'x (f (- n 1)))))) (f 2))
+                  (cond ((assq 'a '((a 1))) 1) (else '(unbound 1 2)))"), Vec::<String>::new());
+    assert_eq!(lint("(define (f) (define (g) (h)) (define (h) 1) (g))\n(quote (x y))"), Vec::<String>::new());
+    assert_eq!(lint("(display 1)\n(car"), ["line 2: Unexpected end of input"]);
+}
+
+#[test]
+#[rustfmt::skip]
+fn pretty_printing() {
+    let code = "(define (f x) (let ((y (* x 2))) (cond ((> y 10) \"big\") (else (g y x y)))))";
+    let obj = &parse_expression(code).unwrap()[0];
+    assert_eq!(pretty::pretty(obj, 80), code);
+    assert_eq!(pretty::pretty(obj, 30), "\
+(define (f x)
+  (let ((y (* x 2)))
+    (cond ((> y 10) \"big\")
+          (else (g y x y)))))");
+    for engine in ENGINES {
+        let (interpreter, output) = limited(engine, Limits::default());
+        interpreter.eval_str("(pretty-print (iota 20) 20) (pretty-print '((a . b) (c d)) 8)").unwrap();
+        assert_eq!(output.take(), "(0 1 2 3 4 5 6 7 8 9\n 10 11 12 13 14 15\n 16 17 18 19)\n((a . b)\n (c d))\n");
+    }
+}
+
+#[test]
+#[rustfmt::skip]
+fn formatting() {
+    let source = "; factorial\n(define (fact n) ; recursive\n  (if (= n 0)   1 (* n (fact (- n 1)))))\n\n\n\n\
+                  (define (f x)\n;; comment\n(list x '(1 . 2) 1e3 ; trailing\n))";
+    let formatted = pretty::format_source(source, 40).unwrap();
+    assert_eq!(formatted, "\
+; factorial
+(define (fact n) ; recursive
+  (if (= n 0) 1 (* n (fact (- n 1)))))
+
+(define (f x)
+  ;; comment
+  (list x
+        '(1 . 2)
+        1000.0 ; trailing
+        ))
+");
+    assert_eq!(pretty::format_source(&formatted, 40).unwrap(), formatted);
+    assert_eq!(pretty::format_source("(a)\n(b ; c)", 40).map_err(|(err, line)| (err.to_string(), line)),
+               Err(("Unexpected end of input".to_string(), 2)));
+}
+
+const DEBUGGED: &str ="(define (inc x)\n  (+ x 1))\n(define (twice y)\n  (let ((z (inc y)))\n    (* 2 z)))";
+
+/// Evaluates `(twice 3)` resuming the pauses in the given ways, returns the pauses shown as strings
+fn debug(interpreter: &Interpreter, resumes: &[Resume]) -> (Result<String, String>, Vec<String>) {
+    let pauses = Rc::new(RefCell::new(vec![]));
+    let log = pauses.clone();
+    let mut resumes = resumes.to_vec();
+    debugger::attach(move |pause: &Pause| {
+        log.borrow_mut().push(pause.frames[0].to_string());
+        resumes.remove(0)
+    });
+    let result = interpreter.eval_str("(twice 3)").map(|v| v.to_string()).map_err(|e| e.to_string());
+    debugger::detach();
+    (result, pauses.take())
+}
+
+#[test]
+#[rustfmt::skip]
+fn debugging() {
+    let interpreter = Interpreter::new().unwrap();
+    interpreter.eval_str(DEBUGGED).unwrap();
+
+    let id = debugger::add_breakpoint(Breakpoint::parse("inc"));
+    let frames = Rc::new(RefCell::new(vec![]));
+    let log = frames.clone();
+    debugger::attach(move |pause: &Pause| {
+        assert_eq!(pause.reason, PauseReason::Breakpoint(id));
+        log.borrow_mut().extend(pause.frames.iter().map(|frame| frame.to_string()));
+        assert_eq!(pause.frames[0].eval("x").unwrap().to_string(), "3");
+        assert_eq!(pause.frames[1].eval("(list y z)").unwrap_err().to_string(), "Unbound variable z");
+        assert_eq!(pause.frames[0].scopes().len(), 3);
+        Resume::Continue
+    });
+    assert_eq!(interpreter.eval_str("(twice 3)").unwrap().to_string(), "8");
+    assert_eq!(frames.take(), vec![
+        "inc at line 2: (+ x 1)", "twice at line 4: (inc y)", "top level at line 1: (twice 3)",
+    ]);
+    assert!(debugger::remove_breakpoint(id) && !debugger::remove_breakpoint(id));
+
+    // a breakpoint on a line pauses at the outermost form
+    let id = debugger::add_breakpoint(Breakpoint::parse("4"));
+    let (result, pauses) = debug(&interpreter, &[Resume::Continue]);
+    assert_eq!((result, pauses), (Ok("8".to_string()), vec!["twice at line 4: (let ((z (inc y))) (* 2 z))".to_string()]));
+    debugger::remove_breakpoint(id);
+
+    debugger::step_in();
+    let (result, pauses) = debug(&interpreter, &[Resume::StepIn, Resume::StepIn, Resume::StepIn, Resume::StepOut, Resume::StepOver]);
+    assert_eq!(result.unwrap(), "8");
+    assert_eq!(pauses, vec![
+        "top level at line 1: (twice 3)",
+        "twice at line 4: (let ((z (inc y))) (* 2 z))",
+        "twice at line 4: (inc y)",
+        "inc at line 2: (+ x 1)",
+        "twice at line 5: (* 2 z)",
+    ]);
+
+    // stepping over a call doesn't pause in the called procedure
+    debugger::step_in();
+    let (_, pauses) = debug(&interpreter, &[Resume::StepIn, Resume::StepIn, Resume::StepOver, Resume::Continue]);
+    assert_eq!(pauses.last().unwrap(), "twice at line 5: (* 2 z)");
+
+    debugger::step_in();
+    let (result, _) = debug(&interpreter, &[Resume::Abort]);
+    assert_eq!(result.unwrap_err(), EvalErr::Interrupted().to_string());
+    assert_eq!(interpreter.eval_str("(twice 3)").unwrap().to_string(), "8");
+
+    assert_eq!(Breakpoint::parse("fib.scm:12"), Breakpoint::Line { file: Some("fib.scm".to_string()), line: 12 });
+    assert_eq!(Breakpoint::parse("a:b"), Breakpoint::Procedure("a:b".to_string()));
+}
+
+#[test]
+#[rustfmt::skip]
+fn sandbox() {
+    let interpreter = Interpreter::builder().sandbox().build().unwrap();
+    for name in ["exit", "emergency-exit", "command-line", "get-environment-variable", "get-environment-variables"] {
+        let err = interpreter.eval_str(&format!("({})", name)).unwrap_err();
+        assert_eq!(err.to_string(), format!("Unbound variable {}", name));
+    }
+    let err = interpreter.eval_str("(load \"prelude.scm\")").unwrap_err();
+    assert_eq!(err.to_string(), "Unbound variable load");
+    assert_eq!(interpreter.eval_str("(reverse (list 1 2))").unwrap().to_string(), "(2 1)");
+    assert!(Interpreter::new().unwrap().eval_str("(load \"prelude.scm\")").is_ok());
+}
+
+struct Counter(RefCell<i64>);
+
+fn counter_next(args: List) -> Result<Rc<Object>, EvalErr> {
+    let counter = foreign::downcast::<Counter>(&args[0])?;
+    *counter.0.borrow_mut() += 1;
+    let value = *counter.0.borrow();
+    Ok(value.into_scheme())
+}
+
+#[test]
+#[rustfmt::skip]
+fn foreign_objects() {
+    let interpreter = Interpreter::builder().no_prelude().build().unwrap();
+    interpreter.set_global("counter?", foreign::predicate::<Counter>());
+    interpreter.set_global("next", Function::from_pointer(counter_next));
+    interpreter.set_global("c1", Object::make_foreign(Counter(RefCell::new(0))));
+    interpreter.set_global("c2", Object::make_foreign(Counter(RefCell::new(10))));
+    interpreter.set_global("named", Object::from(foreign::Foreign::with_name("socket", 1)));
+
+    let eval = |expr| interpreter.eval_str(expr).map(|obj| obj.to_string()).map_err(|e| e.to_string());
+    assert_eq!(eval("(list (next c1) (next c1) (next c2))"), Ok("(1 2 11)".to_string()));
+    assert_eq!(eval("(list (counter? c1) (counter? named) (counter? 5))"), Ok("(#t #f #f)".to_string()));
+    assert_eq!(eval("(list (eqv? c1 c1) (eqv? c1 c2) (equal? c1 c2))"), Ok("(#t #f #f)".to_string()));
+    assert_eq!(eval("named"), Ok("#<socket>".to_string()));
+    assert_eq!(eval("(next named)"), Err("tests::Counter expected, got #<socket>".to_string()));
+}
+
+#[test]
+#[rustfmt::skip]
+fn repl_input() {
+    let mut repl = Repl::new(Interpreter::new().unwrap());
+    assert_eq!(repl.prompt(), "> ");
+    assert!(repl.feed("(define (f x)").is_empty());
+    assert_eq!(repl.prompt(), "... ");
+    assert!(repl.feed("").is_empty());
+    assert!(repl.feed("  (list \"a").is_empty());
+    assert_eq!(repl.feed("b\" x)) (f 1) (f 2)"), vec!["()", "(\"a\nb\" 1)", "(\"a\nb\" 2)"]);
+    assert_eq!(repl.prompt(), "> ");
+    assert_eq!(repl.feed("(f) (f 1)"), vec!["Error: Too few arguments given to f"]);
+    assert_eq!(repl.feed(") (f 1)"), vec!["Error: Unexpected token: \"Rpar\""]);
+    assert!(!repl.is_pending());
+
+    let input = "(+ 1\n 2) (list\n 3)\n(car\n";
+    let mut output = Vec::new();
+    repl.run(input.as_bytes(), &mut output, false).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output, "3\n(3)\nError: Unexpected end of input\n");
+
+    let mut output = Vec::new();
+    repl.run(&b"\"\xff\"\r\n(define abc 1)"[..], &mut output, false).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "\"\u{fffd}\"\n()\n");
+    let names = repl.interpreter().scope().names();
+    assert!(names.contains(&"abc".to_string()) && names.contains(&"cons".to_string()));
+}
+
+#[test]
+#[rustfmt::skip]
+fn repl_commands() {
+    let interpreter = Interpreter::builder().prelude_source("(define (inc x) (+ x 1))").build().unwrap();
+    let mut repl = Repl::new(interpreter);
+    assert!(repl.feed(",help")[0].starts_with(",help"));
+    assert_eq!(repl.feed(",describe inc"), vec!["inc: procedure (inc x)"]);
+    repl.feed("(define (f a . rest) a) (define g (lambda x x)) (define n 5)");
+    assert_eq!(repl.feed(",describe f"), vec!["f: procedure (f a . rest)"]);
+    assert_eq!(repl.feed(",describe g"), vec!["g: procedure (#<lambda> . x)"]);
+    assert_eq!(repl.feed(",describe car"), vec!["car: built-in procedure"]);
+    assert_eq!(repl.feed(",describe n"), vec!["n: 5"]);
+    assert_eq!(repl.feed(",describe m"), vec!["Error: Unbound variable m"]);
+    assert_eq!(repl.feed(",describe"), vec!["Error: ,describe expects an argument"]);
+    assert_eq!(repl.feed(",env").len(), 4);
+
+    let output = repl.feed(",time (inc 1) (inc 2)");
+    assert_eq!(&output[..2], &["2", "3"]);
+    assert!(output[2].starts_with("; elapsed "));
+
+    assert_eq!(repl.feed(",expand (let ((x 1)) x)"), vec!["((lambda (x) x) 1)"]);
+    assert_eq!(repl.feed(",expand (let ((1 2)) 3)"), vec!["Error: Need symbol for 'let' binding, got 1"]);
+    assert_eq!(repl.feed(",expand (f 1)"), vec!["(f 1)"]);
+    assert_eq!(repl.feed(",expand (cond (x 1))"), vec!["(if x (begin 1) (begin))"]);
+    assert_eq!(repl.feed(",expand (cond)"), vec!["Error: 'cond' needs at least 1 clause"]);
+
+    let heap = repl.feed(",heap");
+    assert!(heap[0].starts_with("type") && heap.last().unwrap().starts_with("total"));
+
+    let path = std::env::temp_dir().join(format!("repl-load-{}.scm", std::process::id()));
+    std::fs::write(&path, "(define loaded 1)").unwrap();
+    assert_eq!(repl.feed(&format!(",load {}", path.display())), vec![format!("Loaded {}", path.display())]);
+    assert_eq!(repl.feed("loaded"), vec!["1"]);
+    std::fs::write(&path, "(define loaded 2)").unwrap();
+    repl.feed(",load");
+    assert_eq!(repl.feed("loaded"), vec!["2"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(repl.feed(",reset"), vec!["Environment is reset"]);
+    assert_eq!(repl.feed("(list (inc 1) n)"), vec!["Error: Unbound variable n"]);
+    assert_eq!(repl.feed(",env").len(), 1);
+    assert_eq!(repl.feed(",frobnicate"), vec!["Error: unknown command ,frobnicate, type ,help for the list"]);
+}
+
+#[test]
+#[rustfmt::skip]
+fn repl_debugger() {
+    let mut repl = Repl::new(Interpreter::new().unwrap());
+    let output = SharedOutput::default();
+    let input = "x\n,backtrace\n,locals\n,frame 1\ny\n,finish\n,continue\n,step\n,continue\n";
+    repl.set_debugger_io(Box::new(input.as_bytes()), Box::new(output.clone()));
+    repl.feed(DEBUGGED);
+    assert_eq!(repl.feed(",break inc"), vec!["Breakpoint 1: inc"]);
+    assert_eq!(repl.feed(",breakpoints"), vec!["1: inc"]);
+    assert_eq!(repl.feed("(twice 3)"), vec!["8"]);
+    assert_eq!(output.take(), "\
+; breakpoint 1, inc at line 2: (+ x 1)
+3
+#0 inc at line 2: (+ x 1)
+#1 twice at line 4: (inc y)
+#2 top level at line 1: (twice 3)
+; scope 0
+x = 3
+#1 twice at line 4: (inc y)
+3
+; twice at line 5: (* 2 z)
+");
+    assert_eq!(repl.feed(",delete"), vec!["All breakpoints are deleted"]);
+    assert_eq!(repl.feed(",step (inc 1)"), vec!["2"]);
+    assert_eq!(output.take(), "; top level at line 1: (inc 1)\n; inc at line 2: (+ x 1)\n");
+    // the end of the input aborts the evaluation
+    assert_eq!(repl.feed(",step (inc 1)"), vec![format!("Error: {}", EvalErr::Interrupted())]);
+    assert_eq!(repl.feed(",delete 5"), vec!["Error: no breakpoint 5"]);
+
+    let mut repl = Repl::new(Interpreter::builder().engine(Engine::Bytecode).build().unwrap());
+    assert_eq!(repl.feed(",break inc"), vec!["Error: the debugger works only with the tree-walker"]);
+}
+
+#[test]
+#[rustfmt::skip]
+fn process_context() {
+    assert_eval("(get-environment-variable \"CARGO_PKG_NAME\")", "\"scheme\"");
+    assert_eval("(get-environment-variable \"SCHEME_SURELY_UNSET_VARIABLE\")", "#f");
+    assert_eval("(cdr (find (lambda (p) (equal? (car p) \"CARGO_PKG_NAME\")) (get-environment-variables)))", "\"scheme\"");
+    assert_eval("(command-line)", "()");
+    assert_eval("(jiffies-per-second)", "1000000");
+    assert_eval("(let ((t (current-jiffy))) (and (integer? t) (not (> t (current-jiffy)))))", "#t");
+    assert_eval("(> (current-second) 1500000000.0)", "#t");
+    expect_err("(exit)", EvalErr::Exit(0));
+    expect_err("(exit #f)", EvalErr::Exit(1));
+    expect_err("(exit 3)", EvalErr::Exit(3));
+    expect_err("(exit \"x\")", EvalErr::TypeMismatch("integer".to_string(), "\"x\"".to_string()));
+    expect_err("(get-environment-variable 1)", EvalErr::TypeMismatch("string".to_string(), "1".to_string()));
+
+    let interpreter = Interpreter::builder().command_line(vec!["prog".to_string(), "x".to_string()]).build().unwrap();
+    assert_eq!(interpreter.eval_str("(command-line)").unwrap().to_string(), "(\"prog\" \"x\")");
+
+    let mut repl = Repl::new(interpreter);
+    assert_eq!(repl.feed("1 (exit #f) 2"), vec!["1"]);
+    assert_eq!(repl.exit_code(), Some(1));
+}
+
+#[test]
+fn interruption() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let worker = std::thread::spawn(move || {
+        let scope = Rc::new(Scope::from_global());
+        eval_expr("(define x 42) (define (loop) (loop))", &scope).unwrap();
+        sender.send(scheme::interrupt_handle()).unwrap();
+        let err = eval_expr("(loop)", &scope).unwrap_err().to_string();
+        let x = eval_expr("x", &scope).unwrap().to_string();
+        (err, x)
+    });
+    receiver.recv().unwrap().interrupt();
+    let (err, x) = worker.join().unwrap();
+    assert_eq!(err, "Evaluation interrupted");
+    assert_eq!(x, "42");
+
+    let first = Interpreter::new().unwrap();
+    let second = Interpreter::new().unwrap();
+    fn interrupt(_: List) -> Result<Rc<Object>, EvalErr> {
+        scheme::interrupt_handle().interrupt();
+        Ok(Rc::new(Object::Nil))
+    }
+    first.eval_str("(define (loop) (loop))").unwrap();
+    first.set_global("interrupt", Function::from_pointer(interrupt));
+    assert_eq!(first.eval_str("(begin (interrupt) (loop))").unwrap_err().to_string(), "Evaluation interrupted");
+
+    // an interrupt while nothing is running doesn't cancel the next evaluation
+    first.interrupt_handle().interrupt();
+    assert!(second.eval_str("(+ 1 2)").is_ok());
+    assert!(first.eval_str("(+ 1 2)").is_ok());
 }